@@ -0,0 +1,89 @@
+// HTTP admin API next to a running matching service:
+//   cargo run --example admin_api --features admin
+// Prints each request and the JSON it gets back, including an order refused during a halt.
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use orderbook_rs::*;
+
+const SYMBOL: &str = "DEMO";
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, json) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    println!("{} {} {}\n  {} {}", method, path, body, head.lines().next().unwrap_or_default(), json);
+    Ok(())
+}
+
+fn new_order(client_order_id: &str, side: Side, price: f32, quantity: Quantity) -> NewOrder {
+    NewOrder {
+        client_order_id: client_order_id.to_string(),
+        symbol: SYMBOL.to_string(),
+        side,
+        order_type: OrderType::GoodTillCancel,
+        price: OrderedFloat(price),
+        quantity,
+        all_or_none: false,
+        account_id: Some(1),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let (handle, requests) = EngineHandle::channel();
+    let matching = thread::spawn(move || {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine.add_instrument("OTHER", OrderBook::new());
+        // a fresh journal each run, the service would otherwise pick up the last run's orders
+        let journal_path = std::env::temp_dir().join("admin_api.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine = JournaledEngine::open(&journal_path, engine).expect("open the journal");
+        MatchingService::new(engine, requests).run().get_engine().get_book(SYMBOL).map_or(0, |book| book.size())
+    });
+
+    // an order entry session standing in for a gateway
+    let session_id = handle.new_session_id();
+    let (sink, updates) = mpsc::channel();
+    handle.send(EngineRequest::Connect { session_id, sink: Box::new(move |update| sink.send(update).is_ok()) });
+    let enter = |order: NewOrder| -> io::Result<OrderUpdate> {
+        handle.send(EngineRequest::NewOrder { session_id, order });
+        updates.recv_timeout(TIMEOUT).map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no order update"))
+    };
+    enter(new_order("1", Side::Sell, 101.0, 10))?;
+    enter(new_order("2", Side::Buy, 99.0, 5))?;
+    enter(new_order("3", Side::Buy, 98.0, 5))?;
+    enter(new_order("4", Side::Buy, 101.0, 4))?;
+    while updates.recv_timeout(Duration::from_millis(100)).is_ok() {}
+
+    let server = AdminServer::bind("127.0.0.1:0", handle.clone())?;
+    let address = server.get_local_address();
+    request(address, "GET", "/instruments", "")?;
+    request(address, "GET", "/instruments/DEMO/depth", "")?;
+    request(address, "GET", "/instruments/DEMO/trades", "")?;
+    request(address, "GET", "/orders/2", "")?;
+
+    request(address, "POST", "/instruments/DEMO/halt", "")?;
+    if let OrderUpdate::Rejected { reason, .. } = enter(new_order("5", Side::Buy, 100.0, 1))? {
+        println!("order during the halt: {}", reason);
+    }
+    request(address, "POST", "/instruments/DEMO/resume", "")?;
+
+    request(address, "POST", "/instruments/DEMO/mass-cancel", r#"{"side":"Buy"}"#)?;
+    while let Ok(update) = updates.recv_timeout(Duration::from_millis(100)) {
+        if let OrderUpdate::Cancelled { status, .. } = update {
+            println!("cancelled by ops: {}", status.client_order_id);
+        }
+    }
+    request(address, "GET", "/instruments/DEMO/depth", "")?;
+    request(address, "GET", "/instruments/NOPE/depth", "")?;
+
+    handle.send(EngineRequest::Shutdown);
+    println!("orders left in the book: {}", matching.join().unwrap());
+    Ok(())
+}
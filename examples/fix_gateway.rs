@@ -0,0 +1,89 @@
+// FIX 4.4 acceptor on localhost with two initiators trading against it:
+//   cargo run --example fix_gateway
+// Prints every message the initiators receive, including a resend after a sequence gap.
+use std::io;
+use std::thread;
+use std::time::Duration;
+use orderbook_rs::fix::{msg_types, tags};
+use orderbook_rs::*;
+
+const SYMBOL: &str = "DEMO";
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn show(name: &str, message: &FixMessage) {
+    let fields: Vec<String> = message
+        .get_fields()
+        .iter()
+        .filter(|(tag, _)| !matches!(*tag, tags::SENDER_COMP_ID | tags::TARGET_COMP_ID | tags::SENDING_TIME))
+        .map(|(tag, value)| format!("{}={}", tag, value))
+        .collect();
+    println!("{:<6} <- {}", name, fields.join("|"));
+}
+
+// Shows everything that arrives until `msg_type` does.
+fn expect(initiator: &mut FixInitiator, name: &str, msg_type: &str) -> io::Result<FixMessage> {
+    loop {
+        match initiator.receive(TIMEOUT)? {
+            Some(message) => {
+                show(name, &message);
+                if message.get_msg_type() == msg_type {
+                    return Ok(message);
+                }
+            }
+            None => return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no {} for {}", msg_type, name))),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    // the engine isn't Send, it's built on the matching thread
+    let (handle, requests) = EngineHandle::channel();
+    let matching = thread::spawn(move || {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        // a fresh journal each run, the service would otherwise pick up the last run's orders
+        let journal_path = std::env::temp_dir().join("fix_gateway.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine = JournaledEngine::open(&journal_path, engine).expect("open the journal");
+        let engine = MatchingService::new(engine, requests).run();
+        engine.get_engine().get_book(SYMBOL).map_or(0, |book| book.size())
+    });
+
+    let acceptor = FixAcceptor::bind("127.0.0.1:0", "EXCHANGE", handle.clone())?;
+    let mut alice = FixInitiator::connect(acceptor.get_local_address(), "ALICE", "EXCHANGE", 30)?;
+    let mut bob = FixInitiator::connect(acceptor.get_local_address(), "BOB", "EXCHANGE", 30)?;
+
+    alice.send(FixMessage::new_order_single("A1", SYMBOL, Side::Sell, 10, Some(OrderedFloat(101.0))))?;
+    expect(&mut alice, "alice", msg_types::EXECUTION_REPORT)?;
+    alice.send(FixMessage::order_cancel_replace_request("A2", "A1", SYMBOL, Side::Sell, 8, OrderedFloat(100.0)))?;
+    expect(&mut alice, "alice", msg_types::EXECUTION_REPORT)?;
+
+    // bob's buy trades 5 against alice's replaced order
+    bob.send(FixMessage::new_order_single("B1", SYMBOL, Side::Buy, 5, Some(OrderedFloat(100.0))))?;
+    expect(&mut bob, "bob", msg_types::EXECUTION_REPORT)?;
+    expect(&mut bob, "bob", msg_types::EXECUTION_REPORT)?;
+    expect(&mut alice, "alice", msg_types::EXECUTION_REPORT)?;
+
+    // cancelling a filled order is rejected, the rest of alice's order is cancelled
+    bob.send(FixMessage::order_cancel_request("B2", "B1", SYMBOL, Side::Buy))?;
+    expect(&mut bob, "bob", msg_types::ORDER_CANCEL_REJECT)?;
+    alice.send(FixMessage::order_cancel_request("A3", "A2", SYMBOL, Side::Sell))?;
+    expect(&mut alice, "alice", msg_types::EXECUTION_REPORT)?;
+
+    // bob skips a sequence number, the acceptor asks for a resend and
+    // processes the order once the resent messages fill the gap
+    bob.set_next_outgoing(5);
+    bob.send(FixMessage::new_order_single("B3", SYMBOL, Side::Buy, 1, Some(OrderedFloat(99.0))))?;
+    expect(&mut bob, "bob", msg_types::RESEND_REQUEST)?;
+    expect(&mut bob, "bob", msg_types::EXECUTION_REPORT)?;
+
+    bob.send(FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, "PING"))?;
+    expect(&mut bob, "bob", msg_types::HEARTBEAT)?;
+
+    alice.logout()?;
+    bob.logout()?;
+    handle.send(EngineRequest::Shutdown);
+    // bob's resting order went with bob's session
+    println!("orders left in the book: {}", matching.join().unwrap());
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// ITCH-style market data on loopback:
+//   cargo run --example market_data
+// Orders entered over the binary order entry protocol are published to a file and to
+// a multicast group, a receiver rebuilds the book from each and prints the depth.
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::Duration;
+use orderbook_rs::*;
+
+const SYMBOL: &str = "DEMO";
+const FEED_PATH: &str = "market_data.feed";
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn print_depth(source: &str, depth: Option<&OrderbookLevelInfos>) {
+    let Some(depth) = depth else {
+        return println!("{:<9} no book", source);
+    };
+    let levels = |levels: &Vec<LevelInfo>| levels.iter().map(|level| format!("{}@{}", level.quantity, level.price)).collect::<Vec<_>>().join(" ");
+    println!("{:<9} bids [{}] asks [{}]", source, levels(depth.get_bids()), levels(depth.get_asks()));
+}
+
+fn main() -> io::Result<()> {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 0, 1), 31001);
+    let receiver = MulticastReceiver::join(group, Ipv4Addr::LOCALHOST)?;
+
+    let mut market_data = MarketDataPublisher::new().with_sink(FileSink::create(FEED_PATH)?).with_sink(MulticastSink::new(group, Ipv4Addr::LOCALHOST)?);
+    market_data.system_event(SystemEvent::StartOfMessages)?;
+    let (handle, requests) = EngineHandle::channel();
+    let matching = thread::spawn(move || {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        // a fresh journal each run, the service would otherwise pick up the last run's orders
+        let journal_path = std::env::temp_dir().join("market_data.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine = JournaledEngine::open(&journal_path, engine).expect("open the journal");
+        let engine = MatchingService::new(engine, requests).with_market_data(market_data).run();
+        engine.get_engine().get_book(SYMBOL).map(|book| book.get_orderlevelinfos())
+    });
+
+    let server = OuchServer::bind("127.0.0.1:0", handle.clone())?;
+    let mut client = OuchClient::connect(server.get_local_address())?;
+    let orders = [(Side::Sell, 10, 101.0), (Side::Sell, 5, 102.0), (Side::Buy, 7, 99.0), (Side::Buy, 4, 98.0), (Side::Buy, 12, 101.0)];
+    for (token, (side, quantity, price)) in orders.into_iter().enumerate() {
+        client.enter_order(token as OrderToken, SYMBOL, side, quantity, OrderedFloat(price), TimeInForce::Day)?;
+    }
+    client.replace_order(3, 10, 6, OrderedFloat(97.5))?;
+    client.cancel_order(1)?;
+    while client.receive(Duration::from_millis(200))?.is_some() {}
+
+    // the server stays up, the matching thread stops when asked
+    handle.send(EngineRequest::Shutdown);
+    let depth = matching.join().unwrap();
+    print_depth("engine", depth.as_ref());
+
+    let mut multicast_book = FeedBook::new();
+    while let Some(packet) = receiver.receive(TIMEOUT)? {
+        multicast_book.apply_packet(&packet).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        if multicast_book.get_orderlevelinfos(SYMBOL) == depth {
+            break;
+        }
+    }
+    print_depth("multicast", multicast_book.get_orderlevelinfos(SYMBOL).as_ref());
+
+    let mut file_book = FeedBook::new();
+    let messages = file_book.apply_file(FEED_PATH)?;
+    print_depth("file", file_book.get_orderlevelinfos(SYMBOL).as_ref());
+    println!("{} messages in {}", messages, FEED_PATH);
+    std::fs::remove_file(FEED_PATH)
+}
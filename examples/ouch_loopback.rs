@@ -0,0 +1,60 @@
+// Binary order entry over loopback:
+//   cargo run --release --example ouch_loopback
+// Two clients trade, replace and cancel, then one measures enter/cancel round trips.
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use orderbook_rs::*;
+
+const SYMBOL: &str = "DEMO";
+const TIMEOUT: Duration = Duration::from_secs(2);
+const ROUND_TRIPS: u64 = 10_000;
+
+fn expect(client: &mut OuchClient, name: &str) -> io::Result<OuchResponse> {
+    let response = client.receive(TIMEOUT)?.ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, format!("nothing for {}", name)))?;
+    println!("{:<6} <- {:?}", name, response);
+    Ok(response)
+}
+
+fn main() -> io::Result<()> {
+    let (handle, requests) = EngineHandle::channel();
+    thread::spawn(move || {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        // a fresh journal each run, the service would otherwise pick up the last run's orders
+        let journal_path = std::env::temp_dir().join("ouch_loopback.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine = JournaledEngine::open(&journal_path, engine).expect("open the journal");
+        MatchingService::new(engine, requests).run();
+    });
+    let server = OuchServer::bind("127.0.0.1:0", handle.clone())?;
+    let mut alice = OuchClient::connect(server.get_local_address())?;
+    let mut bob = OuchClient::connect(server.get_local_address())?;
+
+    alice.enter_order(1, SYMBOL, Side::Sell, 10, OrderedFloat(101.0), TimeInForce::Day)?;
+    expect(&mut alice, "alice")?;
+    alice.replace_order(1, 2, 8, OrderedFloat(100.0))?;
+    expect(&mut alice, "alice")?;
+
+    // bob's immediate or cancel buy takes 8 and the other 2 are cancelled
+    bob.enter_order(1, SYMBOL, Side::Buy, 10, OrderedFloat(100.0), TimeInForce::ImmediateOrCancel)?;
+    expect(&mut bob, "bob")?;
+    expect(&mut bob, "bob")?;
+    expect(&mut bob, "bob")?;
+    expect(&mut alice, "alice")?;
+    bob.cancel_order(1)?;
+    expect(&mut bob, "bob")?;
+
+    let start = Instant::now();
+    for token in 100..100 + ROUND_TRIPS {
+        bob.enter_order(token, SYMBOL, Side::Buy, 1, OrderedFloat(90.0), TimeInForce::Day)?;
+        bob.receive(TIMEOUT)?;
+        bob.cancel_order(token)?;
+        bob.receive(TIMEOUT)?;
+    }
+    println!("{} enter/cancel pairs, {:?} per request round trip", ROUND_TRIPS, start.elapsed() / (2 * ROUND_TRIPS as u32));
+
+    alice.close()?;
+    bob.close()?;
+    Ok(())
+}
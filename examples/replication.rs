@@ -0,0 +1,69 @@
+// Primary/backup replication on localhost, run each side in its own terminal:
+//   cargo run --example replication -- primary 127.0.0.1:7000 /tmp/primary.journal
+//   cargo run --example replication -- backup 127.0.0.1:7000 /tmp/backup.journal
+// Both print `sequence state_hash` after every command, the lines must match.
+// Kill the primary and the backup is promoted and keeps trading on its own.
+use std::env;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use orderbook_rs::*;
+
+const SYMBOL: &str = "DEMO";
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.add_instrument(SYMBOL, OrderBook::new());
+    engine
+}
+
+// Deterministic stream of crossing orders, `order_id` keeps going after a promotion.
+fn next_order(order_id: OrderId) -> Order {
+    let side = if order_id.is_multiple_of(2) { Side::Buy } else { Side::Sell };
+    let price = 100.0 + (order_id % 5) as f32 - 2.0;
+    Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), 1 + (order_id % 7) as Quantity)
+}
+
+fn trade_forever(mut engine: JournaledEngine) -> io::Result<()> {
+    let mut order_id = engine.get_journal().get_next_sequence();
+    loop {
+        let trades = engine.add_order(SYMBOL, next_order(order_id))?.unwrap_or_default();
+        println!("{} {:016x} ({} trades)", engine.get_journal().get_next_sequence() - 1, engine.get_state_hash(), trades.len());
+        order_id += 1;
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn run_primary(address: &str, path: &str) -> io::Result<()> {
+    let mut engine = JournaledEngine::open(path, new_engine())?;
+    let replication = ReplicationPrimary::bind(address, path)?;
+    println!("primary on {}", replication.get_local_address());
+    engine.set_replication(replication);
+    trade_forever(engine)
+}
+
+fn run_backup(address: &str, path: &str) -> io::Result<()> {
+    let mut backup = ReplicationBackup::connect(address, path, new_engine())?;
+    println!("backup following {}", address);
+    loop {
+        match backup.poll_timeout(Duration::from_secs(3)) {
+            Ok(0) => {}
+            Ok(_) => println!("{} {:016x}", backup.get_next_sequence() - 1, backup.get_state_hash()),
+            Err(_) => break,
+        }
+    }
+    println!("primary lost, promoting at sequence {}", backup.get_next_sequence());
+    trade_forever(backup.promote())
+}
+
+fn main() -> io::Result<()> {
+    let arguments: Vec<String> = env::args().collect();
+    match arguments.iter().map(String::as_str).collect::<Vec<_>>()[1..] {
+        ["primary", address, path] => run_primary(address, path),
+        ["backup", address, path] => run_backup(address, path),
+        _ => {
+            eprintln!("usage: replication (primary|backup) <address> <journal path>");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// WebSocket market data and order entry on localhost:
+//   cargo run --example websocket --features websocket
+// A viewer subscribes to depth and trades while two authenticated traders enter orders.
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use orderbook_rs::*;
+use tungstenite::{Message, WebSocket};
+
+const SYMBOL: &str = "DEMO";
+
+fn connect(server: &WebSocketServer) -> WebSocket<TcpStream> {
+    let stream = TcpStream::connect(server.get_local_address()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let (socket, _) = tungstenite::client(format!("ws://{}", server.get_local_address()), stream).unwrap();
+    socket
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: &ClientMessage) {
+    socket.send(Message::text(serde_json::to_string(message).unwrap())).unwrap();
+}
+
+// Prints what arrives until nothing comes for a read timeout.
+fn show(name: &str, socket: &mut WebSocket<TcpStream>) {
+    while let Ok(message) = socket.read() {
+        if let Message::Text(text) = message {
+            println!("{:<7} <- {}", name, text.as_str());
+        }
+    }
+}
+
+fn new_order(client_order_id: &str, side: Side, quantity: Quantity, price: f32) -> ClientMessage {
+    ClientMessage::NewOrder {
+        client_order_id: client_order_id.to_string(),
+        symbol: SYMBOL.to_string(),
+        side,
+        order_type: OrderType::GoodTillCancel,
+        price: Some(price),
+        quantity,
+        all_or_none: false,
+    }
+}
+
+fn main() -> io::Result<()> {
+    let (sink, packets) = ChannelSink::channel();
+    let (handle, requests) = EngineHandle::channel();
+    thread::spawn(move || {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        // a fresh journal each run, the service would otherwise pick up the last run's orders
+        let journal_path = std::env::temp_dir().join("websocket.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine = JournaledEngine::open(&journal_path, engine).expect("open the journal");
+        MatchingService::new(engine, requests).with_market_data(MarketDataPublisher::new().with_sink(sink)).run();
+    });
+    let credentials = BTreeMap::from([("alice-token".to_string(), 1), ("bob-token".to_string(), 2)]);
+    let server = WebSocketServer::bind("127.0.0.1:0", handle, packets, credentials)?;
+
+    let mut viewer = connect(&server);
+    for channel in [Channel::L1, Channel::L2, Channel::Trades] {
+        send(&mut viewer, &ClientMessage::Subscribe { symbol: SYMBOL.to_string(), channel });
+    }
+    show("viewer", &mut viewer);
+
+    let mut alice = connect(&server);
+    send(&mut alice, &new_order("a0", Side::Sell, 1, 100.0));
+    send(&mut alice, &ClientMessage::Auth { token: "alice-token".to_string() });
+    send(&mut alice, &new_order("a1", Side::Sell, 10, 101.0));
+    send(&mut alice, &new_order("a2", Side::Sell, 5, 102.0));
+    show("alice", &mut alice);
+    show("viewer", &mut viewer);
+
+    let mut bob = connect(&server);
+    send(&mut bob, &ClientMessage::Auth { token: "bob-token".to_string() });
+    send(&mut bob, &new_order("b1", Side::Buy, 12, 102.0));
+    show("bob", &mut bob);
+    show("alice", &mut alice);
+    show("viewer", &mut viewer);
+
+    // alice's orders go when alice disconnects
+    alice.close(None).unwrap();
+    show("viewer", &mut viewer);
+    Ok(())
+}
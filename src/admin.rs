@@ -0,0 +1,218 @@
+use super::*;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// how long a connection may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a request may wait for the matching thread, one it doesn't get to in time
+// is answered 503 and dropped, so a 503 means the request wasn't carried out.
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_HEADER_LINES: usize = 100;
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+// ----------------------------
+// AdminServer: HTTP/JSON API for operations, one request per connection.
+//   GET  /instruments                          symbols with halt state, resting orders and last trade price
+//   GET  /instruments/{symbol}/depth           displayed levels, bids and asks
+//   GET  /instruments/{symbol}/trades          latest trades, oldest first
+//   GET  /orders/{order_id}                    a resting order and its symbol
+//   POST /instruments/{symbol}/halt            refuse new orders and replaces, cancels still work
+//   POST /instruments/{symbol}/resume
+//   POST /instruments/{symbol}/mass-cancel     body is a `MassCancelFilter`, e.g. {"side":"Buy"}, no body cancels everything
+// Errors come as {"error": "..."} with a 4xx, 500 (the journal failed) or 503 status.
+// Every request is an `AdminRequest` to the matching service, connections are served on threads
+// of their own and never hold up matching. There's no authentication, bind it to an internal address.
+// ----------------------------
+pub struct AdminServer {
+    local_address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+impl AdminServer {
+    pub fn bind(address: impl ToSocketAddrs, handle: EngineHandle) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept_shutdown = shutdown.clone();
+        thread::spawn(move || {
+            while !accept_shutdown.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let handle = handle.clone();
+                        thread::spawn(move || {
+                            let _ = Self::serve(stream, &handle);
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Self { local_address, shutdown })
+    }
+
+    pub fn get_local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    fn serve(mut stream: TcpStream, handle: &EngineHandle) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let (status, body) = match HttpRequest::read_from(&mut BufReader::new(&stream)) {
+            Ok(request) => route(&request, handle),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => (400, error_body(&error.to_string())),
+            Err(error) => return Err(error),
+        };
+        write_response(&mut stream, status, &body)
+    }
+}
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+impl HttpRequest {
+    fn read_from(reader: &mut impl BufRead) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid("malformed request line"));
+        };
+        let (method, path) = (method.to_string(), target.split('?').next().unwrap_or_default().to_string());
+
+        let mut content_length = 0;
+        for _ in 0..MAX_HEADER_LINES {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                if content_length > MAX_BODY_LENGTH {
+                    return Err(invalid("body too large"));
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body)?;
+                return Ok(Self { method, path, body });
+            }
+            if let Some((name, value)) = header.split_once(':')
+                && name.trim().eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().map_err(|_| invalid("malformed content length"))?;
+            }
+        }
+        Err(invalid("too many headers"))
+    }
+}
+
+fn route(request: &HttpRequest, handle: &EngineHandle) -> (u16, Value) {
+    let segments: Vec<String> = request.path.split('/').filter(|segment| !segment.is_empty()).map(percent_decode).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let admin_request = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["instruments"]) => AdminRequest::ListInstruments,
+        ("GET", ["instruments", symbol, "depth"]) => AdminRequest::Depth { symbol: symbol.to_string() },
+        ("GET", ["instruments", symbol, "trades"]) => AdminRequest::RecentTrades { symbol: symbol.to_string() },
+        ("GET", ["orders", order_id]) => match order_id.parse() {
+            Ok(order_id) => AdminRequest::Order { order_id },
+            Err(_) => return (400, error_body("malformed order id")),
+        },
+        ("POST", ["instruments", symbol, "halt"]) => AdminRequest::Halt { symbol: symbol.to_string() },
+        ("POST", ["instruments", symbol, "resume"]) => AdminRequest::Resume { symbol: symbol.to_string() },
+        ("POST", ["instruments", symbol, "mass-cancel"]) => {
+            let filter = if request.body.iter().all(u8::is_ascii_whitespace) {
+                MassCancelFilter::new()
+            } else {
+                match serde_json::from_slice(&request.body) {
+                    Ok(filter) => filter,
+                    Err(error) => return (400, error_body(&format!("malformed filter: {}", error))),
+                }
+            };
+            AdminRequest::MassCancel { symbol: symbol.to_string(), filter }
+        }
+        (_, ["instruments"] | ["instruments", _, "depth" | "trades" | "halt" | "resume" | "mass-cancel"] | ["orders", _]) => {
+            return (405, error_body("method not allowed"));
+        }
+        _ => return (404, error_body("not found")),
+    };
+
+    let symbol = match &admin_request {
+        AdminRequest::Halt { symbol } | AdminRequest::Resume { symbol } | AdminRequest::MassCancel { symbol, .. } => symbol.clone(),
+        _ => Symbol::new(),
+    };
+    match handle.admin(admin_request, ENGINE_TIMEOUT) {
+        Some(AdminResponse::Instruments(instruments)) => (200, json!(instruments)),
+        Some(AdminResponse::Depth(depth)) => (200, json!(depth)),
+        Some(AdminResponse::Order { symbol, order }) => (200, json!({ "symbol": symbol, "order": order })),
+        Some(AdminResponse::Trades(trades)) => (200, json!(trades)),
+        Some(AdminResponse::Halted(halted)) => (200, json!({ "symbol": symbol, "halted": halted })),
+        Some(AdminResponse::MassCancelled(order_ids)) => (200, json!({ "symbol": symbol, "cancelled": order_ids })),
+        Some(AdminResponse::UnknownSymbol) => (404, error_body("unknown symbol")),
+        Some(AdminResponse::UnknownOrder) => (404, error_body("unknown order")),
+        Some(AdminResponse::JournalUnavailable) => (500, error_body("journal unavailable")),
+        None => (503, error_body("matching engine unavailable")),
+    }
+}
+
+fn error_body(message: &str) -> Value {
+    json!({ "error": message })
+}
+
+// %XX escapes in a path segment, a malformed escape is kept as it is.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| bytes.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: &Value) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "Service Unavailable",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
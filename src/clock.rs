@@ -0,0 +1,44 @@
+use super::Timestamp;
+use crate::helperfns::get_timestamp;
+use std::cell::Cell;
+
+// ----------------------------
+// Clock is where books and engines read the time from,
+// inject a ManualClock to make timestamps (and so the whole state) reproducible.
+// ----------------------------
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+// Wall clock, nanoseconds since unix epoch.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        get_timestamp()
+    }
+}
+
+// Only moves when told to, replay sets it to the time each command was journaled at.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<Timestamp>,
+}
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self { now: Cell::new(now) }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, duration: Timestamp) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.get()
+    }
+}
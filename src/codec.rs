@@ -0,0 +1,390 @@
+use super::{FeeModel, FeeRate, FeeSchedule, MassCancelFilter, Order, OrderType, Peg, PegType, Price, Side, Trail, Trigger};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,      // input ended in the middle of a field.
+    InvalidTag(u8),     // unknown enum discriminant.
+    InvalidUtf8,        // string field isn't utf-8.
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+            DecodeError::InvalidUtf8 => write!(f, "invalid utf-8 string"),
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
+
+// ----------------------------
+// Encoder / Decoder: compact little endian binary encoding shared by
+// everything we write to disk or to the wire.
+// strings are a u32 length followed by utf-8 bytes,
+// options are a 0/1 byte followed by the value when present.
+// ----------------------------
+#[derive(Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+impl Encoder {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8);
+    }
+    pub fn put_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_i64(&mut self, value: i64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_f32(&mut self, value: f32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_f64(&mut self, value: f64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn put_bytes(&mut self, value: &[u8]) {
+        self.buffer.extend_from_slice(value);
+    }
+    pub fn put_str(&mut self, value: &str) {
+        self.put_u32(value.len() as u32);
+        self.put_bytes(value.as_bytes());
+    }
+    pub fn put_option<T>(&mut self, value: Option<T>, put: impl FnOnce(&mut Self, T)) {
+        self.put_bool(value.is_some());
+        if let Some(value) = value {
+            put(self, value);
+        }
+    }
+
+    pub fn put_side(&mut self, side: Side) {
+        self.put_u8(match side {
+            Side::Buy => 0,
+            Side::Sell => 1,
+        });
+    }
+
+    pub fn put_order_type(&mut self, order_type: OrderType) {
+        self.put_u8(match order_type {
+            OrderType::GoodTillCancel => 0,
+            OrderType::FillAndKill => 1,
+            OrderType::Market => 2,
+            OrderType::MarketToLimit => 3,
+        });
+    }
+
+    pub fn put_price(&mut self, price: Price) {
+        self.put_f32(price.0);
+    }
+
+    pub fn put_peg(&mut self, peg: Peg) {
+        self.put_u8(match peg.get_peg_type() {
+            PegType::Primary => 0,
+            PegType::Market => 1,
+            PegType::Midpoint => 2,
+        });
+        self.put_price(peg.get_offset());
+    }
+
+    pub fn put_trigger(&mut self, trigger: Trigger) {
+        match trigger {
+            Trigger::Stop(price) => {
+                self.put_u8(0);
+                self.put_price(price);
+            }
+            Trigger::TrailingStop(Trail::Amount(amount)) => {
+                self.put_u8(1);
+                self.put_price(amount);
+            }
+            Trigger::TrailingStop(Trail::Percent(percent)) => {
+                self.put_u8(2);
+                self.put_f32(percent);
+            }
+        }
+    }
+
+    pub fn put_fee_rate(&mut self, rate: FeeRate) {
+        match rate {
+            FeeRate::BasisPoints(bps) => {
+                self.put_u8(0);
+                self.put_f64(bps);
+            }
+            FeeRate::PerUnit(rate) => {
+                self.put_u8(1);
+                self.put_f64(rate);
+            }
+        }
+    }
+
+    pub fn put_fee_schedule(&mut self, schedule: FeeSchedule) {
+        self.put_fee_rate(schedule.get_maker());
+        self.put_fee_rate(schedule.get_taker());
+    }
+
+    pub fn put_fee_model(&mut self, fee_model: &FeeModel) {
+        self.put_fee_schedule(fee_model.get_default());
+        let tiers = fee_model.get_tiers();
+        self.put_u32(tiers.len() as u32);
+        for (account_id, schedule) in tiers {
+            self.put_u64(account_id);
+            self.put_fee_schedule(schedule);
+        }
+    }
+
+    pub fn put_mass_cancel_filter(&mut self, filter: &MassCancelFilter) {
+        self.put_option(filter.get_account_id(), Self::put_u64);
+        self.put_option(filter.get_side(), Self::put_side);
+        self.put_option(filter.get_order_type(), Self::put_order_type);
+        self.put_option(filter.get_price_range(), |encoder, (min_price, max_price)| {
+            encoder.put_price(min_price);
+            encoder.put_price(max_price);
+        });
+        self.put_option(filter.get_session_id(), Self::put_u64);
+        self.put_option(filter.get_persistent(), Self::put_bool);
+    }
+
+    // Every attribute of the order, remaining quantity and group id included.
+    pub fn put_order(&mut self, order: &Order) {
+        self.put_u64(order.get_order_id());
+        self.put_order_type(order.get_order_type());
+        self.put_side(order.get_side());
+        self.put_price(order.get_price());
+        self.put_u32(order.get_initial_quantity());
+        self.put_u32(order.get_remaining_quantity());
+        self.put_option(order.get_peg(), Self::put_peg);
+        self.put_option(order.get_group_id(), Self::put_u64);
+        self.put_option(order.get_min_quantity(), Self::put_u32);
+        self.put_bool(order.is_all_or_none());
+        self.put_bool(order.is_hidden());
+        self.put_option(order.get_account_id(), Self::put_u64);
+        self.put_option(order.get_quote_budget(), |encoder, budget| encoder.put_f64(budget.0));
+        self.put_option(order.get_session_id(), Self::put_u64);
+        self.put_bool(order.is_persistent());
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn get_bytes(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.position.checked_add(length).ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = self.bytes.get(self.position..end).ok_or(DecodeError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+    fn get_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.get_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.get_array::<1>()?[0])
+    }
+    pub fn get_bool(&mut self) -> Result<bool, DecodeError> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+    pub fn get_u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_i64(&mut self) -> Result<i64, DecodeError> {
+        Ok(i64::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_le_bytes(self.get_array()?))
+    }
+    pub fn get_string(&mut self) -> Result<String, DecodeError> {
+        let length = self.get_u32()? as usize;
+        let bytes = self.get_bytes(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+    pub fn get_option<T>(&mut self, get: impl FnOnce(&mut Self) -> Result<T, DecodeError>) -> Result<Option<T>, DecodeError> {
+        if self.get_bool()? {
+            Ok(Some(get(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_side(&mut self) -> Result<Side, DecodeError> {
+        match self.get_u8()? {
+            0 => Ok(Side::Buy),
+            1 => Ok(Side::Sell),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn get_order_type(&mut self) -> Result<OrderType, DecodeError> {
+        match self.get_u8()? {
+            0 => Ok(OrderType::GoodTillCancel),
+            1 => Ok(OrderType::FillAndKill),
+            2 => Ok(OrderType::Market),
+            3 => Ok(OrderType::MarketToLimit),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn get_price(&mut self) -> Result<Price, DecodeError> {
+        Ok(Price::from(self.get_f32()?))
+    }
+
+    pub fn get_peg(&mut self) -> Result<Peg, DecodeError> {
+        let peg_type = match self.get_u8()? {
+            0 => PegType::Primary,
+            1 => PegType::Market,
+            2 => PegType::Midpoint,
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(Peg::new(peg_type, self.get_price()?))
+    }
+
+    pub fn get_trigger(&mut self) -> Result<Trigger, DecodeError> {
+        match self.get_u8()? {
+            0 => Ok(Trigger::Stop(self.get_price()?)),
+            1 => Ok(Trigger::TrailingStop(Trail::Amount(self.get_price()?))),
+            2 => Ok(Trigger::TrailingStop(Trail::Percent(self.get_f32()?))),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn get_fee_rate(&mut self) -> Result<FeeRate, DecodeError> {
+        match self.get_u8()? {
+            0 => Ok(FeeRate::BasisPoints(self.get_f64()?)),
+            1 => Ok(FeeRate::PerUnit(self.get_f64()?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn get_fee_schedule(&mut self) -> Result<FeeSchedule, DecodeError> {
+        Ok(FeeSchedule::new(self.get_fee_rate()?, self.get_fee_rate()?))
+    }
+
+    pub fn get_fee_model(&mut self) -> Result<FeeModel, DecodeError> {
+        let mut fee_model = FeeModel::new(self.get_fee_schedule()?);
+        for _ in 0..self.get_u32()? {
+            let account_id = self.get_u64()?;
+            fee_model.set_tier(account_id, self.get_fee_schedule()?);
+        }
+        Ok(fee_model)
+    }
+
+    pub fn get_mass_cancel_filter(&mut self) -> Result<MassCancelFilter, DecodeError> {
+        let mut filter = MassCancelFilter::new();
+        if let Some(account_id) = self.get_option(Self::get_u64)? {
+            filter = filter.with_account(account_id);
+        }
+        if let Some(side) = self.get_option(Self::get_side)? {
+            filter = filter.with_side(side);
+        }
+        if let Some(order_type) = self.get_option(Self::get_order_type)? {
+            filter = filter.with_order_type(order_type);
+        }
+        if let Some((min_price, max_price)) = self.get_option(|decoder| Ok((decoder.get_price()?, decoder.get_price()?)))? {
+            filter = filter.with_price_range(min_price, max_price);
+        }
+        if let Some(session_id) = self.get_option(Self::get_u64)? {
+            filter = filter.with_session(session_id);
+        }
+        if let Some(persistent) = self.get_option(Self::get_bool)? {
+            filter = filter.with_persistent(persistent);
+        }
+        Ok(filter)
+    }
+
+    pub fn get_order(&mut self) -> Result<Order, DecodeError> {
+        let order_id = self.get_u64()?;
+        let order_type = self.get_order_type()?;
+        let side = self.get_side()?;
+        let price = self.get_price()?;
+        let initial_quantity = self.get_u32()?;
+        let remaining_quantity = self.get_u32()?;
+        let mut order = Order::new(order_id, order_type, side, price, initial_quantity);
+        if remaining_quantity < initial_quantity {
+            order.fill(initial_quantity - remaining_quantity);
+        }
+        if let Some(peg) = self.get_option(Self::get_peg)? {
+            order = order.with_peg(peg);
+        }
+        if let Some(group_id) = self.get_option(Self::get_u64)? {
+            order.set_group_id(group_id);
+        }
+        if let Some(min_quantity) = self.get_option(Self::get_u32)? {
+            order = order.with_min_quantity(min_quantity);
+        }
+        if self.get_bool()? {
+            order = order.with_all_or_none();
+        }
+        if self.get_bool()? {
+            order = order.with_hidden();
+        }
+        if let Some(account_id) = self.get_option(Self::get_u64)? {
+            order = order.with_account(account_id);
+        }
+        if let Some(budget) = self.get_option(Self::get_f64)? {
+            order = order.with_quote_budget(budget.into());
+        }
+        if let Some(session_id) = self.get_option(Self::get_u64)? {
+            order = order.with_session(session_id);
+        }
+        if self.get_bool()? {
+            order = order.with_persistent();
+        }
+        Ok(order)
+    }
+
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+    pub fn get_remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+    pub fn is_finished(&self) -> bool {
+        self.get_remaining() == 0
+    }
+}
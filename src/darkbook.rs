@@ -0,0 +1,262 @@
+use std::cmp::min;
+use std::rc::Rc;
+use super::*;
+
+struct DarkOrderEntry {
+    order: OrderPointer,
+    sequence: u64, // arrival order, the later of two crossing orders is the aggressor
+}
+
+// ----------------------------
+// DarkBook is a midpoint dark pool: orders never show up in any depth,
+// buy and sell interest only cross at the midpoint of a reference BBO
+// supplied from a lit book (e.g. the first levels of `OrderBook::get_orderlevelinfos`).
+// Order price is a limit on the midpoint (NaN means no limit),
+// minimum quantity and all or none restrict every execution like in `OrderBook`.
+// The later of the two orders pays the taker fee, trades are timestamped with the book's clock.
+// ----------------------------
+pub struct DarkBook {
+    orders: HashMap<OrderId, DarkOrderEntry>,
+    buys: OrderPointers, // time priority
+    sells: OrderPointers, // time priority
+    best_bid: Option<Price>,
+    best_ask: Option<Price>,
+    next_sequence: u64,
+    next_trade_id: TradeId,
+    fee_model: FeeModel,
+    fee_accumulator: FeeAccumulator, // fees charged per account since the last reset
+    clock: Rc<dyn Clock>,
+}
+impl Default for DarkBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl DarkBook {
+    pub fn new() -> Self {
+        Self {
+            orders: HashMap::new(),
+            buys: OrderPointers::new(),
+            sells: OrderPointers::new(),
+            best_bid: None,
+            best_ask: None,
+            next_sequence: 0,
+            next_trade_id: 1,
+            fee_model: FeeModel::default(),
+            fee_accumulator: FeeAccumulator::new(),
+            clock: Rc::new(SystemClock),
+        }
+    }
+
+    // Share the lit book's clock (`OrderBook::get_clock`) to timestamp both books alike.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn get_clock(&self) -> Rc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    // Fees of the trades from now on, nothing is charged by default.
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    pub fn get_fee_model(&self) -> &FeeModel {
+        &self.fee_model
+    }
+
+    pub fn get_accumulated_fees(&self, account_id: AccountId) -> Amount {
+        self.fee_accumulator.get_fees(account_id)
+    }
+
+    pub fn reset_accumulated_fees(&mut self, account_id: AccountId) -> Amount {
+        self.fee_accumulator.reset(account_id)
+    }
+
+    pub fn reset_all_accumulated_fees(&mut self) {
+        self.fee_accumulator.reset_all();
+    }
+
+    // No midpoint (so no matching) while either side of the reference is missing, locked or crossed.
+    pub fn get_midpoint(&self) -> Option<Price> {
+        let (best_bid, best_ask) = (self.best_bid?, self.best_ask?);
+        if best_bid >= best_ask {
+            return None;
+        }
+        Some((best_bid + best_ask) / 2.0)
+    }
+
+    fn is_eligible(order: &Order, midpoint: Price) -> bool {
+        let limit = order.get_price();
+        if limit.is_nan() {
+            return true;
+        }
+        match order.get_side() {
+            Side::Buy => limit >= midpoint,
+            Side::Sell => limit <= midpoint,
+        }
+    }
+
+    // Every eligible buy, in time priority, trades against eligible sells in time priority,
+    // a pair is skipped when the execution doesn't satisfy either order's minimum quantity.
+    fn match_orders(&mut self) -> Trades {
+        let mut trades: Trades = Vec::new();
+        let Some(midpoint) = self.get_midpoint() else {
+            return trades;
+        };
+        let timestamp = self.clock.now();
+
+        for buy_rc in self.buys.iter() {
+            if !Self::is_eligible(&buy_rc.borrow(), midpoint) {
+                continue;
+            }
+            for sell_rc in self.sells.iter() {
+                let mut buy = buy_rc.borrow_mut();
+                let mut sell = sell_rc.borrow_mut();
+                if buy.isfilled() {
+                    break;
+                }
+                if sell.isfilled() || !Self::is_eligible(&sell, midpoint) {
+                    continue;
+                }
+
+                let quantity = min(buy.get_remaining_quantity(), sell.get_remaining_quantity());
+                if !buy.can_trade(quantity) || !sell.can_trade(quantity) {
+                    continue;
+                }
+
+                buy.fill(quantity);
+                sell.fill(quantity);
+                let aggressor_side = if self.orders[&buy.get_order_id()].sequence > self.orders[&sell.get_order_id()].sequence {
+                    Side::Buy
+                } else {
+                    Side::Sell
+                };
+                let fee = |order: &Order| {
+                    if order.get_side() == aggressor_side {
+                        self.fee_model.get_taker_fee(order.get_account_id(), midpoint, quantity)
+                    } else {
+                        self.fee_model.get_maker_fee(order.get_account_id(), midpoint, quantity)
+                    }
+                };
+                let trade = Trade::new(
+                    self.next_trade_id,
+                    aggressor_side,
+                    timestamp,
+                    TradeInfo::new(buy.get_order_id(), midpoint, quantity).with_account(buy.get_account_id()).with_fee(fee(&buy)),
+                    TradeInfo::new(sell.get_order_id(), midpoint, quantity).with_account(sell.get_account_id()).with_fee(fee(&sell)),
+                );
+                self.next_trade_id += 1;
+                self.fee_accumulator.add_trade(&trade);
+                trades.push(trade);
+            }
+        }
+
+        // Remove filled orders
+        for orders in [&mut self.buys, &mut self.sells] {
+            orders.retain(|order| {
+                let order = order.borrow();
+                if order.isfilled() {
+                    self.orders.remove(&order.get_order_id());
+                }
+                !order.isfilled()
+            });
+        }
+
+        trades
+    }
+
+    // Updates the reference BBO, a new midpoint can make resting orders cross.
+    pub fn set_reference(&mut self, best_bid: Option<Price>, best_ask: Option<Price>) -> Trades {
+        self.best_bid = best_bid;
+        self.best_ask = best_ask;
+        self.match_orders()
+    }
+
+    // Only GoodTillCancel (rests until crossed) and FillAndKill (crosses now or is cancelled) are supported.
+    pub fn add_order(&mut self, order: OrderPointer) -> Option<Trades> {
+        let (order_id, order_type, side) = {
+            let order = order.borrow();
+            (order.get_order_id(), order.get_order_type(), order.get_side())
+        };
+        if self.orders.contains_key(&order_id) {
+            return None;
+        }
+        if !matches!(order_type, OrderType::GoodTillCancel | OrderType::FillAndKill) {
+            return None;
+        }
+
+        if side == Side::Buy {
+            self.buys.push_back(order.clone());
+        } else {
+            self.sells.push_back(order.clone());
+        }
+        self.orders.insert(order_id, DarkOrderEntry { order: order.clone(), sequence: self.next_sequence });
+        self.next_sequence += 1;
+
+        let trades = self.match_orders();
+
+        if order_type == OrderType::FillAndKill {
+            self.cancel_order(order_id);
+        }
+        Some(trades)
+    }
+
+    pub fn cancel_order(&mut self, order_id: OrderId) {
+        let Some(order_entry) = self.orders.remove(&order_id) else {
+            return;
+        };
+        let orders = if order_entry.order.borrow().get_side() == Side::Buy { &mut self.buys } else { &mut self.sells };
+        if let Some(index) = orders.iter().position(|order| order.borrow().get_order_id() == order_id) {
+            orders.remove(index);
+        }
+    }
+
+    // Modified order keeps its attributes but loses its time priority.
+    pub fn modify_order(&mut self, order: OrderModify) -> Option<Trades> {
+        let modified_order = order.to_order_pointer(&self.orders.get(&order.get_order_id())?.order.borrow());
+
+        self.cancel_order(order.get_order_id());
+
+        self.add_order(modified_order)
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Option<OrderPointer> {
+        self.orders.get(&order_id).map(|order_entry| order_entry.order.clone())
+    }
+
+    pub fn size(&self) -> usize {
+        self.orders.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn order(order_id: OrderId, side: Side, quantity: Quantity, account_id: AccountId) -> OrderPointer {
+        Rc::new(RefCell::new(Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(f32::NAN), quantity).with_account(account_id)))
+    }
+
+    #[test]
+    fn trades_use_the_clock_and_fee_model() {
+        let mut book = DarkBook::new();
+        book.set_clock(Rc::new(ManualClock::new(42)));
+        book.set_fee_model(FeeModel::new(FeeSchedule::new(FeeRate::PerUnit(-0.25), FeeRate::PerUnit(0.5))));
+        book.set_reference(Some(OrderedFloat(99.0)), Some(OrderedFloat(101.0)));
+
+        book.add_order(order(1, Side::Sell, 10, 1)).unwrap();
+        let trades = book.add_order(order(2, Side::Buy, 4, 2)).unwrap();
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!(trade.get_timestamp(), 42);
+        assert_eq!(trade.get_price(), OrderedFloat(100.0));
+        // the buy came later, it takes
+        assert_eq!(trade.get_bid_trade().fee, Amount::from(2.0));
+        assert_eq!(trade.get_ask_trade().fee, Amount::from(-1.0));
+        assert_eq!(book.get_accumulated_fees(2), Amount::from(2.0));
+        assert_eq!(book.get_accumulated_fees(1), Amount::from(-1.0));
+    }
+}
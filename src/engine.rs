@@ -0,0 +1,396 @@
+use super::*;
+use crate::helperfns::fnv1a64;
+use std::cell::Cell;
+use std::rc::Rc;
+use crate::snapshot::{seal, unseal, SnapshotError, SnapshotKind};
+
+// What a single engine command did to one of the books.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookUpdate {
+    pub symbol: Symbol,
+    pub events: Vec<OrderEvent>,
+    pub trades: Trades,
+}
+
+// ----------------------------
+// Engine holds one orderbook per instrument and the gateway sessions,
+// orders entered through a session are only accepted while it's connected
+// and (unless persistent) are cancelled on every book when it disconnects.
+// Every book runs on the engine's clock and numbers its trades from the engine's counter,
+// so trade ids are unique across symbols.
+// ----------------------------
+pub struct Engine {
+    books: BTreeMap<Symbol, OrderBook>,
+    sessions: SessionRegistry,
+    clock: Rc<dyn Clock>,
+    trade_ids: TradeIds,
+}
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Engine {
+    pub fn new() -> Self {
+        Self { books: BTreeMap::new(), sessions: SessionRegistry::new(), clock: Rc::new(SystemClock), trade_ids: Rc::new(Cell::new(1)) }
+    }
+
+    // returns false if the symbol already has a book, the book's clock and trade id counter are replaced
+    // by the engine's (which skips past the ids the book already used).
+    pub fn add_instrument(&mut self, symbol: &str, mut book: OrderBook) -> bool {
+        if self.books.contains_key(symbol) {
+            return false;
+        }
+        book.set_clock(self.clock.clone());
+        self.trade_ids.set(self.trade_ids.get().max(book.get_trade_ids().get()));
+        book.set_trade_ids(self.trade_ids.clone());
+        self.books.insert(symbol.to_string(), book);
+        true
+    }
+
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        for book in self.books.values_mut() {
+            book.set_clock(clock.clone());
+        }
+        self.clock = clock;
+    }
+
+    pub fn get_clock(&self) -> Rc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    pub fn get_book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(symbol)
+    }
+
+    pub fn get_book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(symbol)
+    }
+
+    // Sorted by symbol.
+    pub fn get_symbols(&self) -> Vec<Symbol> {
+        self.books.keys().cloned().collect()
+    }
+
+    pub fn get_sessions(&self) -> &SessionRegistry {
+        &self.sessions
+    }
+
+    fn is_session_allowed(&self, order: &Order) -> bool {
+        order.get_session_id().is_none_or(|session_id| self.sessions.is_connected(session_id))
+    }
+
+    pub fn add_order(&mut self, symbol: &str, order: OrderPointer) -> Option<Trades> {
+        if !self.is_session_allowed(&order.borrow()) {
+            return None;
+        }
+        self.books.get_mut(symbol)?.add_order(order)
+    }
+
+    pub fn add_stop_order(&mut self, symbol: &str, order: OrderPointer, trigger: Trigger) -> Option<Trades> {
+        if !self.is_session_allowed(&order.borrow()) {
+            return None;
+        }
+        self.books.get_mut(symbol)?.add_stop_order(order, trigger)
+    }
+
+    pub fn add_oco_orders(&mut self, symbol: &str, first: OrderLeg, second: OrderLeg) -> Option<(GroupId, Trades)> {
+        if !self.is_session_allowed(&first.get_order().borrow()) || !self.is_session_allowed(&second.get_order().borrow()) {
+            return None;
+        }
+        self.books.get_mut(symbol)?.add_oco_orders(first, second)
+    }
+
+    pub fn add_bracket_order(&mut self, symbol: &str, entry: OrderPointer, take_profit: OrderLeg, stop_loss: OrderLeg) -> Option<(GroupId, Trades)> {
+        let legs = [&entry, take_profit.get_order(), stop_loss.get_order()];
+        if !legs.iter().all(|order| self.is_session_allowed(&order.borrow())) {
+            return None;
+        }
+        self.books.get_mut(symbol)?.add_bracket_order(entry, take_profit, stop_loss)
+    }
+
+    pub fn cancel_order(&mut self, symbol: &str, order_id: OrderId) -> Option<Trades> {
+        Some(self.books.get_mut(symbol)?.cancel_order(order_id))
+    }
+
+    pub fn modify_order(&mut self, symbol: &str, order: OrderModify) -> Option<Trades> {
+        self.books.get_mut(symbol)?.modify_order(order)
+    }
+
+    pub fn mass_cancel(&mut self, symbol: &str, filter: &MassCancelFilter) -> Option<(Vec<OrderId>, Trades)> {
+        Some(self.books.get_mut(symbol)?.mass_cancel(filter))
+    }
+
+    pub fn disable_account(&mut self, symbol: &str, account_id: AccountId) -> Option<(Vec<OrderId>, Trades)> {
+        Some(self.books.get_mut(symbol)?.disable_account(account_id))
+    }
+
+    // The book settings below return false for an unknown symbol.
+    pub fn enable_account(&mut self, symbol: &str, account_id: AccountId) -> bool {
+        self.books.get_mut(symbol).map(|book| book.enable_account(account_id)).is_some()
+    }
+
+    pub fn halt(&mut self, symbol: &str) -> bool {
+        self.books.get_mut(symbol).map(|book| book.halt()).is_some()
+    }
+
+    pub fn resume(&mut self, symbol: &str) -> bool {
+        self.books.get_mut(symbol).map(|book| book.resume()).is_some()
+    }
+
+    pub fn set_fee_model(&mut self, symbol: &str, fee_model: FeeModel) -> bool {
+        self.books.get_mut(symbol).map(|book| book.set_fee_model(fee_model)).is_some()
+    }
+
+    // Every account's accumulated fees when `account_id` is None.
+    pub fn reset_accumulated_fees(&mut self, symbol: &str, account_id: Option<AccountId>) -> bool {
+        self.books
+            .get_mut(symbol)
+            .map(|book| match account_id {
+                Some(account_id) => {
+                    book.reset_accumulated_fees(account_id);
+                }
+                None => book.reset_all_accumulated_fees(),
+            })
+            .is_some()
+    }
+
+    pub fn connect_session(&mut self, session_id: SessionId) -> bool {
+        self.sessions.connect(session_id, self.clock.now())
+    }
+
+    // Cancels every non persistent order of the session on every book,
+    // returns the books that had something cancelled with their cancellation events.
+    pub fn disconnect_session(&mut self, session_id: SessionId) -> Vec<BookUpdate> {
+        if !self.sessions.disconnect(session_id, self.clock.now()) {
+            return Vec::new();
+        }
+
+        let filter = MassCancelFilter::new().with_session(session_id).with_persistent(false);
+        let mut updates = Vec::new();
+        for (symbol, book) in self.books.iter_mut() {
+            let (order_ids, trades) = book.mass_cancel(&filter);
+            if order_ids.is_empty() {
+                continue;
+            }
+            updates.push(BookUpdate { symbol: symbol.clone(), events: book.take_events(), trades });
+        }
+        updates
+    }
+
+    // Snapshot of every book and session, `journal_sequence` is the last journaled
+    // command it includes so replay can pick up right after it.
+    pub fn write_snapshot(&self, journal_sequence: u64) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.put_u64(journal_sequence);
+        self.encode_state(&mut encoder);
+        seal(SnapshotKind::Engine, encoder.get_bytes())
+    }
+
+    // Stable hash of every session and book, compare it after each command
+    // to check two replicas (or a replay and production) are still in step.
+    pub fn state_hash(&self) -> u64 {
+        let mut encoder = Encoder::new();
+        self.encode_state(&mut encoder);
+        fnv1a64(encoder.get_bytes())
+    }
+
+    fn encode_state(&self, encoder: &mut Encoder) {
+        let sessions: Vec<&Session> = self.sessions.iter().collect();
+        encoder.put_u32(sessions.len() as u32);
+        for session in sessions {
+            encoder.put_u64(session.get_session_id());
+            encoder.put_bool(session.get_state() == SessionState::Connected);
+            encoder.put_u64(session.get_changed_at());
+        }
+
+        encoder.put_u32(self.books.len() as u32);
+        for (symbol, book) in &self.books {
+            encoder.put_str(symbol);
+            book.encode_state(encoder);
+        }
+    }
+
+    // Every book of the snapshot must already be added (with its matching policy),
+    // returns the journal sequence the snapshot was taken at.
+    // Nothing changes unless the whole snapshot can be read.
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<u64, SnapshotError> {
+        let mut decoder = Decoder::new(unseal(SnapshotKind::Engine, bytes)?);
+        let journal_sequence = decoder.get_u64()?;
+
+        let mut sessions = SessionRegistry::new();
+        for _ in 0..decoder.get_u32()? {
+            let session_id = decoder.get_u64()?;
+            let state = if decoder.get_bool()? { SessionState::Connected } else { SessionState::Disconnected };
+            sessions.insert(Session::new(session_id, state, decoder.get_u64()?));
+        }
+
+        let mut books = Vec::new();
+        for _ in 0..decoder.get_u32()? {
+            let symbol = decoder.get_string()?;
+            if !self.books.contains_key(&symbol) {
+                return Err(SnapshotError::UnknownSymbol(symbol));
+            }
+            books.push((symbol, OrderBook::decode_state(&mut decoder)?));
+        }
+        if !decoder.is_finished() {
+            return Err(SnapshotError::TrailingBytes(decoder.get_remaining()));
+        }
+
+        self.sessions = sessions;
+        for (symbol, book) in books {
+            self.books.get_mut(&symbol).unwrap().replace_state(book);
+        }
+        Ok(journal_sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const SYMBOL: &str = "DEMO";
+
+    fn order(order_id: OrderId, side: Side, price: f32, quantity: Quantity) -> Order {
+        Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity)
+    }
+
+    fn pointer(order: Order) -> OrderPointer {
+        Rc::new(RefCell::new(order))
+    }
+
+    #[test]
+    fn disconnect_doesnt_activate_bracket_legs() {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine.connect_session(7);
+        engine.add_order(SYMBOL, pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+
+        // the entry fills 4 of 10 and rests, its legs wait for it
+        let entry = pointer(order(2, Side::Buy, 100.0, 10).with_session(7));
+        let take_profit = OrderLeg::Limit(pointer(order(3, Side::Sell, 110.0, 10).with_session(7)));
+        let stop_loss = OrderLeg::Stop(pointer(order(4, Side::Sell, 90.0, 10).with_session(7)), Trigger::Stop(OrderedFloat(95.0)));
+        let (_, trades) = engine.add_bracket_order(SYMBOL, entry, take_profit, stop_loss).unwrap();
+        assert_eq!(trades.len(), 1);
+
+        let updates = engine.disconnect_session(7);
+        assert_eq!(updates.len(), 1);
+        let book = engine.get_book(SYMBOL).unwrap();
+        assert_eq!(book.size(), 0);
+        for order_id in 2..=4 {
+            assert!(book.get_order(order_id).is_none(), "order {} still in the book", order_id);
+            assert!(book.get_group_id(order_id).is_none());
+        }
+    }
+
+    #[test]
+    fn snapshot_with_an_unknown_book_changes_nothing() {
+        let mut source = Engine::new();
+        source.add_instrument(SYMBOL, OrderBook::new());
+        source.add_instrument("OTHER", OrderBook::new());
+        source.connect_session(1);
+        source.add_order(SYMBOL, pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+        let bytes = source.write_snapshot(7);
+
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine.connect_session(2);
+        engine.add_order(SYMBOL, pointer(order(5, Side::Buy, 90.0, 3))).unwrap();
+        let state_hash = engine.state_hash();
+        assert!(matches!(engine.restore_snapshot(&bytes), Err(SnapshotError::UnknownSymbol(symbol)) if symbol == "OTHER"));
+        assert_eq!(engine.state_hash(), state_hash);
+
+        engine.add_instrument("OTHER", OrderBook::new());
+        assert_eq!(engine.restore_snapshot(&bytes).unwrap(), 7);
+        assert_eq!(engine.state_hash(), source.state_hash());
+    }
+
+    #[test]
+    fn same_commands_hash_the_same() {
+        let run = |last_price: f32| {
+            let clock = Rc::new(ManualClock::new(1_000));
+            let mut engine = Engine::new();
+            engine.set_clock(clock.clone());
+            engine.add_instrument(SYMBOL, OrderBook::new());
+            engine.connect_session(1);
+            engine.add_order(SYMBOL, pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+            clock.advance(5);
+            engine.add_order(SYMBOL, pointer(order(2, Side::Buy, 100.0, 10))).unwrap();
+            clock.advance(5);
+            engine.add_order(SYMBOL, pointer(order(3, Side::Sell, last_price, 2))).unwrap();
+            engine.state_hash()
+        };
+
+        assert_eq!(run(101.0), run(101.0));
+        assert_ne!(run(101.0), run(102.0));
+    }
+
+    #[test]
+    fn trade_ids_are_unique_across_symbols() {
+        let clock = Rc::new(ManualClock::new(1_000));
+        let mut engine = Engine::new();
+        engine.set_clock(clock.clone());
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine.add_instrument("OTHER", OrderBook::new());
+
+        engine.add_order(SYMBOL, pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+        let trades = engine.add_order(SYMBOL, pointer(order(2, Side::Buy, 100.0, 4))).unwrap();
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!(trade.get_trade_id(), 1);
+        assert_eq!(trade.get_aggressor_side(), Side::Buy);
+        assert_eq!(trade.get_aggressor_order_id(), 2);
+        assert_eq!(trade.get_passive_order_id(), 1);
+        assert_eq!(trade.get_ask_trade().order_id, 1);
+        assert_eq!(trade.get_timestamp(), 1_000);
+
+        clock.advance(5);
+        engine.add_order("OTHER", pointer(order(3, Side::Buy, 50.0, 2))).unwrap();
+        let trades = engine.add_order("OTHER", pointer(order(4, Side::Sell, 49.0, 2))).unwrap();
+        let trade = &trades[0];
+        assert_eq!(trade.get_trade_id(), 2);
+        assert_eq!(trade.get_aggressor_side(), Side::Sell);
+        assert_eq!(trade.get_aggressor_order_id(), 4);
+        assert_eq!(trade.get_passive_order_id(), 3);
+        assert_eq!(trade.get_bid_trade().order_id, 3);
+        assert_eq!(trade.get_price(), OrderedFloat(50.0));
+        assert_eq!(trade.get_timestamp(), 1_005);
+    }
+
+    #[test]
+    fn added_book_keeps_its_trade_ids_unique() {
+        let mut book = OrderBook::new();
+        book.add_order(pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+        book.add_order(pointer(order(2, Side::Buy, 100.0, 1))).unwrap();
+        book.add_order(pointer(order(3, Side::Buy, 100.0, 1))).unwrap();
+
+        let mut engine = Engine::new();
+        engine.add_instrument("OTHER", OrderBook::new());
+        engine.add_instrument(SYMBOL, book);
+        engine.add_order("OTHER", pointer(order(4, Side::Sell, 100.0, 4))).unwrap();
+        let trades = engine.add_order("OTHER", pointer(order(5, Side::Buy, 100.0, 1))).unwrap();
+        assert_eq!(trades[0].get_trade_id(), 3);
+    }
+
+    #[test]
+    fn restored_engine_goes_on_with_the_trade_ids() {
+        let mut source = Engine::new();
+        source.add_instrument(SYMBOL, OrderBook::new());
+        source.add_instrument("OTHER", OrderBook::new());
+        source.add_order(SYMBOL, pointer(order(1, Side::Sell, 100.0, 4))).unwrap();
+        source.add_order(SYMBOL, pointer(order(2, Side::Buy, 100.0, 1))).unwrap();
+        let bytes = source.write_snapshot(1);
+
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine.add_instrument("OTHER", OrderBook::new());
+        engine.restore_snapshot(&bytes).unwrap();
+        engine.add_order("OTHER", pointer(order(3, Side::Sell, 100.0, 4))).unwrap();
+        let trades = engine.add_order("OTHER", pointer(order(4, Side::Buy, 100.0, 1))).unwrap();
+        assert_eq!(trades[0].get_trade_id(), 2);
+        let trades = engine.add_order(SYMBOL, pointer(order(5, Side::Buy, 100.0, 1))).unwrap();
+        assert_eq!(trades[0].get_trade_id(), 3);
+    }
+}
@@ -0,0 +1,32 @@
+use super::{OrderId, Price, Quantity, Side};
+
+// ----------------------------
+// Events are things the orderbook did to orders nobody asked about
+// one by one (pegged re-pricing, mass cancels, linked orders), collected until `take_events` is called.
+// ----------------------------
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderEvent {
+    // a pegged order was moved to a new price, it lost its time priority.
+    Modified {
+        order_id: OrderId,
+        side: Side,
+        old_price: Price,
+        new_price: Price,
+        remaining_quantity: Quantity,
+    },
+    // an order was cancelled by a mass cancel (filter, kill switch, session disconnect)
+    // or because an order it's linked with traded or left the book (OCO sibling, bracket entry).
+    Cancelled {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        remaining_quantity: Quantity,
+    },
+    // a stop order was triggered or a bracket leg was activated, it went through matching
+    // like a new order: its trades are among the operation's trades and what's left may rest.
+    Activated {
+        order_id: OrderId,
+        side: Side,
+    },
+}
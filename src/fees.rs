@@ -0,0 +1,194 @@
+use super::{AccountId, Amount, BTreeMap, Price, Quantity, Trade};
+
+// Negative rates are rebates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FeeRate {
+    BasisPoints(f64), // of the traded notional, price * quantity.
+    PerUnit(f64),     // per unit of quantity traded.
+}
+impl FeeRate {
+    pub fn get_fee(&self, price: Price, quantity: Quantity) -> Amount {
+        match *self {
+            FeeRate::BasisPoints(bps) => Amount::from(price.into_inner() as f64 * quantity as f64 * bps / 10_000.0),
+            FeeRate::PerUnit(rate) => Amount::from(rate * quantity as f64),
+        }
+    }
+}
+
+// maker is the passive (resting) side of a trade, taker is the aggressor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeSchedule {
+    maker: FeeRate,
+    taker: FeeRate,
+}
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self::new(FeeRate::BasisPoints(0.0), FeeRate::BasisPoints(0.0))
+    }
+}
+impl FeeSchedule {
+    pub fn new(maker: FeeRate, taker: FeeRate) -> Self {
+        Self { maker, taker }
+    }
+    pub fn get_maker(&self) -> FeeRate {
+        self.maker
+    }
+    pub fn get_taker(&self) -> FeeRate {
+        self.taker
+    }
+}
+
+// ----------------------------
+// FeeModel: one default schedule, participants can be put on their own tier.
+// Orders without an account pay the default schedule.
+// ----------------------------
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeModel {
+    default: FeeSchedule,
+    tiers: BTreeMap<AccountId, FeeSchedule>,
+}
+impl FeeModel {
+    pub fn new(default: FeeSchedule) -> Self {
+        Self { default, tiers: BTreeMap::new() }
+    }
+
+    pub fn set_tier(&mut self, account_id: AccountId, schedule: FeeSchedule) {
+        self.tiers.insert(account_id, schedule);
+    }
+    pub fn remove_tier(&mut self, account_id: AccountId) {
+        self.tiers.remove(&account_id);
+    }
+
+    pub fn get_default(&self) -> FeeSchedule {
+        self.default
+    }
+
+    // Accounts on their own tier, sorted by account.
+    pub fn get_tiers(&self) -> Vec<(AccountId, FeeSchedule)> {
+        self.tiers.iter().map(|(account_id, schedule)| (*account_id, *schedule)).collect()
+    }
+
+    pub fn get_schedule(&self, account_id: Option<AccountId>) -> FeeSchedule {
+        account_id.and_then(|account_id| self.tiers.get(&account_id).copied()).unwrap_or(self.default)
+    }
+
+    pub fn get_maker_fee(&self, account_id: Option<AccountId>, price: Price, quantity: Quantity) -> Amount {
+        self.get_schedule(account_id).maker.get_fee(price, quantity)
+    }
+    pub fn get_taker_fee(&self, account_id: Option<AccountId>, price: Price, quantity: Quantity) -> Amount {
+        self.get_schedule(account_id).taker.get_fee(price, quantity)
+    }
+}
+
+// ----------------------------
+// FeeAccumulator sums the fees (rebates are negative) charged to every account.
+// ----------------------------
+#[derive(Default)]
+pub struct FeeAccumulator {
+    fees: BTreeMap<AccountId, Amount>,
+}
+impl FeeAccumulator {
+    pub fn new() -> Self {
+        Self { fees: BTreeMap::new() }
+    }
+
+    pub fn add_trade(&mut self, trade: &Trade) {
+        for trade_info in [trade.get_bid_trade(), trade.get_ask_trade()] {
+            if let Some(account_id) = trade_info.account_id {
+                *self.fees.entry(account_id).or_default() += trade_info.fee;
+            }
+        }
+    }
+
+    pub fn get_fees(&self, account_id: AccountId) -> Amount {
+        self.fees.get(&account_id).copied().unwrap_or_default()
+    }
+
+    // Every account with accumulated fees, sorted by account.
+    pub fn get_all_fees(&self) -> Vec<(AccountId, Amount)> {
+        self.fees.iter().map(|(account_id, fees)| (*account_id, *fees)).collect()
+    }
+
+    // Adds to what's accumulated for `account_id`, used when restoring a snapshot.
+    pub fn add_fees(&mut self, account_id: AccountId, fees: Amount) {
+        *self.fees.entry(account_id).or_default() += fees;
+    }
+
+    // returns what was accumulated before the reset.
+    pub fn reset(&mut self, account_id: AccountId) -> Amount {
+        self.fees.remove(&account_id).unwrap_or_default()
+    }
+
+    pub fn reset_all(&mut self) {
+        self.fees.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Side, TradeInfo};
+    use ordered_float::OrderedFloat;
+
+    fn model() -> FeeModel {
+        // makers get a rebate by default, account 7 pays a flat fee per unit both ways
+        let mut model = FeeModel::new(FeeSchedule::new(FeeRate::BasisPoints(-1.0), FeeRate::BasisPoints(2.0)));
+        model.set_tier(7, FeeSchedule::new(FeeRate::PerUnit(0.5), FeeRate::PerUnit(0.5)));
+        model
+    }
+
+    fn trade(model: &FeeModel, bid_account: Option<AccountId>, ask_account: Option<AccountId>, quantity: Quantity) -> Trade {
+        // the buyer takes
+        let price = OrderedFloat(100.0);
+        let bid_trade = TradeInfo::new(1, price, quantity).with_account(bid_account).with_fee(model.get_taker_fee(bid_account, price, quantity));
+        let ask_trade = TradeInfo::new(2, price, quantity).with_account(ask_account).with_fee(model.get_maker_fee(ask_account, price, quantity));
+        Trade::new(1, Side::Buy, 0, bid_trade, ask_trade)
+    }
+
+    #[test]
+    fn accounts_on_a_tier_pay_its_schedule() {
+        let mut model = model();
+        let price = OrderedFloat(100.0);
+        assert_eq!(model.get_taker_fee(Some(1), price, 50), Amount::from(1.0));
+        assert_eq!(model.get_taker_fee(None, price, 50), Amount::from(1.0));
+        assert_eq!(model.get_taker_fee(Some(7), price, 50), Amount::from(25.0));
+        assert_eq!(model.get_maker_fee(Some(7), price, 50), Amount::from(25.0));
+
+        model.remove_tier(7);
+        assert_eq!(model.get_schedule(Some(7)), model.get_default());
+        assert!(model.get_tiers().is_empty());
+    }
+
+    #[test]
+    fn negative_maker_rate_is_a_rebate() {
+        let model = model();
+        let fee = model.get_maker_fee(Some(1), OrderedFloat(100.0), 50);
+        assert_eq!(fee, Amount::from(-0.5));
+        assert_eq!(FeeRate::PerUnit(-0.25).get_fee(OrderedFloat(100.0), 4), Amount::from(-1.0));
+    }
+
+    #[test]
+    fn accumulator_sums_fees_per_account_until_reset() {
+        let model = model();
+        let mut accumulator = FeeAccumulator::new();
+        accumulator.add_trade(&trade(&model, Some(1), Some(2), 50));
+        accumulator.add_trade(&trade(&model, Some(2), Some(7), 50));
+        // orders without an account aren't accumulated
+        accumulator.add_trade(&trade(&model, None, Some(1), 100));
+
+        // taker fee of the first trade, rebate of the last
+        assert_eq!(accumulator.get_fees(1), Amount::from(0.0));
+        assert_eq!(accumulator.get_fees(2), Amount::from(-0.5 + 1.0));
+        assert_eq!(accumulator.get_fees(7), Amount::from(25.0));
+        assert_eq!(accumulator.get_all_fees().iter().map(|(account_id, _)| *account_id).collect::<Vec<_>>(), vec![1, 2, 7]);
+
+        assert_eq!(accumulator.reset(7), Amount::from(25.0));
+        assert_eq!(accumulator.get_fees(7), Amount::from(0.0));
+        assert_eq!(accumulator.reset(7), Amount::from(0.0));
+        accumulator.reset_all();
+        assert!(accumulator.get_all_fees().is_empty());
+    }
+}
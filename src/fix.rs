@@ -0,0 +1,1011 @@
+use super::*;
+use crate::helperfns::{format_utc_timestamp, get_timestamp};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+// longest BeginString or BodyLength field read before giving up on the header.
+const MAX_HEADER_FIELD_LENGTH: u64 = 32;
+// application messages kept per counterparty for resends, older ones are gap filled.
+const MAX_SENT_MESSAGES: usize = 10_000;
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// messages and updates queued per connection, a counterparty that lets more pile up is disconnected.
+const MAX_QUEUED_INPUTS: usize = 4096;
+
+// Tags used by the acceptor, FIX 4.4 numbering.
+pub mod tags {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+// Message types used by the acceptor.
+pub mod msg_types {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// ----------------------------
+// FixMessage is the body of a message, MsgType first, in field order.
+// BeginString, BodyLength and CheckSum are added by `encode` and checked by `read_from`.
+// ----------------------------
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self { fields: vec![(tags::MSG_TYPE, msg_type.to_string())] }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    // Replaces the first `tag` field or appends one.
+    pub fn set(&mut self, tag: u32, value: impl ToString) {
+        match self.fields.iter_mut().find(|(field_tag, _)| *field_tag == tag) {
+            Some((_, field_value)) => *field_value = value.to_string(),
+            None => self.fields.push((tag, value.to_string())),
+        }
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(field_tag, _)| *field_tag == tag).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, tag: u32) -> Option<T> {
+        self.get(tag)?.parse().ok()
+    }
+
+    pub fn get_msg_type(&self) -> &str {
+        self.get(tags::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn get_seq_num(&self) -> Option<u64> {
+        self.get_parsed(tags::MSG_SEQ_NUM)
+    }
+
+    pub fn is_poss_dup(&self) -> bool {
+        self.get(tags::POSS_DUP_FLAG) == Some("Y")
+    }
+
+    pub fn get_fields(&self) -> &[(u32, String)] {
+        &self.fields
+    }
+
+    // Same message with the standard header right after MsgType.
+    fn with_header(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: u64, sending_time: &str) -> Self {
+        let mut message = Self::new(self.get_msg_type())
+            .with(tags::SENDER_COMP_ID, sender_comp_id)
+            .with(tags::TARGET_COMP_ID, target_comp_id)
+            .with(tags::MSG_SEQ_NUM, seq_num)
+            .with(tags::SENDING_TIME, sending_time);
+        for (tag, value) in &self.fields {
+            if !matches!(*tag, tags::MSG_TYPE | tags::SENDER_COMP_ID | tags::TARGET_COMP_ID | tags::MSG_SEQ_NUM | tags::SENDING_TIME) {
+                message.fields.push((*tag, value.clone()));
+            }
+        }
+        message
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+        let mut message = format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).into_bytes();
+        message.extend_from_slice(&body);
+        let checksum = message.iter().map(|byte| *byte as u32).sum::<u32>() % 256;
+        message.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        message
+    }
+
+    // Reads one message, a bad BeginString, BodyLength or CheckSum is an InvalidData error.
+    pub fn read_from(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut header = Vec::new();
+        if Self::read_header_field(reader, &mut header)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if header != format!("8={}\x01", BEGIN_STRING).as_bytes() {
+            return Err(invalid_data("bad BeginString"));
+        }
+        let start = header.len();
+        Self::read_header_field(reader, &mut header)?;
+        let length = std::str::from_utf8(&header[start..])
+            .ok()
+            .and_then(|field| field.strip_prefix("9="))
+            .and_then(|field| field.strip_suffix('\x01'))
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| *length <= MAX_BODY_LENGTH)
+            .ok_or_else(|| invalid_data("bad BodyLength"))?;
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        let mut trailer = [0; 7];
+        reader.read_exact(&mut trailer)?;
+        let checksum = header.iter().chain(&body).map(|byte| *byte as u32).sum::<u32>() % 256;
+        if trailer != *format!("10={:03}\x01", checksum).as_bytes() {
+            return Err(invalid_data("bad CheckSum"));
+        }
+
+        let body = std::str::from_utf8(&body).map_err(|_| invalid_data("body isn't utf-8"))?;
+        let mut fields = Vec::new();
+        for field in body.split_terminator('\x01') {
+            let (tag, value) = field.split_once('=').ok_or_else(|| invalid_data("field without '='"))?;
+            let tag = tag.parse().map_err(|_| invalid_data("bad tag"))?;
+            fields.push((tag, value.to_string()));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tags::MSG_TYPE) {
+            return Err(invalid_data("MsgType isn't the first body field"));
+        }
+        Ok(Self { fields })
+    }
+
+    // Appends one field up to and including its SOH, a field running past
+    // MAX_HEADER_FIELD_LENGTH is an InvalidData error instead of growing the buffer.
+    fn read_header_field(reader: &mut impl BufRead, header: &mut Vec<u8>) -> io::Result<usize> {
+        let read = reader.by_ref().take(MAX_HEADER_FIELD_LENGTH).read_until(SOH, header)?;
+        if read > 0 && header.last() != Some(&SOH) {
+            return Err(invalid_data("header field too long"));
+        }
+        Ok(read)
+    }
+
+    // ----------------------------
+    // Application messages a client sends, limit orders have a price, market orders don't.
+    // ----------------------------
+    pub fn new_order_single(client_order_id: &str, symbol: &str, side: Side, quantity: Quantity, price: Option<Price>) -> Self {
+        let message = Self::new(msg_types::NEW_ORDER_SINGLE)
+            .with(tags::CL_ORD_ID, client_order_id)
+            .with(tags::SYMBOL, symbol)
+            .with(tags::SIDE, side_to_fix(side))
+            .with(tags::ORDER_QTY, quantity);
+        match price {
+            Some(price) => message.with(tags::ORD_TYPE, "2").with(tags::PRICE, price),
+            None => message.with(tags::ORD_TYPE, "1"),
+        }
+    }
+
+    pub fn order_cancel_request(client_order_id: &str, original_client_order_id: &str, symbol: &str, side: Side) -> Self {
+        Self::new(msg_types::ORDER_CANCEL_REQUEST)
+            .with(tags::CL_ORD_ID, client_order_id)
+            .with(tags::ORIG_CL_ORD_ID, original_client_order_id)
+            .with(tags::SYMBOL, symbol)
+            .with(tags::SIDE, side_to_fix(side))
+    }
+
+    pub fn order_cancel_replace_request(client_order_id: &str, original_client_order_id: &str, symbol: &str, side: Side, quantity: Quantity, price: Price) -> Self {
+        Self::new(msg_types::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(tags::CL_ORD_ID, client_order_id)
+            .with(tags::ORIG_CL_ORD_ID, original_client_order_id)
+            .with(tags::SYMBOL, symbol)
+            .with(tags::SIDE, side_to_fix(side))
+            .with(tags::ORDER_QTY, quantity)
+            .with(tags::ORD_TYPE, "2")
+            .with(tags::PRICE, price)
+    }
+}
+
+fn side_to_fix(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    }
+}
+
+fn side_from_fix(value: &str) -> Option<Side> {
+    match value {
+        "1" => Some(Side::Buy),
+        "2" => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+fn is_admin(msg_type: &str) -> bool {
+    matches!(
+        msg_type,
+        msg_types::HEARTBEAT | msg_types::TEST_REQUEST | msg_types::RESEND_REQUEST | msg_types::REJECT | msg_types::SEQUENCE_RESET | msg_types::LOGOUT | msg_types::LOGON
+    )
+}
+
+// Sequence numbers and sent application messages of one counterparty,
+// kept across reconnects until the counterparty logs on with ResetSeqNumFlag.
+// Only the last MAX_SENT_MESSAGES are kept, a resend gap fills anything older.
+struct FixSessionStore {
+    next_outgoing: u64,
+    next_incoming: u64,
+    sent: BTreeMap<u64, FixMessage>,
+    logged_on: bool,
+}
+impl FixSessionStore {
+    fn new() -> Self {
+        Self { next_outgoing: 1, next_incoming: 1, sent: BTreeMap::new(), logged_on: false }
+    }
+}
+
+type SessionStores = Arc<Mutex<BTreeMap<String, FixSessionStore>>>;
+
+enum FixInput {
+    Message(FixMessage),
+    Update(OrderUpdate),
+    Closed,
+}
+
+// Reads messages off `stream` on a thread of its own until the connection drops,
+// it stops reading while the queue is full.
+fn spawn_reader(stream: &TcpStream, sender: SyncSender<FixInput>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    thread::spawn(move || {
+        while let Ok(message) = FixMessage::read_from(&mut reader) {
+            if sender.send(FixInput::Message(message)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(FixInput::Closed);
+    });
+    Ok(())
+}
+
+// ----------------------------
+// FixAcceptor: FIX 4.4 order entry over TCP, one thread per connection.
+// Session layer: Logon (optionally resetting sequence numbers), Heartbeat and TestRequest
+// on the negotiated interval, gap detection with ResendRequest, answering the counterparty's
+// ResendRequest (application messages resent as PossDup, admin ones gap filled), SequenceReset and Logout.
+// Application layer: NewOrderSingle, OrderCancelRequest and OrderCancelReplaceRequest go to the
+// matching thread, ExecutionReports and OrderCancelRejects come back. Orders are cancelled when
+// their connection drops (see `Engine::disconnect_session`), a connection that lets
+// MAX_QUEUED_INPUTS updates pile up is dropped.
+// ----------------------------
+pub struct FixAcceptor {
+    local_address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+impl FixAcceptor {
+    // `comp_id` is our SenderCompID, clients must send it as TargetCompID.
+    pub fn bind(address: impl ToSocketAddrs, comp_id: &str, handle: EngineHandle) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stores: SessionStores = Arc::new(Mutex::new(BTreeMap::new()));
+
+        let comp_id = comp_id.to_string();
+        let accept_shutdown = shutdown.clone();
+        thread::spawn(move || {
+            while !accept_shutdown.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let (comp_id, handle, stores, shutdown) = (comp_id.clone(), handle.clone(), stores.clone(), accept_shutdown.clone());
+                        thread::spawn(move || {
+                            let _ = FixConnection::serve(stream, comp_id, handle, stores, shutdown);
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Self { local_address, shutdown })
+    }
+
+    pub fn get_local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+}
+impl Drop for FixAcceptor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+struct FixConnection {
+    stream: TcpStream,
+    comp_id: String,
+    counterparty: String,
+    session_id: SessionId,
+    handle: EngineHandle,
+    stores: SessionStores,
+    heartbeat_interval: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request_sent: Option<Instant>,
+    resend_requested: bool,
+    next_exec_id: u64,
+}
+impl FixConnection {
+    fn serve(stream: TcpStream, comp_id: String, handle: EngineHandle, stores: SessionStores, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        let (sender, inputs) = mpsc::sync_channel(MAX_QUEUED_INPUTS);
+        spawn_reader(&stream, sender.clone())?;
+
+        let Ok(FixInput::Message(logon)) = inputs.recv_timeout(LOGON_TIMEOUT) else {
+            return stream.shutdown(Shutdown::Both);
+        };
+        let (Some(counterparty), Some(seq_num), Some(heartbeat_interval)) =
+            (logon.get(tags::SENDER_COMP_ID), logon.get_seq_num(), logon.get_parsed::<u64>(tags::HEART_BT_INT))
+        else {
+            return stream.shutdown(Shutdown::Both);
+        };
+        if logon.get_msg_type() != msg_types::LOGON || logon.get(tags::TARGET_COMP_ID) != Some(comp_id.as_str()) || heartbeat_interval == 0 {
+            return stream.shutdown(Shutdown::Both);
+        }
+
+        let reset = logon.get(tags::RESET_SEQ_NUM_FLAG) == Some("Y");
+        let expected = {
+            let mut stores = stores.lock().unwrap();
+            let store = stores.entry(counterparty.to_string()).or_insert_with(FixSessionStore::new);
+            if store.logged_on {
+                return stream.shutdown(Shutdown::Both);
+            }
+            if reset {
+                *store = FixSessionStore::new();
+            }
+            store.logged_on = true;
+            store.next_incoming
+        };
+
+        let mut connection = FixConnection {
+            stream,
+            comp_id,
+            counterparty: counterparty.to_string(),
+            session_id: handle.new_session_id(),
+            handle,
+            stores,
+            heartbeat_interval: Duration::from_secs(heartbeat_interval),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request_sent: None,
+            resend_requested: false,
+            next_exec_id: 1,
+        };
+        let result = connection.run(seq_num, expected, reset, sender, inputs, shutdown);
+
+        connection.handle.send(EngineRequest::Disconnect { session_id: connection.session_id });
+        if let Some(store) = connection.stores.lock().unwrap().get_mut(&connection.counterparty) {
+            store.logged_on = false;
+        }
+        let _ = connection.stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    fn run(&mut self, seq_num: u64, expected: u64, reset: bool, sender: SyncSender<FixInput>, inputs: Receiver<FixInput>, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+        if seq_num < expected {
+            return self.logout(&format!("MsgSeqNum too low, expecting {} but received {}", expected, seq_num));
+        }
+
+        let mut reply = FixMessage::new(msg_types::LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, self.heartbeat_interval.as_secs());
+        if reset {
+            reply.set(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        self.send(reply)?;
+        self.handle.send(EngineRequest::Connect {
+            session_id: self.session_id,
+            sink: connection_sink(sender, self.stream.try_clone()?, |update| Some(FixInput::Update(update))),
+        });
+        if seq_num > expected {
+            self.request_resend(expected)?;
+        } else {
+            self.set_next_incoming(seq_num + 1);
+        }
+
+        loop {
+            if shutdown.load(Ordering::Relaxed) {
+                return self.logout("acceptor shutting down");
+            }
+            match inputs.recv_timeout(POLL_INTERVAL) {
+                Ok(FixInput::Message(message)) => {
+                    self.last_received = Instant::now();
+                    self.test_request_sent = None;
+                    if !self.on_message(message)? {
+                        return Ok(());
+                    }
+                }
+                Ok(FixInput::Update(update)) => self.on_update(update)?,
+                Ok(FixInput::Closed) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            if !self.check_heartbeats()? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Heartbeat when we've been quiet, TestRequest when they have, gives up
+    // (returns false) when a TestRequest goes unanswered for another interval.
+    fn check_heartbeats(&mut self) -> io::Result<bool> {
+        if self.last_sent.elapsed() >= self.heartbeat_interval {
+            self.send(FixMessage::new(msg_types::HEARTBEAT))?;
+        }
+        let grace = self.heartbeat_interval + self.heartbeat_interval / 5;
+        match self.test_request_sent {
+            Some(sent) if sent.elapsed() >= grace => return Ok(false),
+            None if self.last_received.elapsed() >= grace => {
+                self.send(FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, format!("TEST-{}", get_timestamp())))?;
+                self.test_request_sent = Some(Instant::now());
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn get_next_incoming(&self) -> u64 {
+        self.stores.lock().unwrap().get(&self.counterparty).map_or(1, |store| store.next_incoming)
+    }
+
+    fn set_next_incoming(&self, next_incoming: u64) {
+        if let Some(store) = self.stores.lock().unwrap().get_mut(&self.counterparty) {
+            store.next_incoming = next_incoming;
+        }
+    }
+
+    // returns false when the session is over.
+    fn on_message(&mut self, message: FixMessage) -> io::Result<bool> {
+        let Some(seq_num) = message.get_seq_num() else {
+            self.logout("MsgSeqNum missing")?;
+            return Ok(false);
+        };
+        let expected = self.get_next_incoming();
+
+        if message.get_msg_type() == msg_types::SEQUENCE_RESET {
+            let new_seq_no = message.get_parsed::<u64>(tags::NEW_SEQ_NO).unwrap_or(expected);
+            let gap_fill = message.get(tags::GAP_FILL_FLAG) == Some("Y");
+            if (!gap_fill || seq_num <= expected) && new_seq_no > expected {
+                self.set_next_incoming(new_seq_no);
+                self.resend_requested = false;
+            }
+            return Ok(true);
+        }
+        if seq_num > expected {
+            // dropped, the resend brings it back in order
+            if !self.resend_requested {
+                self.request_resend(expected)?;
+            }
+            return Ok(true);
+        }
+        if seq_num < expected {
+            if message.is_poss_dup() {
+                return Ok(true);
+            }
+            self.logout(&format!("MsgSeqNum too low, expecting {} but received {}", expected, seq_num))?;
+            return Ok(false);
+        }
+        self.set_next_incoming(expected + 1);
+        self.resend_requested = false;
+
+        match message.get_msg_type() {
+            msg_types::HEARTBEAT | msg_types::REJECT | msg_types::LOGON => {}
+            msg_types::TEST_REQUEST => {
+                let test_req_id = message.get(tags::TEST_REQ_ID).unwrap_or_default().to_string();
+                self.send(FixMessage::new(msg_types::HEARTBEAT).with(tags::TEST_REQ_ID, test_req_id))?;
+            }
+            msg_types::RESEND_REQUEST => {
+                let begin = message.get_parsed(tags::BEGIN_SEQ_NO).unwrap_or(1);
+                let end = message.get_parsed(tags::END_SEQ_NO).unwrap_or(0);
+                self.resend(begin, end)?;
+            }
+            msg_types::LOGOUT => {
+                self.send(FixMessage::new(msg_types::LOGOUT))?;
+                return Ok(false);
+            }
+            msg_types::NEW_ORDER_SINGLE => self.on_new_order_single(&message)?,
+            msg_types::ORDER_CANCEL_REQUEST => self.on_order_cancel_request(&message)?,
+            msg_types::ORDER_CANCEL_REPLACE_REQUEST => self.on_order_cancel_replace_request(&message)?,
+            _ => self.reject(&message, tags::MSG_TYPE, 11, "unsupported MsgType")?,
+        }
+        Ok(true)
+    }
+
+    fn on_new_order_single(&mut self, message: &FixMessage) -> io::Result<()> {
+        let Some(client_order_id) = message.get(tags::CL_ORD_ID) else {
+            return self.reject(message, tags::CL_ORD_ID, 1, "ClOrdID missing");
+        };
+        let Some(symbol) = message.get(tags::SYMBOL) else {
+            return self.reject(message, tags::SYMBOL, 1, "Symbol missing");
+        };
+        let Some(side) = message.get(tags::SIDE).and_then(side_from_fix) else {
+            return self.reject(message, tags::SIDE, 5, "Side must be 1 (buy) or 2 (sell)");
+        };
+        let Some(quantity) = message.get_parsed::<Quantity>(tags::ORDER_QTY) else {
+            return self.reject(message, tags::ORDER_QTY, 5, "bad OrderQty");
+        };
+        let is_market = match message.get(tags::ORD_TYPE) {
+            Some("1") => true,
+            Some("2") => false,
+            _ => return self.reject(message, tags::ORD_TYPE, 5, "OrdType must be 1 (market) or 2 (limit)"),
+        };
+        let price = if is_market {
+            Price::from(f32::NAN)
+        } else {
+            match message.get_parsed::<f32>(tags::PRICE) {
+                Some(price) if price.is_finite() => Price::from(price),
+                _ => return self.reject(message, tags::PRICE, 5, "limit orders need a Price"),
+            }
+        };
+        // 0 day and 1 good till cancel rest, 3 immediate or cancel, 4 fill or kill
+        let (order_type, all_or_none) = match (message.get(tags::TIME_IN_FORCE).unwrap_or("0"), is_market) {
+            ("0" | "1" | "3", true) => (OrderType::Market, false),
+            ("4", true) => (OrderType::Market, true),
+            ("0" | "1", false) => (OrderType::GoodTillCancel, false),
+            ("3", false) => (OrderType::FillAndKill, false),
+            ("4", false) => (OrderType::FillAndKill, true),
+            _ => return self.reject(message, tags::TIME_IN_FORCE, 5, "unsupported TimeInForce"),
+        };
+
+        let order = NewOrder {
+            client_order_id: client_order_id.to_string(),
+            symbol: symbol.to_string(),
+            side,
+            order_type,
+            price,
+            quantity,
+            all_or_none,
+            account_id: message.get_parsed(tags::ACCOUNT),
+        };
+        self.handle.send(EngineRequest::NewOrder { session_id: self.session_id, order });
+        Ok(())
+    }
+
+    fn on_order_cancel_request(&mut self, message: &FixMessage) -> io::Result<()> {
+        let (Some(client_order_id), Some(original_client_order_id)) = (message.get(tags::CL_ORD_ID), message.get(tags::ORIG_CL_ORD_ID)) else {
+            return self.reject(message, tags::ORIG_CL_ORD_ID, 1, "ClOrdID and OrigClOrdID are required");
+        };
+        self.handle.send(EngineRequest::CancelOrder {
+            session_id: self.session_id,
+            client_order_id: client_order_id.to_string(),
+            original_client_order_id: original_client_order_id.to_string(),
+        });
+        Ok(())
+    }
+
+    fn on_order_cancel_replace_request(&mut self, message: &FixMessage) -> io::Result<()> {
+        let (Some(client_order_id), Some(original_client_order_id)) = (message.get(tags::CL_ORD_ID), message.get(tags::ORIG_CL_ORD_ID)) else {
+            return self.reject(message, tags::ORIG_CL_ORD_ID, 1, "ClOrdID and OrigClOrdID are required");
+        };
+        let Some(quantity) = message.get_parsed::<Quantity>(tags::ORDER_QTY) else {
+            return self.reject(message, tags::ORDER_QTY, 5, "bad OrderQty");
+        };
+        let price = match (message.get(tags::ORD_TYPE), message.get_parsed::<f32>(tags::PRICE)) {
+            (Some("1"), _) => Price::from(f32::NAN),
+            (_, Some(price)) if price.is_finite() => Price::from(price),
+            _ => return self.reject(message, tags::PRICE, 5, "limit orders need a Price"),
+        };
+        self.handle.send(EngineRequest::ReplaceOrder {
+            session_id: self.session_id,
+            client_order_id: client_order_id.to_string(),
+            original_client_order_id: original_client_order_id.to_string(),
+            price,
+            quantity,
+        });
+        Ok(())
+    }
+
+    fn on_update(&mut self, update: OrderUpdate) -> io::Result<()> {
+        let message = match update {
+            OrderUpdate::Accepted(status) => self.execution_report(&status, "0", None),
+            OrderUpdate::Rejected { order, reason } => {
+                let mut message = FixMessage::new(msg_types::EXECUTION_REPORT)
+                    .with(tags::ORDER_ID, "NONE")
+                    .with(tags::CL_ORD_ID, order.client_order_id)
+                    .with(tags::EXEC_ID, self.new_exec_id())
+                    .with(tags::EXEC_TYPE, "8")
+                    .with(tags::ORD_STATUS, "8")
+                    .with(tags::SYMBOL, order.symbol)
+                    .with(tags::SIDE, side_to_fix(order.side))
+                    .with(tags::ORDER_QTY, order.quantity)
+                    .with(tags::LEAVES_QTY, 0)
+                    .with(tags::CUM_QTY, 0)
+                    .with(tags::AVG_PX, 0)
+                    .with(tags::TEXT, reason);
+                if !order.price.is_nan() {
+                    message.set(tags::PRICE, order.price);
+                }
+                message
+            }
+            OrderUpdate::Filled { status, trade_id, price, quantity } => {
+                let mut message = self.execution_report(&status, "F", None);
+                message.set(tags::EXEC_ID, format!("{}-{}-{}", status.symbol, trade_id, status.order_id));
+                message.with(tags::LAST_PX, price).with(tags::LAST_QTY, quantity)
+            }
+            OrderUpdate::Cancelled { status, original_client_order_id } => {
+                self.execution_report(&status, "4", original_client_order_id.as_deref()).with(tags::ORD_STATUS, "4")
+            }
+            OrderUpdate::Replaced { status, original_client_order_id } => self.execution_report(&status, "5", Some(&original_client_order_id)),
+            OrderUpdate::CancelRejected { client_order_id, original_client_order_id, status, is_replace, reason } => {
+                let (order_id, ord_status, cxl_rej_reason) = match &status {
+                    Some(status) => (status.order_id.to_string(), Self::get_ord_status(status), "0"),
+                    None => ("NONE".to_string(), "8", "1"),
+                };
+                FixMessage::new(msg_types::ORDER_CANCEL_REJECT)
+                    .with(tags::ORDER_ID, order_id)
+                    .with(tags::CL_ORD_ID, client_order_id)
+                    .with(tags::ORIG_CL_ORD_ID, original_client_order_id)
+                    .with(tags::ORD_STATUS, ord_status)
+                    .with(tags::CXL_REJ_RESPONSE_TO, if is_replace { "2" } else { "1" })
+                    .with(tags::CXL_REJ_REASON, cxl_rej_reason)
+                    .with(tags::TEXT, reason)
+            }
+        };
+        self.send(message)
+    }
+
+    fn get_ord_status(status: &OrderStatus) -> &'static str {
+        if status.cumulative_quantity >= status.quantity {
+            "2"
+        } else if status.cumulative_quantity > 0 {
+            "1"
+        } else {
+            "0"
+        }
+    }
+
+    fn new_exec_id(&mut self) -> String {
+        let exec_id = format!("{}.{}", self.session_id, self.next_exec_id);
+        self.next_exec_id += 1;
+        exec_id
+    }
+
+    fn execution_report(&mut self, status: &OrderStatus, exec_type: &str, original_client_order_id: Option<&str>) -> FixMessage {
+        let mut message = FixMessage::new(msg_types::EXECUTION_REPORT)
+            .with(tags::ORDER_ID, status.order_id)
+            .with(tags::CL_ORD_ID, &status.client_order_id)
+            .with(tags::EXEC_ID, self.new_exec_id())
+            .with(tags::EXEC_TYPE, exec_type)
+            .with(tags::ORD_STATUS, Self::get_ord_status(status))
+            .with(tags::SYMBOL, &status.symbol)
+            .with(tags::SIDE, side_to_fix(status.side))
+            .with(tags::ORDER_QTY, status.quantity)
+            .with(tags::ORD_TYPE, if matches!(status.order_type, OrderType::Market) { "1" } else { "2" })
+            .with(tags::LEAVES_QTY, status.leaves_quantity)
+            .with(tags::CUM_QTY, status.cumulative_quantity)
+            .with(tags::AVG_PX, status.average_price);
+        if !status.price.is_nan() {
+            message.set(tags::PRICE, status.price);
+        }
+        if let Some(original_client_order_id) = original_client_order_id {
+            message.set(tags::ORIG_CL_ORD_ID, original_client_order_id);
+        }
+        message
+    }
+
+    // Session level Reject of a message that came in fine but can't be processed.
+    fn reject(&mut self, reference: &FixMessage, tag: u32, reason: u32, text: &str) -> io::Result<()> {
+        self.send(
+            FixMessage::new(msg_types::REJECT)
+                .with(tags::REF_SEQ_NUM, reference.get_seq_num().unwrap_or(0))
+                .with(tags::REF_TAG_ID, tag)
+                .with(tags::SESSION_REJECT_REASON, reason)
+                .with(tags::TEXT, text),
+        )
+    }
+
+    fn request_resend(&mut self, from: u64) -> io::Result<()> {
+        self.resend_requested = true;
+        self.send(FixMessage::new(msg_types::RESEND_REQUEST).with(tags::BEGIN_SEQ_NO, from).with(tags::END_SEQ_NO, 0))
+    }
+
+    fn logout(&mut self, text: &str) -> io::Result<()> {
+        self.send(FixMessage::new(msg_types::LOGOUT).with(tags::TEXT, text))
+    }
+
+    // Stamps the header with the next sequence number, application messages are kept for resends.
+    fn send(&mut self, message: FixMessage) -> io::Result<()> {
+        let message = {
+            let mut stores = self.stores.lock().unwrap();
+            let store = stores.get_mut(&self.counterparty).unwrap();
+            let seq_num = store.next_outgoing;
+            store.next_outgoing += 1;
+            let message = message.with_header(&self.comp_id, &self.counterparty, seq_num, &format_utc_timestamp(get_timestamp()));
+            if !is_admin(message.get_msg_type()) {
+                store.sent.insert(seq_num, message.clone());
+                while store.sent.len() > MAX_SENT_MESSAGES {
+                    store.sent.pop_first();
+                }
+            }
+            message
+        };
+        self.write(&message)
+    }
+
+    fn write(&mut self, message: &FixMessage) -> io::Result<()> {
+        self.stream.write_all(&message.encode())?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    // Answers a ResendRequest, `end` 0 means everything sent so far.
+    fn resend(&mut self, begin: u64, end: u64) -> io::Result<()> {
+        let (messages, last_seq_num) = {
+            let stores = self.stores.lock().unwrap();
+            let store = stores.get(&self.counterparty).unwrap();
+            let last_seq_num = store.next_outgoing - 1;
+            let end = if end == 0 { last_seq_num } else { end.min(last_seq_num) };
+            let messages: Vec<FixMessage> = if begin <= end { store.sent.range(begin..=end).map(|(_, message)| message.clone()).collect() } else { Vec::new() };
+            (messages, end)
+        };
+
+        let sending_time = format_utc_timestamp(get_timestamp());
+        let mut next = begin;
+        for message in messages {
+            let seq_num = message.get_seq_num().unwrap();
+            if seq_num > next {
+                self.write_gap_fill(next, seq_num, &sending_time)?;
+            }
+            let original_sending_time = message.get(tags::SENDING_TIME).unwrap_or_default().to_string();
+            let message = message.with(tags::POSS_DUP_FLAG, "Y").with(tags::SENDING_TIME, &sending_time).with(tags::ORIG_SENDING_TIME, original_sending_time);
+            self.write(&message)?;
+            next = seq_num + 1;
+        }
+        if next <= last_seq_num {
+            self.write_gap_fill(next, last_seq_num + 1, &sending_time)?;
+        }
+        Ok(())
+    }
+
+    // Admin messages aren't resent, a gap fill skips the counterparty past them.
+    fn write_gap_fill(&mut self, seq_num: u64, new_seq_no: u64, sending_time: &str) -> io::Result<()> {
+        let message = FixMessage::new(msg_types::SEQUENCE_RESET)
+            .with(tags::GAP_FILL_FLAG, "Y")
+            .with(tags::NEW_SEQ_NO, new_seq_no)
+            .with(tags::POSS_DUP_FLAG, "Y")
+            .with_header(&self.comp_id, &self.counterparty, seq_num, sending_time);
+        self.write(&message)
+    }
+}
+
+// ----------------------------
+// FixInitiator is a minimal client side session for exercising the acceptor:
+// it logs on (resetting sequence numbers), stamps and sends messages, answers
+// TestRequests and resends what it sent when asked, everything it receives is handed back.
+// ----------------------------
+pub struct FixInitiator {
+    stream: TcpStream,
+    inputs: Receiver<FixInput>,
+    comp_id: String,
+    target_comp_id: String,
+    next_outgoing: u64,
+    sent: BTreeMap<u64, FixMessage>,
+}
+impl FixInitiator {
+    pub fn connect(address: impl ToSocketAddrs, comp_id: &str, target_comp_id: &str, heartbeat_interval: u64) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let (sender, inputs) = mpsc::sync_channel(MAX_QUEUED_INPUTS);
+        spawn_reader(&stream, sender)?;
+        let mut initiator = Self {
+            stream,
+            inputs,
+            comp_id: comp_id.to_string(),
+            target_comp_id: target_comp_id.to_string(),
+            next_outgoing: 1,
+            sent: BTreeMap::new(),
+        };
+
+        initiator.send(
+            FixMessage::new(msg_types::LOGON)
+                .with(tags::ENCRYPT_METHOD, 0)
+                .with(tags::HEART_BT_INT, heartbeat_interval)
+                .with(tags::RESET_SEQ_NUM_FLAG, "Y"),
+        )?;
+        match initiator.receive(LOGON_TIMEOUT)? {
+            Some(reply) if reply.get_msg_type() == msg_types::LOGON => Ok(initiator),
+            _ => Err(io::Error::new(io::ErrorKind::ConnectionRefused, "logon refused")),
+        }
+    }
+
+    // Returns the sequence number the message went out with.
+    pub fn send(&mut self, message: FixMessage) -> io::Result<u64> {
+        let seq_num = self.next_outgoing;
+        self.next_outgoing += 1;
+        let message = message.with_header(&self.comp_id, &self.target_comp_id, seq_num, &format_utc_timestamp(get_timestamp()));
+        self.stream.write_all(&message.encode())?;
+        self.sent.insert(seq_num, message);
+        Ok(seq_num)
+    }
+
+    // Skips sequence numbers, to see the acceptor detect the gap.
+    pub fn set_next_outgoing(&mut self, next_outgoing: u64) {
+        self.next_outgoing = next_outgoing;
+    }
+
+    // Next message from the acceptor, None when nothing came within `timeout`.
+    pub fn receive(&mut self, timeout: Duration) -> io::Result<Option<FixMessage>> {
+        let message = match self.inputs.recv_timeout(timeout) {
+            Ok(FixInput::Message(message)) => message,
+            Ok(_) | Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::ConnectionAborted.into()),
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+        };
+        match message.get_msg_type() {
+            msg_types::TEST_REQUEST => {
+                let test_req_id = message.get(tags::TEST_REQ_ID).unwrap_or_default().to_string();
+                self.send(FixMessage::new(msg_types::HEARTBEAT).with(tags::TEST_REQ_ID, test_req_id))?;
+            }
+            msg_types::RESEND_REQUEST => {
+                let begin = message.get_parsed(tags::BEGIN_SEQ_NO).unwrap_or(1);
+                let mut resent = Vec::new();
+                let mut next = begin;
+                for (seq_num, message) in self.sent.range(begin..) {
+                    if *seq_num > next {
+                        resent.push(Self::gap_fill(next, *seq_num));
+                    }
+                    resent.push(message.clone().with(tags::POSS_DUP_FLAG, "Y"));
+                    next = seq_num + 1;
+                }
+                if next < self.next_outgoing {
+                    resent.push(Self::gap_fill(next, self.next_outgoing));
+                }
+                for message in resent {
+                    let message = message.with_header(&self.comp_id, &self.target_comp_id, message.get_seq_num().unwrap(), &format_utc_timestamp(get_timestamp()));
+                    self.stream.write_all(&message.encode())?;
+                }
+            }
+            _ => {}
+        }
+        Ok(Some(message))
+    }
+
+    fn gap_fill(seq_num: u64, new_seq_no: u64) -> FixMessage {
+        FixMessage::new(msg_types::SEQUENCE_RESET)
+            .with(tags::MSG_SEQ_NUM, seq_num)
+            .with(tags::GAP_FILL_FLAG, "Y")
+            .with(tags::NEW_SEQ_NO, new_seq_no)
+            .with(tags::POSS_DUP_FLAG, "Y")
+    }
+
+    // Next message of `msg_type`, skipping the others.
+    pub fn receive_type(&mut self, msg_type: &str, timeout: Duration) -> io::Result<Option<FixMessage>> {
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.receive(remaining)? {
+                Some(message) if message.get_msg_type() == msg_type => return Ok(Some(message)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn logout(mut self) -> io::Result<()> {
+        self.send(FixMessage::new(msg_types::LOGOUT))?;
+        let _ = self.receive_type(msg_types::LOGOUT, Duration::from_secs(1));
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SYMBOL: &str = "DEMO";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // A matching service on a fresh journal and an acceptor in front of it.
+    fn start(name: &str) -> (FixAcceptor, EngineHandle) {
+        let (handle, requests) = EngineHandle::channel();
+        let path = std::env::temp_dir().join(format!("orderbook-rs-{}-{}.journal", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.add_instrument(SYMBOL, OrderBook::new());
+            MatchingService::new(JournaledEngine::open(&path, engine).unwrap(), requests).run();
+        });
+        (FixAcceptor::bind("127.0.0.1:0", "EXCHANGE", handle.clone()).unwrap(), handle)
+    }
+
+    fn receive(initiator: &mut FixInitiator) -> FixMessage {
+        initiator.receive(TIMEOUT).unwrap().expect("no message from the acceptor")
+    }
+
+    #[test]
+    fn logon_order_and_resend() {
+        let (acceptor, handle) = start("fix-loopback");
+        let mut alice = FixInitiator::connect(acceptor.get_local_address(), "ALICE", "EXCHANGE", 30).unwrap();
+
+        alice.send(FixMessage::new_order_single("A1", SYMBOL, Side::Buy, 10, Some(OrderedFloat(100.0)))).unwrap();
+        let report = receive(&mut alice);
+        assert_eq!(report.get_msg_type(), msg_types::EXECUTION_REPORT);
+        assert_eq!(report.get_seq_num(), Some(2));
+        assert_eq!(report.get(tags::CL_ORD_ID), Some("A1"));
+        assert_eq!(report.get(tags::ORD_STATUS), Some("0"));
+        assert_eq!(report.get_parsed::<Quantity>(tags::LEAVES_QTY), Some(10));
+
+        // the logon is gap filled and the execution report comes again as a possible duplicate
+        alice.send(FixMessage::new(msg_types::RESEND_REQUEST).with(tags::BEGIN_SEQ_NO, 1).with(tags::END_SEQ_NO, 0)).unwrap();
+        let gap_fill = receive(&mut alice);
+        assert_eq!(gap_fill.get_msg_type(), msg_types::SEQUENCE_RESET);
+        assert_eq!((gap_fill.get_seq_num(), gap_fill.get(tags::GAP_FILL_FLAG)), (Some(1), Some("Y")));
+        assert_eq!(gap_fill.get_parsed::<u64>(tags::NEW_SEQ_NO), Some(2));
+        let resent = receive(&mut alice);
+        assert_eq!(resent.get_msg_type(), msg_types::EXECUTION_REPORT);
+        assert_eq!(resent.get_seq_num(), Some(2));
+        assert!(resent.is_poss_dup());
+        assert_eq!(resent.get(tags::CL_ORD_ID), Some("A1"));
+        assert!(resent.get(tags::ORIG_SENDING_TIME).is_some());
+
+        alice.logout().unwrap();
+        handle.send(EngineRequest::Shutdown);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let message = FixMessage::new_order_single("A1", SYMBOL, Side::Sell, 5, Some(OrderedFloat(101.5))).with_header("ALICE", "EXCHANGE", 7, "20260101-00:00:00.000");
+        let read = FixMessage::read_from(&mut Cursor::new(message.encode())).unwrap();
+        assert_eq!(read.get_fields(), message.get_fields());
+    }
+
+    #[test]
+    fn endless_header_field_is_rejected() {
+        let mut bytes = format!("8={}\x019=", BEGIN_STRING).into_bytes();
+        bytes.extend(std::iter::repeat_n(b'1', 1 << 20));
+        let error = FixMessage::read_from(&mut Cursor::new(bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = FixMessage::read_from(&mut Cursor::new(vec![b'8'; 1 << 20])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,762 @@
+use super::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// trades kept per symbol for `AdminRequest::RecentTrades` unless `with_trade_history` says otherwise.
+const DEFAULT_TRADE_HISTORY: usize = 100;
+// how much longer than its deadline `EngineHandle::admin` waits for an answer,
+// so a request the matching thread started before the deadline is always answered.
+const ADMIN_REPLY_GRACE: Duration = Duration::from_secs(1);
+
+// A new order as every order entry protocol sees it, `price` is NaN for market orders.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NewOrder {
+    pub client_order_id: String,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub order_type: OrderType,
+    #[cfg_attr(feature = "serde", serde(with = "crate::helperfns::nan_as_null"))]
+    pub price: Price,
+    pub quantity: Quantity,
+    pub all_or_none: bool,
+    pub account_id: Option<AccountId>,
+}
+
+// Where a gateway order stands, what execution reports are built from.
+// `quantity` is the order quantity (replaces included), filled quantity counts towards it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderStatus {
+    pub order_id: OrderId,
+    pub client_order_id: String,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub order_type: OrderType,
+    #[cfg_attr(feature = "serde", serde(with = "crate::helperfns::nan_as_null"))]
+    pub price: Price,
+    pub quantity: Quantity,
+    pub leaves_quantity: Quantity,
+    pub cumulative_quantity: Quantity,
+    pub average_price: f64,
+}
+
+// Why the gateway refused a request, protocols map it to their own codes (FIX sends the text).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderRejectReason {
+    DuplicateClientOrderId,
+    UnknownSymbol,
+    InvalidQuantity,        // new order quantity is zero.
+    QuantityNotAboveFilled, // a replace can't bring the order quantity down to what already filled.
+    TradingHalted,
+    RejectedByBook,
+    UnknownOrder,           // cancel or replace of an order the session has no live order for.
+    JournalUnavailable,     // the request couldn't be journaled, nothing was done.
+}
+impl fmt::Display for OrderRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderRejectReason::DuplicateClientOrderId => write!(f, "duplicate client order id"),
+            OrderRejectReason::UnknownSymbol => write!(f, "unknown symbol"),
+            OrderRejectReason::InvalidQuantity => write!(f, "quantity must be positive"),
+            OrderRejectReason::QuantityNotAboveFilled => write!(f, "quantity not above filled quantity"),
+            OrderRejectReason::TradingHalted => write!(f, "trading halted"),
+            OrderRejectReason::RejectedByBook => write!(f, "rejected by book"),
+            OrderRejectReason::UnknownOrder => write!(f, "unknown order"),
+            OrderRejectReason::JournalUnavailable => write!(f, "journal unavailable"),
+        }
+    }
+}
+
+// What happened to an order of a session, sent to the session in the order it happened.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderUpdate {
+    Accepted(OrderStatus),
+    Rejected { order: NewOrder, reason: OrderRejectReason },
+    Filled { status: OrderStatus, trade_id: TradeId, price: Price, quantity: Quantity },
+    // original_client_order_id is None when the engine cancelled the order on its own
+    // (fill and kill remainder, mass cancel).
+    Cancelled { status: OrderStatus, original_client_order_id: Option<String> },
+    Replaced { status: OrderStatus, original_client_order_id: String },
+    CancelRejected { client_order_id: String, original_client_order_id: String, status: Option<OrderStatus>, is_replace: bool, reason: OrderRejectReason },
+}
+
+// Delivers updates to a session, returns false once the session is gone.
+pub type UpdateSink = Box<dyn FnMut(OrderUpdate) -> bool + Send>;
+
+// Sink of a TCP session, updates are converted (None skips one) and queued on `sender` for the
+// connection's writer. The queue is bounded: once it's full the session can't keep up and
+// its connection is shut down, rather than growing without limit or missing updates.
+pub fn connection_sink<T: Send + 'static>(sender: SyncSender<T>, stream: TcpStream, mut convert: impl FnMut(OrderUpdate) -> Option<T> + Send + 'static) -> UpdateSink {
+    Box::new(move |update| {
+        let Some(item) = convert(update) else {
+            return true;
+        };
+        match sender.try_send(item) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                let _ = stream.shutdown(Shutdown::Both);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    })
+}
+
+// What operations can ask the matching service besides order entry.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminRequest {
+    ListInstruments,
+    Depth { symbol: Symbol },
+    Order { order_id: OrderId },
+    RecentTrades { symbol: Symbol },
+    Halt { symbol: Symbol },
+    Resume { symbol: Symbol },
+    MassCancel { symbol: Symbol, filter: MassCancelFilter },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstrumentInfo {
+    pub symbol: Symbol,
+    pub halted: bool,
+    pub orders: usize,
+    pub last_trade_price: Option<Price>,
+}
+
+pub enum AdminResponse {
+    Instruments(Vec<InstrumentInfo>),
+    Depth(OrderbookLevelInfos),
+    Order { symbol: Symbol, order: Order },
+    Trades(Vec<Trade>), // oldest first
+    Halted(bool), // after Halt and Resume, whether the book is halted now
+    MassCancelled(Vec<OrderId>),
+    UnknownSymbol,
+    UnknownOrder,
+    JournalUnavailable, // the request couldn't be journaled, nothing was done
+}
+
+pub enum EngineRequest {
+    Connect { session_id: SessionId, sink: UpdateSink },
+    Disconnect { session_id: SessionId },
+    NewOrder { session_id: SessionId, order: NewOrder },
+    CancelOrder { session_id: SessionId, client_order_id: String, original_client_order_id: String },
+    ReplaceOrder { session_id: SessionId, client_order_id: String, original_client_order_id: String, price: Price, quantity: Quantity },
+    // dropped without an answer when the matching thread gets to it after `deadline`
+    Admin { request: AdminRequest, reply: Sender<AdminResponse>, deadline: Instant },
+    Shutdown,
+}
+
+// ----------------------------
+// EngineHandle is how network threads reach the matching thread,
+// it's Send and cheap to clone, requests are processed one at a time in arrival order.
+// ----------------------------
+#[derive(Clone)]
+pub struct EngineHandle {
+    requests: Sender<EngineRequest>,
+    next_session_id: Arc<AtomicU64>,
+}
+impl EngineHandle {
+    // The requests go to `MatchingService::new` on the matching thread.
+    pub fn channel() -> (Self, EngineRequests) {
+        let (requests, receiver) = mpsc::channel();
+        let next_session_id = Arc::new(AtomicU64::new(1));
+        (Self { requests, next_session_id: next_session_id.clone() }, EngineRequests { receiver, next_session_id })
+    }
+
+    // Unique across every gateway sharing this handle.
+    pub fn new_session_id(&self) -> SessionId {
+        self.next_session_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // returns false once the matching service has stopped.
+    pub fn send(&self, request: EngineRequest) -> bool {
+        self.requests.send(request).is_ok()
+    }
+
+    // Sends an admin request and waits for the answer, None when the matching service has stopped
+    // or didn't get to the request within `timeout`. A request that timed out is never carried out:
+    // the matching thread drops it once it's past `timeout` instead of running it later.
+    pub fn admin(&self, request: AdminRequest, timeout: Duration) -> Option<AdminResponse> {
+        let (reply, response) = mpsc::channel();
+        if !self.send(EngineRequest::Admin { request, reply, deadline: Instant::now() + timeout }) {
+            return None;
+        }
+        response.recv_timeout(timeout + ADMIN_REPLY_GRACE).ok()
+    }
+}
+
+// The matching thread's end of an `EngineHandle`.
+pub struct EngineRequests {
+    receiver: Receiver<EngineRequest>,
+    next_session_id: Arc<AtomicU64>, // shared with the handles
+}
+
+struct GatewayOrder {
+    session_id: SessionId,
+    status: OrderStatus,
+}
+
+// ----------------------------
+// MatchingService owns the engine on the matching thread and turns gateway requests
+// into engine calls, the engine's order ids are assigned here and every fill,
+// cancel and replace is reported to the session owning the order (both sides of a trade).
+// Orders are entered with their session, so a session going away cancels them.
+// Everything goes through the engine's journal (and its backups) before it's applied,
+// a request that can't be journaled is rejected and changes nothing.
+// With a market data publisher every book the service touches is published after the request.
+// Admin requests are answered on the matching thread too, in arrival order with everything else.
+// ----------------------------
+pub struct MatchingService {
+    engine: JournaledEngine,
+    requests: Receiver<EngineRequest>,
+    sinks: BTreeMap<SessionId, UpdateSink>,
+    orders: BTreeMap<OrderId, GatewayOrder>,
+    client_orders: BTreeMap<(SessionId, String), OrderId>,
+    next_order_id: OrderId,
+    market_data: Option<MarketDataPublisher>,
+    trade_history: BTreeMap<Symbol, VecDeque<Trade>>,
+    trade_history_limit: usize,
+}
+impl MatchingService {
+    // `engine` may come back from a restart: session and order ids carry on after the ones
+    // it already knows, and the sessions it still has connected are disconnected
+    // (their connections are gone) which cancels their orders like any other disconnect.
+    // Session ids handed out before this ran may clash with restored ones, after a restart
+    // wait for the service to answer (any admin request) before gateways accept connections.
+    pub fn new(mut engine: JournaledEngine, requests: EngineRequests) -> Self {
+        let sessions: Vec<Session> = engine.get_engine().get_sessions().iter().copied().collect();
+        let highest_session_id = sessions.iter().map(|session| session.get_session_id()).max().unwrap_or(0);
+        requests.next_session_id.fetch_max(highest_session_id + 1, Ordering::Relaxed);
+        for session in sessions.iter().filter(|session| session.get_state() == SessionState::Connected) {
+            let _ = engine.disconnect_session(session.get_session_id());
+        }
+
+        Self {
+            next_order_id: engine.get_highest_order_id() + 1,
+            engine,
+            requests: requests.receiver,
+            sinks: BTreeMap::new(),
+            orders: BTreeMap::new(),
+            client_orders: BTreeMap::new(),
+            market_data: None,
+            trade_history: BTreeMap::new(),
+            trade_history_limit: DEFAULT_TRADE_HISTORY,
+        }
+    }
+
+    pub fn with_market_data(mut self, market_data: MarketDataPublisher) -> Self {
+        self.market_data = Some(market_data);
+        self
+    }
+
+    // how many of the latest trades per symbol `AdminRequest::RecentTrades` returns.
+    pub fn with_trade_history(mut self, limit: usize) -> Self {
+        self.trade_history_limit = limit;
+        self
+    }
+
+    // Processes requests until Shutdown or every handle is dropped, then gives the engine back.
+    pub fn run(mut self) -> JournaledEngine {
+        while let Ok(request) = self.requests.recv() {
+            if !self.process(request) {
+                break;
+            }
+        }
+        self.engine
+    }
+
+    // returns false on Shutdown.
+    pub fn process(&mut self, request: EngineRequest) -> bool {
+        match request {
+            EngineRequest::Connect { session_id, sink } => {
+                // a session that couldn't be journaled as connected has its orders rejected by the engine
+                let _ = self.engine.connect_session(session_id);
+                self.sinks.insert(session_id, sink);
+            }
+            EngineRequest::Disconnect { session_id } => {
+                self.sinks.remove(&session_id);
+                for update in self.engine.disconnect_session(session_id).unwrap_or_default() {
+                    self.on_trades(&update.trades);
+                    self.on_events(&update.events);
+                    self.on_book_changed(&update.symbol, &[], &update.events, &update.trades);
+                }
+            }
+            EngineRequest::NewOrder { session_id, order } => self.new_order(session_id, order),
+            EngineRequest::CancelOrder { session_id, client_order_id, original_client_order_id } => {
+                self.cancel_order(session_id, client_order_id, original_client_order_id)
+            }
+            EngineRequest::ReplaceOrder { session_id, client_order_id, original_client_order_id, price, quantity } => {
+                self.replace_order(session_id, client_order_id, original_client_order_id, price, quantity)
+            }
+            EngineRequest::Admin { request, reply, deadline } => {
+                if Instant::now() <= deadline {
+                    let response = self.admin(request);
+                    let _ = reply.send(response);
+                }
+            }
+            EngineRequest::Shutdown => return false,
+        }
+        true
+    }
+
+    pub fn get_engine(&self) -> &Engine {
+        self.engine.get_engine()
+    }
+
+    pub fn get_market_data(&self) -> Option<&MarketDataPublisher> {
+        self.market_data.as_ref()
+    }
+
+    // Records the trades and publishes what the operation on `order_ids` did to the book,
+    // a failing sink shows up as a sequence gap on the feed, matching goes on.
+    fn on_book_changed(&mut self, symbol: &str, order_ids: &[OrderId], events: &[OrderEvent], trades: &Trades) {
+        if !trades.is_empty() {
+            let history = self.trade_history.entry(symbol.to_string()).or_default();
+            history.extend(trades.iter().copied());
+            while history.len() > self.trade_history_limit {
+                history.pop_front();
+            }
+        }
+        if let (Some(market_data), Some(book)) = (self.market_data.as_mut(), self.engine.get_engine().get_book(symbol)) {
+            let _ = market_data.update(symbol, book, order_ids, events, trades);
+        }
+    }
+
+    fn send(&mut self, session_id: SessionId, update: OrderUpdate) {
+        if let Some(sink) = self.sinks.get_mut(&session_id)
+            && !sink(update)
+        {
+            self.sinks.remove(&session_id);
+        }
+    }
+
+    fn new_order(&mut self, session_id: SessionId, order: NewOrder) {
+        if self.client_orders.contains_key(&(session_id, order.client_order_id.clone())) {
+            return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::DuplicateClientOrderId });
+        }
+        if self.engine.get_engine().get_book(&order.symbol).is_none() {
+            return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::UnknownSymbol });
+        }
+        if order.quantity == 0 {
+            return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::InvalidQuantity });
+        }
+        if self.engine.get_engine().get_book(&order.symbol).is_some_and(|book| book.is_halted()) {
+            return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::TradingHalted });
+        }
+
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let mut engine_order = Order::new(order_id, order.order_type, order.side, order.price, order.quantity).with_session(session_id);
+        if order.all_or_none {
+            engine_order = engine_order.with_all_or_none();
+        }
+        if let Some(account_id) = order.account_id {
+            engine_order = engine_order.with_account(account_id);
+        }
+
+        let trades = match self.engine.add_order(&order.symbol, engine_order) {
+            Ok(Some(trades)) => trades,
+            Ok(None) => return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::RejectedByBook }),
+            Err(_) => return self.send(session_id, OrderUpdate::Rejected { order, reason: OrderRejectReason::JournalUnavailable }),
+        };
+
+        let status = OrderStatus {
+            order_id,
+            client_order_id: order.client_order_id.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side,
+            order_type: order.order_type,
+            price: order.price,
+            quantity: order.quantity,
+            leaves_quantity: order.quantity,
+            cumulative_quantity: 0,
+            average_price: 0.0,
+        };
+        self.send(session_id, OrderUpdate::Accepted(status.clone()));
+        self.client_orders.insert((session_id, order.client_order_id), order_id);
+        self.orders.insert(order_id, GatewayOrder { session_id, status });
+
+        self.on_trades(&trades);
+        // whatever didn't trade and isn't resting (fill and kill, market) is gone
+        if self.orders.contains_key(&order_id) && !self.is_live(&order.symbol, order_id) {
+            self.close_order(order_id, None);
+        }
+        let events = self.drain_events(&order.symbol);
+        self.on_book_changed(&order.symbol, &[order_id], &events, &trades);
+    }
+
+    fn cancel_order(&mut self, session_id: SessionId, client_order_id: String, original_client_order_id: String) {
+        let Some(order_id) = self.find_live_order(session_id, &original_client_order_id) else {
+            return self.send(session_id, OrderUpdate::CancelRejected {
+                client_order_id,
+                original_client_order_id,
+                status: None,
+                is_replace: false,
+                reason: OrderRejectReason::UnknownOrder,
+            });
+        };
+
+        let symbol = self.orders[&order_id].status.symbol.clone();
+        let trades = match self.engine.cancel_order(&symbol, order_id) {
+            Ok(trades) => trades.unwrap_or_default(),
+            Err(_) => {
+                return self.send(session_id, OrderUpdate::CancelRejected {
+                    client_order_id,
+                    original_client_order_id,
+                    status: Some(self.orders[&order_id].status.clone()),
+                    is_replace: false,
+                    reason: OrderRejectReason::JournalUnavailable,
+                });
+            }
+        };
+        self.rename_order(session_id, order_id, &original_client_order_id, client_order_id);
+        self.close_order(order_id, Some(original_client_order_id));
+        self.on_trades(&trades);
+        let events = self.drain_events(&symbol);
+        self.on_book_changed(&symbol, &[order_id], &events, &trades);
+    }
+
+    fn replace_order(&mut self, session_id: SessionId, client_order_id: String, original_client_order_id: String, price: Price, quantity: Quantity) {
+        let reject = |status: Option<OrderStatus>, reason: OrderRejectReason| OrderUpdate::CancelRejected {
+            client_order_id: client_order_id.clone(),
+            original_client_order_id: original_client_order_id.clone(),
+            status,
+            is_replace: true,
+            reason,
+        };
+        let Some(order_id) = self.find_live_order(session_id, &original_client_order_id) else {
+            return self.send(session_id, reject(None, OrderRejectReason::UnknownOrder));
+        };
+        let status = self.orders[&order_id].status.clone();
+        if quantity <= status.cumulative_quantity {
+            return self.send(session_id, reject(Some(status), OrderRejectReason::QuantityNotAboveFilled));
+        }
+        if self.engine.get_engine().get_book(&status.symbol).is_some_and(|book| book.is_halted()) {
+            return self.send(session_id, reject(Some(status), OrderRejectReason::TradingHalted));
+        }
+
+        // the engine's order only holds what's left to fill
+        let modify = OrderModify::new(order_id, status.side, price, quantity - status.cumulative_quantity);
+        let modified = match self.engine.modify_order(&status.symbol, modify) {
+            Ok(modified) => modified,
+            Err(_) => return self.send(session_id, reject(Some(status), OrderRejectReason::JournalUnavailable)),
+        };
+        let Some(trades) = modified else {
+            self.send(session_id, reject(Some(status.clone()), OrderRejectReason::RejectedByBook));
+            // the book drops the original when the replacement is refused
+            if !self.is_live(&status.symbol, order_id) {
+                self.close_order(order_id, None);
+            }
+            let events = self.drain_events(&status.symbol);
+            self.on_book_changed(&status.symbol, &[order_id], &events, &Trades::new());
+            return;
+        };
+
+        let symbol = status.symbol;
+        self.rename_order(session_id, order_id, &original_client_order_id, client_order_id);
+        let order = self.orders.get_mut(&order_id).unwrap();
+        order.status.price = price;
+        order.status.quantity = quantity;
+        order.status.leaves_quantity = quantity - order.status.cumulative_quantity;
+        let status = order.status.clone();
+        self.send(session_id, OrderUpdate::Replaced { status, original_client_order_id });
+
+        self.on_trades(&trades);
+        if self.orders.contains_key(&order_id) && !self.is_live(&symbol, order_id) {
+            self.close_order(order_id, None);
+        }
+        let events = self.drain_events(&symbol);
+        self.on_book_changed(&symbol, &[order_id], &events, &trades);
+    }
+
+    fn admin(&mut self, request: AdminRequest) -> AdminResponse {
+        match request {
+            AdminRequest::ListInstruments => AdminResponse::Instruments(
+                self.engine
+                    .get_engine()
+                    .get_symbols()
+                    .into_iter()
+                    .filter_map(|symbol| {
+                        let book = self.engine.get_engine().get_book(&symbol)?;
+                        Some(InstrumentInfo {
+                            halted: book.is_halted(),
+                            orders: book.size(),
+                            last_trade_price: book.get_last_trade_price(),
+                            symbol,
+                        })
+                    })
+                    .collect(),
+            ),
+            AdminRequest::Depth { symbol } => match self.engine.get_engine().get_book(&symbol) {
+                Some(book) => AdminResponse::Depth(book.get_orderlevelinfos()),
+                None => AdminResponse::UnknownSymbol,
+            },
+            AdminRequest::Order { order_id } => {
+                for symbol in self.engine.get_engine().get_symbols() {
+                    if let Some(order) = self.engine.get_engine().get_book(&symbol).and_then(|book| book.get_order(order_id)) {
+                        return AdminResponse::Order { order: order.borrow().clone(), symbol };
+                    }
+                }
+                AdminResponse::UnknownOrder
+            }
+            AdminRequest::RecentTrades { symbol } => match self.engine.get_engine().get_book(&symbol) {
+                Some(_) => AdminResponse::Trades(self.trade_history.get(&symbol).map(|history| history.iter().copied().collect()).unwrap_or_default()),
+                None => AdminResponse::UnknownSymbol,
+            },
+            // unknown symbols are checked first so they aren't journaled
+            AdminRequest::Halt { symbol } | AdminRequest::Resume { symbol } | AdminRequest::MassCancel { symbol, .. }
+                if self.engine.get_engine().get_book(&symbol).is_none() =>
+            {
+                AdminResponse::UnknownSymbol
+            }
+            AdminRequest::Halt { symbol } => match self.engine.halt(&symbol) {
+                Ok(_) => AdminResponse::Halted(true),
+                Err(_) => AdminResponse::JournalUnavailable,
+            },
+            AdminRequest::Resume { symbol } => match self.engine.resume(&symbol) {
+                Ok(_) => AdminResponse::Halted(false),
+                Err(_) => AdminResponse::JournalUnavailable,
+            },
+            AdminRequest::MassCancel { symbol, filter } => {
+                let (order_ids, trades) = match self.engine.mass_cancel(&symbol, filter) {
+                    Ok(cancelled) => cancelled.unwrap_or_default(),
+                    Err(_) => return AdminResponse::JournalUnavailable,
+                };
+                self.on_trades(&trades);
+                let events = self.drain_events(&symbol);
+                self.on_book_changed(&symbol, &[], &events, &trades);
+                AdminResponse::MassCancelled(order_ids)
+            }
+        }
+    }
+
+    fn find_live_order(&self, session_id: SessionId, client_order_id: &str) -> Option<OrderId> {
+        let order_id = *self.client_orders.get(&(session_id, client_order_id.to_string()))?;
+        let symbol = &self.orders.get(&order_id)?.status.symbol;
+        self.is_live(symbol, order_id).then_some(order_id)
+    }
+
+    fn is_live(&self, symbol: &str, order_id: OrderId) -> bool {
+        self.engine.get_engine().get_book(symbol).is_some_and(|book| book.get_order(order_id).is_some())
+    }
+
+    fn rename_order(&mut self, session_id: SessionId, order_id: OrderId, original_client_order_id: &str, client_order_id: String) {
+        self.client_orders.remove(&(session_id, original_client_order_id.to_string()));
+        self.client_orders.insert((session_id, client_order_id.clone()), order_id);
+        if let Some(order) = self.orders.get_mut(&order_id) {
+            order.status.client_order_id = client_order_id;
+        }
+    }
+
+    // The order left the book without filling completely.
+    fn close_order(&mut self, order_id: OrderId, original_client_order_id: Option<String>) {
+        let Some(mut order) = self.orders.remove(&order_id) else {
+            return;
+        };
+        self.client_orders.remove(&(order.session_id, order.status.client_order_id.clone()));
+        order.status.leaves_quantity = 0;
+        self.send(order.session_id, OrderUpdate::Cancelled { status: order.status, original_client_order_id });
+    }
+
+    fn on_trades(&mut self, trades: &Trades) {
+        for trade in trades {
+            for trade_info in [trade.get_bid_trade(), trade.get_ask_trade()] {
+                let Some(order) = self.orders.get_mut(&trade_info.order_id) else {
+                    continue;
+                };
+                let status = &mut order.status;
+                let filled_value = status.average_price * status.cumulative_quantity as f64 + trade_info.price.0 as f64 * trade_info.quantity as f64;
+                status.cumulative_quantity += trade_info.quantity;
+                status.leaves_quantity = status.quantity - status.cumulative_quantity;
+                status.average_price = filled_value / status.cumulative_quantity as f64;
+                let update = OrderUpdate::Filled {
+                    status: status.clone(),
+                    trade_id: trade.get_trade_id(),
+                    price: trade_info.price,
+                    quantity: trade_info.quantity,
+                };
+                let session_id = order.session_id;
+                if status.leaves_quantity == 0 {
+                    let order = self.orders.remove(&trade_info.order_id).unwrap();
+                    self.client_orders.remove(&(session_id, order.status.client_order_id));
+                }
+                self.send(session_id, update);
+            }
+        }
+    }
+
+    // Takes the book's events and reacts to them, returns them for the feed.
+    fn drain_events(&mut self, symbol: &str) -> Vec<OrderEvent> {
+        let events = self.engine.take_events(symbol);
+        self.on_events(&events);
+        events
+    }
+
+    fn on_events(&mut self, events: &[OrderEvent]) {
+        for event in events {
+            if let OrderEvent::Cancelled { order_id, .. } = event {
+                self.close_order(*order_id, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    const SYMBOL: &str = "DEMO";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("orderbook-rs-{}-{}.journal", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn new_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine
+    }
+
+    // What's left of the engine once the service stopped, the engine itself stays on its thread.
+    struct Stopped {
+        state_hash: u64,
+        highest_order_id: OrderId,
+        resting_orders: usize,
+    }
+
+    fn start(path: &Path) -> (EngineHandle, thread::JoinHandle<Stopped>) {
+        let (handle, requests) = EngineHandle::channel();
+        let path = path.to_path_buf();
+        let matching = thread::spawn(move || {
+            let engine = MatchingService::new(JournaledEngine::open(&path, new_engine()).unwrap(), requests).run();
+            Stopped {
+                state_hash: engine.get_state_hash(),
+                highest_order_id: engine.get_highest_order_id(),
+                resting_orders: engine.get_engine().get_book(SYMBOL).unwrap().size(),
+            }
+        });
+        (handle, matching)
+    }
+
+    fn stop(handle: EngineHandle, matching: thread::JoinHandle<Stopped>) -> Stopped {
+        handle.send(EngineRequest::Shutdown);
+        matching.join().unwrap()
+    }
+
+    fn is_halted(handle: &EngineHandle) -> bool {
+        match handle.admin(AdminRequest::ListInstruments, TIMEOUT) {
+            Some(AdminResponse::Instruments(instruments)) => instruments[0].halted,
+            _ => panic!("no instruments"),
+        }
+    }
+
+    #[test]
+    fn admin_requests_are_journaled() {
+        let path = journal_path("gateway-admin");
+        let (handle, matching) = start(&path);
+        assert!(matches!(handle.admin(AdminRequest::Halt { symbol: SYMBOL.to_string() }, TIMEOUT), Some(AdminResponse::Halted(true))));
+        assert!(matches!(handle.admin(AdminRequest::Halt { symbol: "NOPE".to_string() }, TIMEOUT), Some(AdminResponse::UnknownSymbol)));
+        let stopped = stop(handle, matching);
+
+        let replayed = JournaledEngine::open(&path, new_engine()).unwrap();
+        assert!(replayed.get_engine().get_book(SYMBOL).unwrap().is_halted());
+        assert_eq!(replayed.get_state_hash(), stopped.state_hash);
+    }
+
+    #[test]
+    fn expired_admin_request_isnt_carried_out() {
+        let (handle, matching) = start(&journal_path("gateway-expired"));
+        let (reply, response) = mpsc::channel();
+        let deadline = Instant::now() - Duration::from_millis(1);
+        handle.send(EngineRequest::Admin { request: AdminRequest::Halt { symbol: SYMBOL.to_string() }, reply, deadline });
+        assert!(!is_halted(&handle));
+        assert!(response.try_recv().is_err());
+        stop(handle, matching);
+    }
+
+    #[test]
+    fn restart_carries_on_after_journaled_ids() {
+        let path = journal_path("gateway-restart");
+        let (handle, matching) = start(&path);
+        let session_id = handle.new_session_id();
+        let (sink, updates) = mpsc::channel();
+        handle.send(EngineRequest::Connect { session_id, sink: Box::new(move |update| sink.send(update).is_ok()) });
+        let order = NewOrder {
+            client_order_id: "1".to_string(),
+            symbol: SYMBOL.to_string(),
+            side: Side::Buy,
+            order_type: OrderType::GoodTillCancel,
+            price: OrderedFloat(100.0),
+            quantity: 10,
+            all_or_none: false,
+            account_id: None,
+        };
+        handle.send(EngineRequest::NewOrder { session_id, order });
+        let Ok(OrderUpdate::Accepted(status)) = updates.recv_timeout(TIMEOUT) else { panic!("order not accepted") };
+        assert_eq!(stop(handle, matching).resting_orders, 1);
+
+        // the session's connection didn't survive the restart, so its order is cancelled
+        let (handle, matching) = start(&path);
+        assert!(!is_halted(&handle));
+        assert!(handle.new_session_id() > session_id);
+        let stopped = stop(handle, matching);
+        assert_eq!(stopped.highest_order_id, status.order_id);
+        assert_eq!(stopped.resting_orders, 0);
+    }
+
+    #[test]
+    fn full_connection_queue_drops_the_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (sender, queued) = mpsc::sync_channel(2);
+        let mut sink = connection_sink(sender, stream, |update| match update {
+            OrderUpdate::Rejected { reason, .. } => Some(reason),
+            _ => None,
+        });
+        let rejected = |reason| OrderUpdate::Rejected {
+            order: NewOrder {
+                client_order_id: "1".to_string(),
+                symbol: SYMBOL.to_string(),
+                side: Side::Buy,
+                order_type: OrderType::GoodTillCancel,
+                price: OrderedFloat(100.0),
+                quantity: 10,
+                all_or_none: false,
+                account_id: None,
+            },
+            reason,
+        };
+
+        assert!(sink(rejected(OrderRejectReason::UnknownSymbol)));
+        assert!(sink(rejected(OrderRejectReason::TradingHalted)));
+        // skipped updates aren't queued
+        assert!(sink(OrderUpdate::CancelRejected {
+            client_order_id: "2".to_string(),
+            original_client_order_id: "1".to_string(),
+            status: None,
+            is_replace: false,
+            reason: OrderRejectReason::UnknownOrder,
+        }));
+        assert!(!sink(rejected(OrderRejectReason::RejectedByBook)));
+
+        // what was queued is still there, the connection is closed
+        assert_eq!(queued.try_iter().collect::<Vec<_>>(), vec![OrderRejectReason::UnknownSymbol, OrderRejectReason::TradingHalted]);
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -1,11 +1,85 @@
-use super::Trades;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{Timestamp, Trades};
 
 pub fn print_trades (trades: &Trades) {
     for trade in trades {
         let bid_trade = trade.get_bid_trade();
         let ask_trade = trade.get_ask_trade();
-        println!("Trade: {} -> {}", ask_trade.order_id, bid_trade.order_id);
-        println!("  Bid: price = {}, qunatity = {}", bid_trade.price, bid_trade.quantity);
-        println!("  Ask: price = {}, qunatity = {}", ask_trade.price, ask_trade.quantity);
+        println!("Trade {}: {} -> {} (aggressor: {:?})", trade.get_trade_id(), ask_trade.order_id, bid_trade.order_id, trade.get_aggressor_side());
+        println!("  price = {}, qunatity = {}", trade.get_price(), trade.get_quantity());
+        println!("  fees: bid = {}, ask = {}", bid_trade.fee, ask_trade.fee);
+    }
+}
+
+// nanoseconds since unix epoch
+pub fn get_timestamp() -> Timestamp {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as Timestamp).unwrap_or(0)
+}
+
+// CRC-32 (IEEE, reflected 0xEDB88320), guards journal records and snapshots against torn or corrupt writes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// FNV-1a 64 bit, unlike std's hashers its output is fixed across processes, platforms and releases.
+pub fn fnv1a64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// `timestamp` as a UTC "YYYYMMDD-HH:MM:SS.sss" (FIX UTCTimestamp).
+pub fn format_utc_timestamp(timestamp: Timestamp) -> String {
+    let milliseconds = timestamp / 1_000_000;
+    let seconds = milliseconds / 1000;
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil inverse)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        milliseconds % 1000
+    )
+}
+
+// Serde for prices that are NaN for market orders: JSON has no NaN, so it goes
+// on the wire as null and null comes back as NaN.
+#[cfg(feature = "serde")]
+pub mod nan_as_null {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use crate::{OrderedFloat, Price};
+
+    pub fn serialize<S: Serializer>(price: &Price, serializer: S) -> Result<S::Ok, S::Error> {
+        (!price.is_nan()).then_some(price.0).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Price, D::Error> {
+        Ok(OrderedFloat(Option::<f32>::deserialize(deserializer)?.unwrap_or(f32::NAN)))
     }
 }
@@ -0,0 +1,720 @@
+use super::*;
+use crate::codec::{DecodeError, Decoder, Encoder};
+use crate::helperfns::get_timestamp;
+use crate::ouch::{read_frame, write_frame};
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+// messages are packed into packets up to this size, small enough for one UDP datagram.
+pub const MAX_PACKET_SIZE: usize = 1400;
+const PACKET_HEADER_SIZE: usize = 10;
+
+// Identifies an instrument on the feed, assigned in order of first appearance
+// and announced with a StockDirectory message before anything else about it.
+pub type Locate = u16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SystemEvent {
+    StartOfMessages,
+    StartOfMarketHours,
+    EndOfMarketHours,
+    EndOfMessages,
+}
+
+// ----------------------------
+// ItchMessage: one market data message, `timestamp` is the book's clock in nanoseconds.
+// Every message but SystemEvent carries the instrument's locate, order references are engine
+// order ids (unique per instrument). Executions are reported against the resting order,
+// Trade is for executions against orders never displayed (hidden orders).
+// ----------------------------
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItchMessage {
+    SystemEvent { timestamp: Timestamp, event: SystemEvent },
+    StockDirectory { timestamp: Timestamp, locate: Locate, symbol: Symbol },
+    AddOrder { timestamp: Timestamp, locate: Locate, order_reference: OrderId, side: Side, shares: Quantity, price: Price },
+    OrderExecuted { timestamp: Timestamp, locate: Locate, order_reference: OrderId, executed_shares: Quantity, match_number: TradeId },
+    OrderCancel { timestamp: Timestamp, locate: Locate, order_reference: OrderId, cancelled_shares: Quantity },
+    OrderDelete { timestamp: Timestamp, locate: Locate, order_reference: OrderId },
+    OrderReplace { timestamp: Timestamp, locate: Locate, original_order_reference: OrderId, new_order_reference: OrderId, shares: Quantity, price: Price },
+    Trade { timestamp: Timestamp, locate: Locate, side: Side, shares: Quantity, price: Price, match_number: TradeId },
+}
+impl ItchMessage {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            ItchMessage::SystemEvent { timestamp, event } => {
+                encoder.put_u8(b'S');
+                encoder.put_u64(*timestamp);
+                encoder.put_u8(match event {
+                    SystemEvent::StartOfMessages => b'O',
+                    SystemEvent::StartOfMarketHours => b'Q',
+                    SystemEvent::EndOfMarketHours => b'M',
+                    SystemEvent::EndOfMessages => b'C',
+                });
+            }
+            ItchMessage::StockDirectory { timestamp, locate, symbol } => {
+                encoder.put_u8(b'R');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_str(symbol);
+            }
+            ItchMessage::AddOrder { timestamp, locate, order_reference, side, shares, price } => {
+                encoder.put_u8(b'A');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_u64(*order_reference);
+                encoder.put_side(*side);
+                encoder.put_u32(*shares);
+                encoder.put_price(*price);
+            }
+            ItchMessage::OrderExecuted { timestamp, locate, order_reference, executed_shares, match_number } => {
+                encoder.put_u8(b'E');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_u64(*order_reference);
+                encoder.put_u32(*executed_shares);
+                encoder.put_u64(*match_number);
+            }
+            ItchMessage::OrderCancel { timestamp, locate, order_reference, cancelled_shares } => {
+                encoder.put_u8(b'X');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_u64(*order_reference);
+                encoder.put_u32(*cancelled_shares);
+            }
+            ItchMessage::OrderDelete { timestamp, locate, order_reference } => {
+                encoder.put_u8(b'D');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_u64(*order_reference);
+            }
+            ItchMessage::OrderReplace { timestamp, locate, original_order_reference, new_order_reference, shares, price } => {
+                encoder.put_u8(b'U');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_u64(*original_order_reference);
+                encoder.put_u64(*new_order_reference);
+                encoder.put_u32(*shares);
+                encoder.put_price(*price);
+            }
+            ItchMessage::Trade { timestamp, locate, side, shares, price, match_number } => {
+                encoder.put_u8(b'P');
+                encoder.put_u64(*timestamp);
+                encoder.put_u16(*locate);
+                encoder.put_side(*side);
+                encoder.put_u32(*shares);
+                encoder.put_price(*price);
+                encoder.put_u64(*match_number);
+            }
+        }
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.get_u8()? {
+            b'S' => Ok(ItchMessage::SystemEvent {
+                timestamp: decoder.get_u64()?,
+                event: match decoder.get_u8()? {
+                    b'O' => SystemEvent::StartOfMessages,
+                    b'Q' => SystemEvent::StartOfMarketHours,
+                    b'M' => SystemEvent::EndOfMarketHours,
+                    b'C' => SystemEvent::EndOfMessages,
+                    tag => return Err(DecodeError::InvalidTag(tag)),
+                },
+            }),
+            b'R' => Ok(ItchMessage::StockDirectory { timestamp: decoder.get_u64()?, locate: decoder.get_u16()?, symbol: decoder.get_string()? }),
+            b'A' => Ok(ItchMessage::AddOrder {
+                timestamp: decoder.get_u64()?,
+                locate: decoder.get_u16()?,
+                order_reference: decoder.get_u64()?,
+                side: decoder.get_side()?,
+                shares: decoder.get_u32()?,
+                price: decoder.get_price()?,
+            }),
+            b'E' => Ok(ItchMessage::OrderExecuted {
+                timestamp: decoder.get_u64()?,
+                locate: decoder.get_u16()?,
+                order_reference: decoder.get_u64()?,
+                executed_shares: decoder.get_u32()?,
+                match_number: decoder.get_u64()?,
+            }),
+            b'X' => Ok(ItchMessage::OrderCancel {
+                timestamp: decoder.get_u64()?,
+                locate: decoder.get_u16()?,
+                order_reference: decoder.get_u64()?,
+                cancelled_shares: decoder.get_u32()?,
+            }),
+            b'D' => Ok(ItchMessage::OrderDelete { timestamp: decoder.get_u64()?, locate: decoder.get_u16()?, order_reference: decoder.get_u64()? }),
+            b'U' => Ok(ItchMessage::OrderReplace {
+                timestamp: decoder.get_u64()?,
+                locate: decoder.get_u16()?,
+                original_order_reference: decoder.get_u64()?,
+                new_order_reference: decoder.get_u64()?,
+                shares: decoder.get_u32()?,
+                price: decoder.get_price()?,
+            }),
+            b'P' => Ok(ItchMessage::Trade {
+                timestamp: decoder.get_u64()?,
+                locate: decoder.get_u16()?,
+                side: decoder.get_side()?,
+                shares: decoder.get_u32()?,
+                price: decoder.get_price()?,
+                match_number: decoder.get_u64()?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+// ----------------------------
+// Packets: the sequence number of the first message (u64), the message count (u16),
+// then every message as a u16 length and its encoding. Sequence numbers start at 1
+// and count messages, so a receiver spots a lost packet from the next one's sequence.
+// ----------------------------
+pub fn decode_packet(packet: &[u8]) -> Result<(u64, Vec<ItchMessage>), DecodeError> {
+    let mut decoder = Decoder::new(packet);
+    let sequence = decoder.get_u64()?;
+    let count = decoder.get_u16()?;
+    let mut messages = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let length = decoder.get_u16()? as usize;
+        messages.push(ItchMessage::decode(&mut Decoder::new(decoder.get_bytes(length)?))?);
+    }
+    Ok((sequence, messages))
+}
+
+// Where the publisher's packets go.
+pub trait PacketSink {
+    fn send_packet(&mut self, packet: &[u8]) -> io::Result<()>;
+}
+
+// Appends packets to a file, framed like order entry messages (u16 length prefix).
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+impl FileSink {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { writer: BufWriter::new(File::create(path)?) })
+    }
+}
+impl PacketSink for FileSink {
+    fn send_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        write_frame(&mut self.writer, packet)?;
+        self.writer.flush()
+    }
+}
+
+// Hands packets to another thread, e.g. a server rebuilding books from the feed.
+pub struct ChannelSink {
+    sender: Sender<Vec<u8>>,
+}
+impl ChannelSink {
+    pub fn channel() -> (Self, Receiver<Vec<u8>>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { sender }, receiver)
+    }
+}
+impl PacketSink for ChannelSink {
+    fn send_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.sender.send(packet.to_vec()).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "packet receiver is gone"))
+    }
+}
+
+// One datagram per packet to a multicast group, sent from `interface`
+// (127.0.0.1 keeps the feed on the loopback interface).
+pub struct MulticastSink {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+}
+impl MulticastSink {
+    pub fn new(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Self> {
+        let socket = UdpSocket::bind((interface, 0))?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(1)?;
+        Ok(Self { socket, group })
+    }
+}
+impl PacketSink for MulticastSink {
+    fn send_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.socket.send_to(packet, self.group).map(|_| ())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct VisibleOrder {
+    side: Side,
+    price: Price,
+    shares: Quantity,
+}
+
+// ----------------------------
+// MarketDataPublisher turns book activity into the feed: after every operation on a book
+// `update` publishes the operation's trades as executions and compares the orders it touched
+// (the ones it was about, traded or got an event for) with what the feed last showed them as.
+// Its cost grows with what the operation did, not with the book.
+// ----------------------------
+pub struct MarketDataPublisher {
+    sinks: Vec<Box<dyn PacketSink + Send>>,
+    locates: BTreeMap<Symbol, Locate>,
+    views: BTreeMap<Locate, BTreeMap<OrderId, VisibleOrder>>,
+    next_sequence: u64,
+}
+impl Default for MarketDataPublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MarketDataPublisher {
+    pub fn new() -> Self {
+        Self { sinks: Vec::new(), locates: BTreeMap::new(), views: BTreeMap::new(), next_sequence: 1 }
+    }
+
+    pub fn with_sink(mut self, sink: impl PacketSink + Send + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn get_next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn system_event(&mut self, event: SystemEvent) -> io::Result<()> {
+        self.publish(vec![ItchMessage::SystemEvent { timestamp: get_timestamp(), event }])
+    }
+
+    // Publishes what the operation(s) since the last update did to `book` (the book of `symbol`):
+    // `order_ids` are the orders they were about (added, cancelled, modified), `events` and `trades`
+    // are what the book reported for them. The first update of a symbol publishes its whole book
+    // (e.g. orders restored from a journal), it fails without publishing once every locate is taken.
+    pub fn update(&mut self, symbol: &str, book: &OrderBook, order_ids: &[OrderId], events: &[OrderEvent], trades: &Trades) -> io::Result<()> {
+        let timestamp = book.get_clock().now();
+        let mut messages = Vec::new();
+        let is_new_symbol = !self.locates.contains_key(symbol);
+        let locate = match self.locates.get(symbol) {
+            Some(locate) => *locate,
+            None => {
+                let locate = Locate::try_from(self.locates.len() + 1)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("no locate left for {}", symbol)))?;
+                self.locates.insert(symbol.to_string(), locate);
+                messages.push(ItchMessage::StockDirectory { timestamp, locate, symbol: symbol.to_string() });
+                locate
+            }
+        };
+        let view = self.views.entry(locate).or_default();
+
+        let mut touched: BTreeSet<OrderId> = order_ids.iter().copied().collect();
+        touched.extend(events.iter().map(|event| match event {
+            OrderEvent::Modified { order_id, .. } | OrderEvent::Cancelled { order_id, .. } | OrderEvent::Activated { order_id, .. } => *order_id,
+        }));
+        for trade in trades {
+            touched.insert(trade.get_aggressor_order_id());
+            touched.insert(trade.get_passive_order_id());
+            // a displayed order only aggresses after it was modified across the spread,
+            // it leaves the feed and comes back as a new order if anything of it rests
+            if view.remove(&trade.get_aggressor_order_id()).is_some() {
+                messages.push(ItchMessage::OrderDelete { timestamp, locate, order_reference: trade.get_aggressor_order_id() });
+            }
+            let order_reference = trade.get_passive_order_id();
+            let Some(order) = view.get_mut(&order_reference) else {
+                let side = if trade.get_aggressor_side() == Side::Buy { Side::Sell } else { Side::Buy };
+                messages.push(ItchMessage::Trade { timestamp, locate, side, shares: trade.get_quantity(), price: trade.get_price(), match_number: trade.get_trade_id() });
+                continue;
+            };
+            // re-priced (pegged) before it traded
+            if order.price != trade.get_price() {
+                order.price = trade.get_price();
+                messages.push(ItchMessage::OrderReplace {
+                    timestamp,
+                    locate,
+                    original_order_reference: order_reference,
+                    new_order_reference: order_reference,
+                    shares: order.shares,
+                    price: order.price,
+                });
+            }
+            messages.push(ItchMessage::OrderExecuted { timestamp, locate, order_reference, executed_shares: trade.get_quantity(), match_number: trade.get_trade_id() });
+            order.shares = order.shares.saturating_sub(trade.get_quantity());
+            if order.shares == 0 {
+                view.remove(&order_reference);
+            }
+        }
+
+        if is_new_symbol {
+            touched.extend(book.iter_resting_orders().map(|order| order.borrow().get_order_id()));
+        }
+
+        for order_reference in touched {
+            let current = book.get_resting_order(order_reference).and_then(|order| {
+                let order = order.borrow();
+                (!order.is_hidden()).then(|| VisibleOrder { side: order.get_side(), price: order.get_price(), shares: order.get_remaining_quantity() })
+            });
+            let shown = view.get(&order_reference).copied();
+            match (shown, current) {
+                (None, None) => continue,
+                (Some(shown), Some(order)) if order == shown => continue,
+                (Some(shown), Some(order)) if order.side == shown.side && order.price == shown.price && order.shares < shown.shares => {
+                    messages.push(ItchMessage::OrderCancel { timestamp, locate, order_reference, cancelled_shares: shown.shares - order.shares });
+                }
+                (Some(shown), Some(order)) if order.side == shown.side => messages.push(ItchMessage::OrderReplace {
+                    timestamp,
+                    locate,
+                    original_order_reference: order_reference,
+                    new_order_reference: order_reference,
+                    shares: order.shares,
+                    price: order.price,
+                }),
+                // gone, or amended to the other side (added back right after)
+                (Some(_), _) => {
+                    messages.push(ItchMessage::OrderDelete { timestamp, locate, order_reference });
+                    if let Some(order) = current {
+                        messages.push(ItchMessage::AddOrder { timestamp, locate, order_reference, side: order.side, shares: order.shares, price: order.price });
+                    }
+                }
+                (None, Some(order)) => {
+                    messages.push(ItchMessage::AddOrder { timestamp, locate, order_reference, side: order.side, shares: order.shares, price: order.price });
+                }
+            }
+            match current {
+                Some(order) => view.insert(order_reference, order),
+                None => view.remove(&order_reference),
+            };
+        }
+        self.publish(messages)
+    }
+
+    // Packs `messages` into packets and hands them to every sink. Sequence numbers are used up
+    // even when a sink fails, receivers see the loss as a gap.
+    fn publish(&mut self, messages: Vec<ItchMessage>) -> io::Result<()> {
+        let mut packets = Vec::new();
+        let mut packet = Encoder::new();
+        let mut count: u16 = 0;
+        for message in messages {
+            let mut encoder = Encoder::new();
+            message.encode(&mut encoder);
+            if count > 0 && PACKET_HEADER_SIZE + packet.len() + 2 + encoder.len() > MAX_PACKET_SIZE {
+                packets.push(Self::seal_packet(self.next_sequence, count, packet));
+                self.next_sequence += count as u64;
+                packet = Encoder::new();
+                count = 0;
+            }
+            packet.put_u16(encoder.len() as u16);
+            packet.put_bytes(encoder.get_bytes());
+            count += 1;
+        }
+        if count > 0 {
+            packets.push(Self::seal_packet(self.next_sequence, count, packet));
+            self.next_sequence += count as u64;
+        }
+
+        let mut result = Ok(());
+        for packet in &packets {
+            for sink in &mut self.sinks {
+                if let Err(error) = sink.send_packet(packet) {
+                    result = Err(error);
+                }
+            }
+        }
+        result
+    }
+
+    fn seal_packet(sequence: u64, count: u16, messages: Encoder) -> Vec<u8> {
+        let mut packet = Encoder::new();
+        packet.put_u64(sequence);
+        packet.put_u16(count);
+        packet.put_bytes(messages.get_bytes());
+        packet.into_bytes()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FeedError {
+    Decode(DecodeError),
+    Gap { expected: u64, received: u64 }, // packets were lost, the books can't be trusted anymore.
+    UnknownLocate(Locate),
+    UnknownOrder(OrderId),
+}
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeedError::Decode(error) => write!(f, "malformed packet: {}", error),
+            FeedError::Gap { expected, received } => write!(f, "expected sequence {} but received {}", expected, received),
+            FeedError::UnknownLocate(locate) => write!(f, "unknown locate {}", locate),
+            FeedError::UnknownOrder(order_id) => write!(f, "unknown order {}", order_id),
+        }
+    }
+}
+impl std::error::Error for FeedError {}
+impl From<DecodeError> for FeedError {
+    fn from(error: DecodeError) -> Self {
+        FeedError::Decode(error)
+    }
+}
+
+#[derive(Default)]
+struct FeedInstrument {
+    orders: BTreeMap<OrderId, VisibleOrder>,
+    bids: BTreeMap<Reverse<Price>, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+}
+impl FeedInstrument {
+    fn add(&mut self, order_reference: OrderId, order: VisibleOrder) {
+        self.change_level(order.side, order.price, order.shares as i64);
+        self.orders.insert(order_reference, order);
+    }
+
+    // Takes `shares` off an order, removes it when nothing is left (or `shares` is None).
+    fn reduce(&mut self, order_reference: OrderId, shares: Option<Quantity>) -> Result<(), FeedError> {
+        let order = self.orders.get_mut(&order_reference).ok_or(FeedError::UnknownOrder(order_reference))?;
+        let shares = shares.unwrap_or(order.shares).min(order.shares);
+        order.shares -= shares;
+        let (side, price, left) = (order.side, order.price, order.shares);
+        if left == 0 {
+            self.orders.remove(&order_reference);
+        }
+        self.change_level(side, price, -(shares as i64));
+        Ok(())
+    }
+
+    fn change_level(&mut self, side: Side, price: Price, delta: i64) {
+        fn apply<K: Ord + Copy>(levels: &mut BTreeMap<K, Quantity>, key: K, delta: i64) {
+            let quantity = (levels.get(&key).copied().unwrap_or(0) as i64 + delta) as Quantity;
+            if quantity == 0 {
+                levels.remove(&key);
+            } else {
+                levels.insert(key, quantity);
+            }
+        }
+        match side {
+            Side::Buy => apply(&mut self.bids, Reverse(price), delta),
+            Side::Sell => apply(&mut self.asks, price, delta),
+        }
+    }
+}
+
+// ----------------------------
+// FeedBook rebuilds displayed depth per instrument from the feed, from a file
+// or packet by packet off the network. Packets must arrive in sequence,
+// a packet already seen is skipped, a missing one is a Gap error.
+// ----------------------------
+pub struct FeedBook {
+    next_sequence: u64,
+    symbols: BTreeMap<Locate, Symbol>,
+    books: BTreeMap<Locate, FeedInstrument>,
+    last_event: Option<SystemEvent>,
+}
+impl Default for FeedBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl FeedBook {
+    pub fn new() -> Self {
+        Self { next_sequence: 1, symbols: BTreeMap::new(), books: BTreeMap::new(), last_event: None }
+    }
+
+    pub fn get_next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn get_last_event(&self) -> Option<SystemEvent> {
+        self.last_event
+    }
+
+    pub fn get_symbols(&self) -> Vec<Symbol> {
+        self.symbols.values().cloned().collect()
+    }
+
+    pub fn get_symbol(&self, locate: Locate) -> Option<&Symbol> {
+        self.symbols.get(&locate)
+    }
+
+    // Side, price and displayed shares of an order on the book.
+    pub fn get_order(&self, locate: Locate, order_reference: OrderId) -> Option<(Side, Price, Quantity)> {
+        let order = self.books.get(&locate)?.orders.get(&order_reference)?;
+        Some((order.side, order.price, order.shares))
+    }
+
+    // Displayed depth, comparable with `OrderBook::get_orderlevelinfos` of the published book.
+    pub fn get_orderlevelinfos(&self, symbol: &str) -> Option<OrderbookLevelInfos> {
+        let (locate, _) = self.symbols.iter().find(|(_, known)| known.as_str() == symbol)?;
+        let book = self.books.get(locate)?;
+        Some(OrderbookLevelInfos::new(
+            book.bids.iter().map(|(Reverse(price), quantity)| LevelInfo::new(*price, *quantity)).collect(),
+            book.asks.iter().map(|(price, quantity)| LevelInfo::new(*price, *quantity)).collect(),
+        ))
+    }
+
+    // Returns the number of messages applied.
+    pub fn apply_packet(&mut self, packet: &[u8]) -> Result<usize, FeedError> {
+        let (sequence, messages) = decode_packet(packet)?;
+        if sequence > self.next_sequence {
+            return Err(FeedError::Gap { expected: self.next_sequence, received: sequence });
+        }
+        let skip = (self.next_sequence - sequence) as usize;
+        let mut applied = 0;
+        for message in messages.iter().skip(skip) {
+            self.apply(message)?;
+            self.next_sequence += 1;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    pub fn apply_file(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut applied = 0;
+        loop {
+            let packet = match read_frame(&mut reader) {
+                Ok(packet) => packet,
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(applied),
+                Err(error) => return Err(error),
+            };
+            applied += self.apply_packet(&packet).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        }
+    }
+
+    pub fn apply(&mut self, message: &ItchMessage) -> Result<(), FeedError> {
+        match message {
+            ItchMessage::SystemEvent { event, .. } => self.last_event = Some(*event),
+            ItchMessage::StockDirectory { locate, symbol, .. } => {
+                self.symbols.insert(*locate, symbol.clone());
+                self.books.entry(*locate).or_default();
+            }
+            ItchMessage::AddOrder { locate, order_reference, side, shares, price, .. } => {
+                self.get_book(*locate)?.add(*order_reference, VisibleOrder { side: *side, price: *price, shares: *shares });
+            }
+            ItchMessage::OrderExecuted { locate, order_reference, executed_shares, .. } => {
+                self.get_book(*locate)?.reduce(*order_reference, Some(*executed_shares))?;
+            }
+            ItchMessage::OrderCancel { locate, order_reference, cancelled_shares, .. } => {
+                self.get_book(*locate)?.reduce(*order_reference, Some(*cancelled_shares))?;
+            }
+            ItchMessage::OrderDelete { locate, order_reference, .. } => self.get_book(*locate)?.reduce(*order_reference, None)?,
+            ItchMessage::OrderReplace { locate, original_order_reference, new_order_reference, shares, price, .. } => {
+                let book = self.get_book(*locate)?;
+                let side = book.orders.get(original_order_reference).ok_or(FeedError::UnknownOrder(*original_order_reference))?.side;
+                book.reduce(*original_order_reference, None)?;
+                book.add(*new_order_reference, VisibleOrder { side, price: *price, shares: *shares });
+            }
+            ItchMessage::Trade { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn get_book(&mut self, locate: Locate) -> Result<&mut FeedInstrument, FeedError> {
+        self.books.get_mut(&locate).ok_or(FeedError::UnknownLocate(locate))
+    }
+}
+
+// Receives feed packets from a multicast group joined on `interface`.
+pub struct MulticastReceiver {
+    socket: UdpSocket,
+}
+impl MulticastReceiver {
+    pub fn join(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))?;
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        Ok(Self { socket })
+    }
+
+    // Next packet, None when nothing came within `timeout` (a zero timeout doesn't wait).
+    pub fn receive(&self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        self.socket.set_nonblocking(timeout.is_zero())?;
+        if !timeout.is_zero() {
+            self.socket.set_read_timeout(Some(timeout))?;
+        }
+        let mut buffer = vec![0; 64 * 1024];
+        match self.socket.recv(&mut buffer) {
+            Ok(length) => {
+                buffer.truncate(length);
+                Ok(Some(buffer))
+            }
+            Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const SYMBOL: &str = "DEMO";
+
+    fn order(order_id: OrderId, side: Side, price: f32, quantity: Quantity) -> OrderPointer {
+        Rc::new(RefCell::new(Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity)))
+    }
+
+    // Publishes one operation and checks a book rebuilt from the packets shows what the book displays.
+    fn publish(publisher: &mut MarketDataPublisher, packets: &Receiver<Vec<u8>>, feed: &mut FeedBook, book: &mut OrderBook, order_ids: &[OrderId], trades: Trades) {
+        let events = book.take_events();
+        publisher.update(SYMBOL, book, order_ids, &events, &trades).unwrap();
+        for packet in packets.try_iter() {
+            feed.apply_packet(&packet).unwrap();
+        }
+        assert_eq!(feed.get_orderlevelinfos(SYMBOL), Some(book.get_orderlevelinfos()));
+    }
+
+    #[test]
+    fn feed_rebuilds_the_published_book() {
+        let path = std::env::temp_dir().join(format!("orderbook-rs-itch-{}.feed", std::process::id()));
+        let (sink, packets) = ChannelSink::channel();
+        let mut publisher = MarketDataPublisher::new().with_sink(FileSink::create(&path).unwrap()).with_sink(sink);
+        let mut feed = FeedBook::new();
+        let mut book = OrderBook::new();
+
+        // resting before the first update, published with it
+        book.add_order(order(1, Side::Sell, 101.0, 10)).unwrap();
+        let trades = book.add_order(order(2, Side::Sell, 102.0, 5)).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[2], trades);
+        let trades = book.add_order(order(3, Side::Buy, 99.0, 7)).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[3], trades);
+        book.add_order(Rc::new(RefCell::new(Order::new(4, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(99.0), 3).with_hidden()))).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[4], Trades::new());
+        let pegged = Order::new(5, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(98.0), 2).with_peg(Peg::new(PegType::Primary, OrderedFloat(0.0)));
+        let trades = book.add_order(Rc::new(RefCell::new(pegged))).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[5], trades);
+        let trades = book.add_stop_order(order(6, Side::Buy, 102.0, 4), Trigger::Stop(OrderedFloat(101.0))).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[6], trades);
+        let trades = book.add_stop_order(order(11, Side::Buy, 100.0, 3), Trigger::Stop(OrderedFloat(101.0))).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[11], trades);
+        let (_, trades) = book.add_oco_orders(OrderLeg::Limit(order(7, Side::Sell, 104.0, 3)), OrderLeg::Limit(order(8, Side::Sell, 106.0, 3))).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[7, 8], trades);
+
+        // takes order 1, the print triggers the stops: one takes 4 of order 2, the other rests
+        // without trading. The rest rests at 101 and the pegged bid follows it
+        let trades = book.add_order(order(9, Side::Buy, 101.0, 12)).unwrap();
+        assert_eq!(trades.len(), 2);
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[9], trades);
+        assert_eq!(book.get_order(5).unwrap().borrow().get_price(), OrderedFloat(101.0));
+        assert!(book.get_resting_order(11).is_some());
+
+        // to the other side
+        let trades = book.modify_order(OrderModify::new(3, Side::Sell, OrderedFloat(103.0), 2)).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[3], trades);
+        let trades = book.cancel_order(2);
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[2], trades);
+
+        // takes order 3 and part of order 7, its OCO sibling goes
+        let trades = book.add_order(order(10, Side::Buy, 104.0, 3)).unwrap();
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[10], trades);
+        assert!(book.get_order(8).is_none());
+
+        let (order_ids, trades) = book.mass_cancel(&MassCancelFilter::new().with_side(Side::Buy));
+        assert!(!order_ids.is_empty());
+        publish(&mut publisher, &packets, &mut feed, &mut book, &[], trades);
+
+        drop(publisher);
+        let mut file_feed = FeedBook::new();
+        file_feed.apply_file(&path).unwrap();
+        assert_eq!(file_feed.get_next_sequence(), feed.get_next_sequence());
+        assert_eq!(file_feed.get_orderlevelinfos(SYMBOL), Some(book.get_orderlevelinfos()));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,816 @@
+use super::*;
+use crate::codec::{DecodeError, Decoder, Encoder};
+use crate::helperfns::crc32;
+use crate::snapshot::restore_at;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+// An OCO or bracket leg as journaled, a trigger makes it a stop leg.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandLeg {
+    pub order: Order,
+    pub trigger: Option<Trigger>,
+}
+impl CommandLeg {
+    pub fn from_leg(leg: &OrderLeg) -> Self {
+        match leg {
+            OrderLeg::Limit(order) => Self { order: order.borrow().clone(), trigger: None },
+            OrderLeg::Stop(order, trigger) => Self { order: order.borrow().clone(), trigger: Some(*trigger) },
+        }
+    }
+
+    pub fn to_leg(&self) -> OrderLeg {
+        let order = Rc::new(RefCell::new(self.order.clone()));
+        match self.trigger {
+            Some(trigger) => OrderLeg::Stop(order, trigger),
+            None => OrderLeg::Limit(order),
+        }
+    }
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_order(&self.order);
+        encoder.put_option(self.trigger, Encoder::put_trigger);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Self { order: decoder.get_order()?, trigger: decoder.get_option(Decoder::get_trigger)? })
+    }
+}
+
+// ----------------------------
+// Command is everything that changes the engine's state,
+// applying the same commands in the same order to engines with the same
+// instruments reproduces the same books, order ids and trade ids.
+// ----------------------------
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    AddOrder { symbol: Symbol, order: Order },
+    AddStopOrder { symbol: Symbol, order: Order, trigger: Trigger },
+    CancelOrder { symbol: Symbol, order_id: OrderId },
+    ModifyOrder { symbol: Symbol, order_id: OrderId, side: Side, price: Price, quantity: Quantity },
+    ConnectSession { session_id: SessionId },
+    DisconnectSession { session_id: SessionId },
+    AddOcoOrders { symbol: Symbol, first: CommandLeg, second: CommandLeg },
+    AddBracketOrder { symbol: Symbol, entry: Order, take_profit: CommandLeg, stop_loss: CommandLeg },
+    MassCancel { symbol: Symbol, filter: MassCancelFilter },
+    DisableAccount { symbol: Symbol, account_id: AccountId },
+    EnableAccount { symbol: Symbol, account_id: AccountId },
+    Halt { symbol: Symbol },
+    Resume { symbol: Symbol },
+    SetFeeModel { symbol: Symbol, fee_model: FeeModel },
+    ResetAccumulatedFees { symbol: Symbol, account_id: Option<AccountId> }, // every account when None
+}
+impl Command {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Command::AddOrder { symbol, order } => {
+                encoder.put_u8(0);
+                encoder.put_str(symbol);
+                encoder.put_order(order);
+            }
+            Command::AddStopOrder { symbol, order, trigger } => {
+                encoder.put_u8(1);
+                encoder.put_str(symbol);
+                encoder.put_order(order);
+                encoder.put_trigger(*trigger);
+            }
+            Command::CancelOrder { symbol, order_id } => {
+                encoder.put_u8(2);
+                encoder.put_str(symbol);
+                encoder.put_u64(*order_id);
+            }
+            Command::ModifyOrder { symbol, order_id, side, price, quantity } => {
+                encoder.put_u8(3);
+                encoder.put_str(symbol);
+                encoder.put_u64(*order_id);
+                encoder.put_side(*side);
+                encoder.put_price(*price);
+                encoder.put_u32(*quantity);
+            }
+            Command::ConnectSession { session_id } => {
+                encoder.put_u8(4);
+                encoder.put_u64(*session_id);
+            }
+            Command::DisconnectSession { session_id } => {
+                encoder.put_u8(5);
+                encoder.put_u64(*session_id);
+            }
+            Command::AddOcoOrders { symbol, first, second } => {
+                encoder.put_u8(6);
+                encoder.put_str(symbol);
+                first.encode(encoder);
+                second.encode(encoder);
+            }
+            Command::AddBracketOrder { symbol, entry, take_profit, stop_loss } => {
+                encoder.put_u8(7);
+                encoder.put_str(symbol);
+                encoder.put_order(entry);
+                take_profit.encode(encoder);
+                stop_loss.encode(encoder);
+            }
+            Command::MassCancel { symbol, filter } => {
+                encoder.put_u8(8);
+                encoder.put_str(symbol);
+                encoder.put_mass_cancel_filter(filter);
+            }
+            Command::DisableAccount { symbol, account_id } => {
+                encoder.put_u8(9);
+                encoder.put_str(symbol);
+                encoder.put_u64(*account_id);
+            }
+            Command::EnableAccount { symbol, account_id } => {
+                encoder.put_u8(10);
+                encoder.put_str(symbol);
+                encoder.put_u64(*account_id);
+            }
+            Command::Halt { symbol } => {
+                encoder.put_u8(11);
+                encoder.put_str(symbol);
+            }
+            Command::Resume { symbol } => {
+                encoder.put_u8(12);
+                encoder.put_str(symbol);
+            }
+            Command::SetFeeModel { symbol, fee_model } => {
+                encoder.put_u8(13);
+                encoder.put_str(symbol);
+                encoder.put_fee_model(fee_model);
+            }
+            Command::ResetAccumulatedFees { symbol, account_id } => {
+                encoder.put_u8(14);
+                encoder.put_str(symbol);
+                encoder.put_option(*account_id, Encoder::put_u64);
+            }
+        }
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.get_u8()? {
+            0 => Ok(Command::AddOrder { symbol: decoder.get_string()?, order: decoder.get_order()? }),
+            1 => Ok(Command::AddStopOrder {
+                symbol: decoder.get_string()?,
+                order: decoder.get_order()?,
+                trigger: decoder.get_trigger()?,
+            }),
+            2 => Ok(Command::CancelOrder { symbol: decoder.get_string()?, order_id: decoder.get_u64()? }),
+            3 => Ok(Command::ModifyOrder {
+                symbol: decoder.get_string()?,
+                order_id: decoder.get_u64()?,
+                side: decoder.get_side()?,
+                price: decoder.get_price()?,
+                quantity: decoder.get_u32()?,
+            }),
+            4 => Ok(Command::ConnectSession { session_id: decoder.get_u64()? }),
+            5 => Ok(Command::DisconnectSession { session_id: decoder.get_u64()? }),
+            6 => Ok(Command::AddOcoOrders {
+                symbol: decoder.get_string()?,
+                first: CommandLeg::decode(decoder)?,
+                second: CommandLeg::decode(decoder)?,
+            }),
+            7 => Ok(Command::AddBracketOrder {
+                symbol: decoder.get_string()?,
+                entry: decoder.get_order()?,
+                take_profit: CommandLeg::decode(decoder)?,
+                stop_loss: CommandLeg::decode(decoder)?,
+            }),
+            8 => Ok(Command::MassCancel { symbol: decoder.get_string()?, filter: decoder.get_mass_cancel_filter()? }),
+            9 => Ok(Command::DisableAccount { symbol: decoder.get_string()?, account_id: decoder.get_u64()? }),
+            10 => Ok(Command::EnableAccount { symbol: decoder.get_string()?, account_id: decoder.get_u64()? }),
+            11 => Ok(Command::Halt { symbol: decoder.get_string()? }),
+            12 => Ok(Command::Resume { symbol: decoder.get_string()? }),
+            13 => Ok(Command::SetFeeModel { symbol: decoder.get_string()?, fee_model: decoder.get_fee_model()? }),
+            14 => Ok(Command::ResetAccumulatedFees { symbol: decoder.get_string()?, account_id: decoder.get_option(Decoder::get_u64)? }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    // Ids of the orders the command enters, none for every other command.
+    pub fn get_order_ids(&self) -> Vec<OrderId> {
+        match self {
+            Command::AddOrder { order, .. } | Command::AddStopOrder { order, .. } => vec![order.get_order_id()],
+            Command::AddOcoOrders { first, second, .. } => vec![first.order.get_order_id(), second.order.get_order_id()],
+            Command::AddBracketOrder { entry, take_profit, stop_loss, .. } => {
+                vec![entry.get_order_id(), take_profit.order.get_order_id(), stop_loss.order.get_order_id()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Runs the command against `engine`, returns the trades it produced.
+    pub fn apply(&self, engine: &mut Engine) -> Trades {
+        match self {
+            Command::AddOrder { symbol, order } => {
+                engine.add_order(symbol, Rc::new(RefCell::new(order.clone()))).unwrap_or_default()
+            }
+            Command::AddStopOrder { symbol, order, trigger } => {
+                engine.add_stop_order(symbol, Rc::new(RefCell::new(order.clone())), *trigger).unwrap_or_default()
+            }
+            Command::CancelOrder { symbol, order_id } => engine.cancel_order(symbol, *order_id).unwrap_or_default(),
+            Command::ModifyOrder { symbol, order_id, side, price, quantity } => {
+                engine.modify_order(symbol, OrderModify::new(*order_id, *side, *price, *quantity)).unwrap_or_default()
+            }
+            Command::ConnectSession { session_id } => {
+                engine.connect_session(*session_id);
+                Trades::new()
+            }
+            Command::DisconnectSession { session_id } => {
+                engine.disconnect_session(*session_id).into_iter().flat_map(|update| update.trades).collect()
+            }
+            Command::AddOcoOrders { symbol, first, second } => {
+                engine.add_oco_orders(symbol, first.to_leg(), second.to_leg()).map(|(_, trades)| trades).unwrap_or_default()
+            }
+            Command::AddBracketOrder { symbol, entry, take_profit, stop_loss } => engine
+                .add_bracket_order(symbol, Rc::new(RefCell::new(entry.clone())), take_profit.to_leg(), stop_loss.to_leg())
+                .map(|(_, trades)| trades)
+                .unwrap_or_default(),
+            Command::MassCancel { symbol, filter } => engine.mass_cancel(symbol, filter).map(|(_, trades)| trades).unwrap_or_default(),
+            Command::DisableAccount { symbol, account_id } => {
+                engine.disable_account(symbol, *account_id).map(|(_, trades)| trades).unwrap_or_default()
+            }
+            Command::EnableAccount { symbol, account_id } => {
+                engine.enable_account(symbol, *account_id);
+                Trades::new()
+            }
+            Command::Halt { symbol } => {
+                engine.halt(symbol);
+                Trades::new()
+            }
+            Command::Resume { symbol } => {
+                engine.resume(symbol);
+                Trades::new()
+            }
+            Command::SetFeeModel { symbol, fee_model } => {
+                engine.set_fee_model(symbol, fee_model.clone());
+                Trades::new()
+            }
+            Command::ResetAccumulatedFees { symbol, account_id } => {
+                engine.reset_accumulated_fees(symbol, *account_id);
+                Trades::new()
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: Timestamp, // engine time the command ran at
+    pub command: Command,
+}
+impl JournalEntry {
+    // Runs the command with `clock` (the engine's clock) set to the time it originally ran at.
+    pub fn apply(&self, engine: &mut Engine, clock: &ManualClock) -> Trades {
+        clock.set(self.timestamp);
+        self.command.apply(engine)
+    }
+}
+
+// Replays `entries` into `engine` and returns the state hash after each of them,
+// as (sequence, hash) pairs to compare against another replica step by step.
+pub fn replay_with_hashes(engine: &mut Engine, clock: &ManualClock, entries: &[JournalEntry]) -> Vec<(u64, u64)> {
+    entries
+        .iter()
+        .map(|entry| {
+            entry.apply(engine, clock);
+            (entry.sequence, engine.state_hash())
+        })
+        .collect()
+}
+
+// record header: payload length u32, sequence u64, header checksum u32 (over length and sequence),
+// checksum u32 (over sequence and payload).
+const HEADER_SIZE: usize = 20;
+
+// ----------------------------
+// Journal is an append only file of checksummed, sequence numbered commands,
+// every record is on disk (fsync) before `append` returns.
+// A crash can only leave a torn last record (cut short, or zeros the file was extended with),
+// which `open` drops, a bad record anywhere else is reported as corruption. Lengths are
+// checked on their own so a corrupt one can't pass for a torn tail and truncate the journal.
+// An append that fails is cut off again, the journal ends with the last good record.
+// ----------------------------
+pub struct Journal {
+    file: File,
+    length: u64, // of the good records, where the next one goes
+    next_sequence: u64,
+}
+impl Journal {
+    // Opens (or creates) the journal at `path` and returns the entries already in it.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Vec<JournalEntry>)> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let (entries, valid_length) = Self::parse(&bytes)?;
+        if valid_length < bytes.len() {
+            file.set_len(valid_length as u64)?;
+            file.sync_data()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let next_sequence = entries.last().map_or(1, |entry| entry.sequence + 1);
+        Ok((Self { file, length: valid_length as u64, next_sequence }, entries))
+    }
+
+    // Reads the entries of the journal at `path` without opening it for writing.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<JournalEntry>> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::parse(&bytes)?.0)
+    }
+
+    // Reads at most `limit` entries from `from_sequence` on, record by record so the
+    // journal is never loaded whole, records before `from_sequence` are skipped undecoded.
+    // Stops at the end of the journal or at a torn or corrupt record.
+    pub fn read_range(path: impl AsRef<Path>, from_sequence: u64, limit: usize) -> io::Result<Vec<JournalEntry>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        let mut header = [0; HEADER_SIZE];
+        while entries.len() < limit {
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+            let (length, sequence, header_checksum, checksum) = Self::parse_header(&header);
+            if Self::header_checksum(length, sequence) != header_checksum {
+                break;
+            }
+            let length = length as u64;
+            if sequence < from_sequence {
+                reader.seek_relative(length as i64)?;
+                continue;
+            }
+
+            let mut payload = Vec::new();
+            if (&mut reader).take(length).read_to_end(&mut payload)? as u64 != length || Self::checksum(sequence, &payload) != checksum {
+                break;
+            }
+            let mut decoder = Decoder::new(&payload);
+            let (timestamp, command) = decoder
+                .get_u64()
+                .and_then(|timestamp| Ok((timestamp, Command::decode(&mut decoder)?)))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            entries.push(JournalEntry { sequence, timestamp, command });
+        }
+        Ok(entries)
+    }
+
+    // Returns the valid entries and the length of the journal they occupy.
+    fn parse(bytes: &[u8]) -> io::Result<(Vec<JournalEntry>, usize)> {
+        let mut entries: Vec<JournalEntry> = Vec::new();
+        let mut position = 0;
+        while position < bytes.len() {
+            let record = &bytes[position..];
+            if record.len() < HEADER_SIZE || record.iter().all(|byte| *byte == 0) {
+                break;
+            }
+            let (length, sequence, header_checksum, checksum) = Self::parse_header(record[..HEADER_SIZE].try_into().unwrap());
+            if Self::header_checksum(length, sequence) != header_checksum {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal record at offset {} has a corrupt header", position)));
+            }
+            let length = length as usize;
+            // the length is good, the record really runs past the end of the file
+            let Some(payload) = record.get(HEADER_SIZE..HEADER_SIZE + length) else {
+                break;
+            };
+
+            let is_last = record[HEADER_SIZE + length..].iter().all(|byte| *byte == 0);
+            if Self::checksum(sequence, payload) != checksum {
+                if is_last {
+                    break;
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal record at offset {} fails its checksum", position)));
+            }
+            let expected = entries.last().map_or(sequence, |entry| entry.sequence + 1);
+            if sequence != expected {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal sequence {} where {} was expected", sequence, expected)));
+            }
+
+            let mut decoder = Decoder::new(payload);
+            let (timestamp, command) = decoder
+                .get_u64()
+                .and_then(|timestamp| Ok((timestamp, Command::decode(&mut decoder)?)))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            entries.push(JournalEntry { sequence, timestamp, command });
+            position += HEADER_SIZE + length;
+        }
+        Ok((entries, position))
+    }
+
+    // (payload length, sequence, header checksum, checksum)
+    fn parse_header(header: &[u8; HEADER_SIZE]) -> (u32, u64, u32, u32) {
+        (
+            u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            u64::from_le_bytes(header[4..12].try_into().unwrap()),
+            u32::from_le_bytes(header[12..16].try_into().unwrap()),
+            u32::from_le_bytes(header[16..20].try_into().unwrap()),
+        )
+    }
+
+    fn header_checksum(length: u32, sequence: u64) -> u32 {
+        let mut bytes = length.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&sequence.to_le_bytes());
+        crc32(&bytes)
+    }
+
+    fn checksum(sequence: u64, payload: &[u8]) -> u32 {
+        let mut bytes = sequence.to_le_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        crc32(&bytes)
+    }
+
+    // Durably appends `command` run at `timestamp`, returns its sequence number.
+    pub fn append(&mut self, timestamp: Timestamp, command: &Command) -> io::Result<u64> {
+        let sequence = self.next_sequence;
+        let mut payload = Encoder::new();
+        payload.put_u64(timestamp);
+        command.encode(&mut payload);
+        let payload = payload.into_bytes();
+
+        let mut record = Encoder::new();
+        record.put_u32(payload.len() as u32);
+        record.put_u64(sequence);
+        record.put_u32(Self::header_checksum(payload.len() as u32, sequence));
+        record.put_u32(Self::checksum(sequence, &payload));
+        record.put_bytes(&payload);
+
+        if let Err(error) = self.file.write_all(record.get_bytes()).and_then(|_| self.file.sync_data()) {
+            // whatever part of the record got written goes, the next append starts clean
+            let _ = self.file.set_len(self.length).and_then(|_| self.file.seek(SeekFrom::Start(self.length)));
+            return Err(error);
+        }
+        self.length += record.len() as u64;
+        self.next_sequence += 1;
+        Ok(sequence)
+    }
+
+    pub fn get_next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+}
+
+// ----------------------------
+// JournaledEngine writes every command to the journal before the engine
+// sees it, so nothing is published that a restart couldn't reproduce.
+// Rejected commands are journaled too, replaying them rejects them again.
+// The engine runs on a ManualClock set to the time journaled with each command,
+// time itself is read from the source clock (the system clock by default).
+// ----------------------------
+pub struct JournaledEngine {
+    engine: Engine,
+    journal: Journal,
+    clock: Rc<ManualClock>,
+    source_clock: Rc<dyn Clock>,
+    replication: Option<ReplicationPrimary>, // backups following this engine
+    highest_order_id: OrderId, // of every order entered so far, 0 before the first one
+}
+impl JournaledEngine {
+    // `engine` must be fresh and have the same instruments (and book settings)
+    // as when the journal was written, the journal at `path` is replayed into it.
+    pub fn open(path: impl AsRef<Path>, mut engine: Engine) -> io::Result<Self> {
+        let clock = Rc::new(ManualClock::default());
+        engine.set_clock(clock.clone());
+        let (journal, entries) = Journal::open(path)?;
+        for entry in &entries {
+            entry.apply(&mut engine, &clock);
+        }
+        let highest_order_id = Self::find_highest_order_id(&engine, &entries);
+        Ok(Self { engine, journal, clock, source_clock: Rc::new(SystemClock), replication: None, highest_order_id })
+    }
+
+    // Same as open but starts from the snapshot at `snapshot_path` (when there's one)
+    // and only replays the journal entries written after it.
+    pub fn open_with_snapshot(path: impl AsRef<Path>, snapshot_path: impl AsRef<Path>, mut engine: Engine) -> io::Result<Self> {
+        let snapshot = match std::fs::read(snapshot_path) {
+            Ok(bytes) => Some(bytes),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+        let clock = Rc::new(ManualClock::default());
+        engine.set_clock(clock.clone());
+        let (mut journal, entries) = Journal::open(path)?;
+        let last_sequence = restore_at(&mut engine, &clock, snapshot.as_deref(), &entries, u64::MAX)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        // a journal started over after the snapshot continues the snapshot's sequence
+        journal.next_sequence = journal.next_sequence.max(last_sequence + 1);
+        let highest_order_id = Self::find_highest_order_id(&engine, &entries);
+        Ok(Self { engine, journal, clock, source_clock: Rc::new(SystemClock), replication: None, highest_order_id })
+    }
+
+    // Orders in the journal and, for a journal started over after a snapshot, the ones resting in the books.
+    fn find_highest_order_id(engine: &Engine, entries: &[JournalEntry]) -> OrderId {
+        let mut highest_order_id = entries.iter().flat_map(|entry| entry.command.get_order_ids()).max().unwrap_or(0);
+        for symbol in engine.get_symbols() {
+            for order in engine.get_book(&symbol).into_iter().flat_map(|book| book.iter_resting_orders()) {
+                highest_order_id = highest_order_id.max(order.borrow().get_order_id());
+            }
+        }
+        highest_order_id
+    }
+
+    // Where command timestamps come from from now on.
+    pub fn set_source_clock(&mut self, source_clock: Rc<dyn Clock>) {
+        self.source_clock = source_clock;
+    }
+
+    // Writes a snapshot of the engine as of the last journaled command,
+    // to a temporary file first so a crash never leaves a half written snapshot at `path`.
+    pub fn write_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let bytes = self.engine.write_snapshot(self.journal.next_sequence - 1);
+        let temporary_path = path.with_extension("tmp");
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        std::fs::rename(temporary_path, path)
+    }
+
+    // Every command journaled from now on is also sent to the backups of `replication`.
+    pub fn set_replication(&mut self, replication: ReplicationPrimary) {
+        self.replication = Some(replication);
+    }
+
+    // Journals `command` with the current time, hands it to the backups
+    // and moves the engine's clock to it.
+    fn write_ahead(&mut self, command: &Command) -> io::Result<u64> {
+        let timestamp = self.source_clock.now();
+        let sequence = self.journal.append(timestamp, command)?;
+        self.highest_order_id = command.get_order_ids().into_iter().fold(self.highest_order_id, OrderId::max);
+        if let Some(replication) = &self.replication {
+            replication.publish(&JournalEntry { sequence, timestamp, command: command.clone() });
+        }
+        self.clock.set(timestamp);
+        Ok(sequence)
+    }
+
+    // Backups: journals and applies an entry of the primary's journal as is,
+    // it has to be the next one in sequence.
+    pub fn apply_replicated(&mut self, entry: &JournalEntry) -> io::Result<Trades> {
+        if entry.sequence != self.journal.next_sequence {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("entry {} isn't next, {} is", entry.sequence, self.journal.next_sequence)));
+        }
+        self.journal.append(entry.timestamp, &entry.command)?;
+        self.highest_order_id = entry.command.get_order_ids().into_iter().fold(self.highest_order_id, OrderId::max);
+        if let Some(replication) = &self.replication {
+            replication.publish(entry);
+        }
+        Ok(entry.apply(&mut self.engine, &self.clock))
+    }
+
+    // Journals then applies `command`.
+    pub fn execute(&mut self, command: Command) -> io::Result<Trades> {
+        self.write_ahead(&command)?;
+        Ok(command.apply(&mut self.engine))
+    }
+
+    pub fn add_order(&mut self, symbol: &str, order: Order) -> io::Result<Option<Trades>> {
+        self.write_ahead(&Command::AddOrder { symbol: symbol.to_string(), order: order.clone() })?;
+        Ok(self.engine.add_order(symbol, Rc::new(RefCell::new(order))))
+    }
+
+    pub fn add_stop_order(&mut self, symbol: &str, order: Order, trigger: Trigger) -> io::Result<Option<Trades>> {
+        self.write_ahead(&Command::AddStopOrder { symbol: symbol.to_string(), order: order.clone(), trigger })?;
+        Ok(self.engine.add_stop_order(symbol, Rc::new(RefCell::new(order)), trigger))
+    }
+
+    pub fn cancel_order(&mut self, symbol: &str, order_id: OrderId) -> io::Result<Option<Trades>> {
+        self.write_ahead(&Command::CancelOrder { symbol: symbol.to_string(), order_id })?;
+        Ok(self.engine.cancel_order(symbol, order_id))
+    }
+
+    pub fn modify_order(&mut self, symbol: &str, order: OrderModify) -> io::Result<Option<Trades>> {
+        self.write_ahead(&Command::ModifyOrder {
+            symbol: symbol.to_string(),
+            order_id: order.get_order_id(),
+            side: order.get_side(),
+            price: order.get_price(),
+            quantity: order.get_quantity(),
+        })?;
+        Ok(self.engine.modify_order(symbol, order))
+    }
+
+    pub fn connect_session(&mut self, session_id: SessionId) -> io::Result<bool> {
+        self.write_ahead(&Command::ConnectSession { session_id })?;
+        Ok(self.engine.connect_session(session_id))
+    }
+
+    pub fn disconnect_session(&mut self, session_id: SessionId) -> io::Result<Vec<BookUpdate>> {
+        self.write_ahead(&Command::DisconnectSession { session_id })?;
+        Ok(self.engine.disconnect_session(session_id))
+    }
+
+    pub fn add_oco_orders(&mut self, symbol: &str, first: OrderLeg, second: OrderLeg) -> io::Result<Option<(GroupId, Trades)>> {
+        self.write_ahead(&Command::AddOcoOrders {
+            symbol: symbol.to_string(),
+            first: CommandLeg::from_leg(&first),
+            second: CommandLeg::from_leg(&second),
+        })?;
+        Ok(self.engine.add_oco_orders(symbol, first, second))
+    }
+
+    pub fn add_bracket_order(&mut self, symbol: &str, entry: Order, take_profit: OrderLeg, stop_loss: OrderLeg) -> io::Result<Option<(GroupId, Trades)>> {
+        self.write_ahead(&Command::AddBracketOrder {
+            symbol: symbol.to_string(),
+            entry: entry.clone(),
+            take_profit: CommandLeg::from_leg(&take_profit),
+            stop_loss: CommandLeg::from_leg(&stop_loss),
+        })?;
+        Ok(self.engine.add_bracket_order(symbol, Rc::new(RefCell::new(entry)), take_profit, stop_loss))
+    }
+
+    pub fn mass_cancel(&mut self, symbol: &str, filter: MassCancelFilter) -> io::Result<Option<(Vec<OrderId>, Trades)>> {
+        self.write_ahead(&Command::MassCancel { symbol: symbol.to_string(), filter })?;
+        Ok(self.engine.mass_cancel(symbol, &filter))
+    }
+
+    pub fn disable_account(&mut self, symbol: &str, account_id: AccountId) -> io::Result<Option<(Vec<OrderId>, Trades)>> {
+        self.write_ahead(&Command::DisableAccount { symbol: symbol.to_string(), account_id })?;
+        Ok(self.engine.disable_account(symbol, account_id))
+    }
+
+    pub fn enable_account(&mut self, symbol: &str, account_id: AccountId) -> io::Result<bool> {
+        self.write_ahead(&Command::EnableAccount { symbol: symbol.to_string(), account_id })?;
+        Ok(self.engine.enable_account(symbol, account_id))
+    }
+
+    pub fn halt(&mut self, symbol: &str) -> io::Result<bool> {
+        self.write_ahead(&Command::Halt { symbol: symbol.to_string() })?;
+        Ok(self.engine.halt(symbol))
+    }
+
+    pub fn resume(&mut self, symbol: &str) -> io::Result<bool> {
+        self.write_ahead(&Command::Resume { symbol: symbol.to_string() })?;
+        Ok(self.engine.resume(symbol))
+    }
+
+    pub fn set_fee_model(&mut self, symbol: &str, fee_model: FeeModel) -> io::Result<bool> {
+        self.write_ahead(&Command::SetFeeModel { symbol: symbol.to_string(), fee_model: fee_model.clone() })?;
+        Ok(self.engine.set_fee_model(symbol, fee_model))
+    }
+
+    pub fn reset_accumulated_fees(&mut self, symbol: &str, account_id: Option<AccountId>) -> io::Result<bool> {
+        self.write_ahead(&Command::ResetAccumulatedFees { symbol: symbol.to_string(), account_id })?;
+        Ok(self.engine.reset_accumulated_fees(symbol, account_id))
+    }
+
+    // Events aren't journaled, replaying the commands that caused them reproduces them.
+    pub fn take_events(&mut self, symbol: &str) -> Vec<OrderEvent> {
+        self.engine.get_book_mut(symbol).map(|book| book.take_events()).unwrap_or_default()
+    }
+
+    pub fn get_state_hash(&self) -> u64 {
+        self.engine.state_hash()
+    }
+
+    pub fn get_engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    // Order ids above it were never used, gateways assigning ids carry on from there after a restart.
+    pub fn get_highest_order_id(&self) -> OrderId {
+        self.highest_order_id
+    }
+
+    pub fn get_journal(&self) -> &Journal {
+        &self.journal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SYMBOL: &str = "DEMO";
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("orderbook-rs-{}-{}.journal", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn new_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine
+    }
+
+    fn limit(order_id: OrderId, side: Side, price: f32, quantity: Quantity) -> Order {
+        Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity)
+    }
+
+    fn stop(order_id: OrderId, side: Side, trigger_price: f32, quantity: Quantity) -> OrderLeg {
+        let order = Order::new(order_id, OrderType::Market, side, OrderedFloat(f32::NAN), quantity);
+        OrderLeg::Stop(Rc::new(RefCell::new(order)), Trigger::Stop(OrderedFloat(trigger_price)))
+    }
+
+    #[test]
+    fn replay_reproduces_every_command() {
+        let path = journal_path("replay");
+        let mut journaled = JournaledEngine::open(&path, new_engine()).unwrap();
+        let fees = FeeSchedule::new(FeeRate::BasisPoints(-1.0), FeeRate::BasisPoints(2.0));
+        journaled.set_fee_model(SYMBOL, FeeModel::new(fees)).unwrap();
+        journaled.add_order(SYMBOL, limit(1, Side::Sell, 101.0, 10).with_account(1)).unwrap().unwrap();
+        journaled.add_order(SYMBOL, limit(2, Side::Sell, 102.0, 10).with_account(2)).unwrap().unwrap();
+        let first = OrderLeg::Limit(Rc::new(RefCell::new(limit(3, Side::Buy, 99.0, 5))));
+        journaled.add_oco_orders(SYMBOL, first, stop(4, Side::Buy, 103.0, 5)).unwrap().unwrap();
+        // the entry trades with order 1 and activates its legs
+        let take_profit = OrderLeg::Limit(Rc::new(RefCell::new(limit(6, Side::Sell, 105.0, 4))));
+        let (_, trades) = journaled.add_bracket_order(SYMBOL, limit(5, Side::Buy, 101.0, 4).with_account(3), take_profit, stop(7, Side::Sell, 98.0, 4)).unwrap().unwrap();
+        assert_eq!(trades.len(), 1);
+
+        let (cancelled, _) = journaled.disable_account(SYMBOL, 2).unwrap().unwrap();
+        assert_eq!(cancelled, vec![2]);
+        assert!(journaled.add_order(SYMBOL, limit(8, Side::Sell, 103.0, 1).with_account(2)).unwrap().is_none());
+        journaled.enable_account(SYMBOL, 2).unwrap();
+        journaled.halt(SYMBOL).unwrap();
+        assert!(journaled.add_order(SYMBOL, limit(9, Side::Sell, 103.0, 1)).unwrap().is_none());
+        journaled.resume(SYMBOL).unwrap();
+        journaled.halt(SYMBOL).unwrap();
+        let (cancelled, _) = journaled.mass_cancel(SYMBOL, MassCancelFilter::new().with_side(Side::Buy)).unwrap().unwrap();
+        assert_eq!(cancelled, vec![3, 4]);
+        journaled.reset_accumulated_fees(SYMBOL, Some(1)).unwrap();
+        let hash = journaled.get_state_hash();
+        assert_ne!(hash, new_engine().state_hash());
+        drop(journaled);
+
+        let replayed = JournaledEngine::open(&path, new_engine()).unwrap();
+        assert_eq!(replayed.get_state_hash(), hash);
+        let book = replayed.get_engine().get_book(SYMBOL).unwrap();
+        assert!(book.is_halted());
+        assert!(book.get_order(6).is_some() && book.get_order(3).is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn commands_round_trip_through_the_codec() {
+        let commands = [
+            Command::MassCancel {
+                symbol: SYMBOL.to_string(),
+                filter: MassCancelFilter::new().with_account(7).with_side(Side::Sell).with_price_range(OrderedFloat(1.0), OrderedFloat(2.0)).with_persistent(false),
+            },
+            Command::SetFeeModel { symbol: SYMBOL.to_string(), fee_model: FeeModel::new(FeeSchedule::new(FeeRate::PerUnit(0.5), FeeRate::BasisPoints(3.0))) },
+            Command::ResetAccumulatedFees { symbol: SYMBOL.to_string(), account_id: None },
+        ];
+        for command in commands {
+            let mut encoder = Encoder::new();
+            command.encode(&mut encoder);
+            let bytes = encoder.into_bytes();
+            let decoded = Command::decode(&mut Decoder::new(&bytes)).unwrap();
+            let mut reencoded = Encoder::new();
+            decoded.encode(&mut reencoded);
+            assert_eq!(reencoded.into_bytes(), bytes);
+        }
+    }
+
+    // three connects, returns the journal's bytes and where each record starts
+    fn write_three(path: &Path) -> (Vec<u8>, Vec<usize>) {
+        let (mut journal, _) = Journal::open(path).unwrap();
+        for session_id in 1..=3 {
+            journal.append(session_id * 10, &Command::ConnectSession { session_id }).unwrap();
+        }
+        let bytes = std::fs::read(path).unwrap();
+        let record_length = bytes.len() / 3;
+        (bytes, vec![0, record_length, 2 * record_length])
+    }
+
+    #[test]
+    fn torn_tail_is_dropped() {
+        let path = journal_path("torn");
+        let (bytes, offsets) = write_three(&path);
+
+        // cut in the middle of the last record, then zeros the file was extended with
+        let mut torn = bytes[..offsets[2] + HEADER_SIZE + 1].to_vec();
+        torn.extend_from_slice(&[0; 64]);
+        std::fs::write(&path, &torn).unwrap();
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), offsets[2] as u64);
+
+        assert_eq!(journal.append(30, &Command::ConnectSession { session_id: 3 }).unwrap(), 3);
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn corrupt_length_isnt_taken_for_a_torn_tail() {
+        let path = journal_path("corrupt-length");
+        let (mut bytes, offsets) = write_three(&path);
+
+        // the middle record now claims to run past the end of the file
+        bytes[offsets[1]..offsets[1] + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let error = Journal::open(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert!(Journal::read_range(&path, 1, 10).unwrap().len() == 1);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,5 +1,7 @@
 use super::{Price, Quantity};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LevelInfo {
     pub price: Price,
     pub quantity: Quantity,
@@ -10,6 +12,8 @@ impl LevelInfo {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderbookLevelInfos {
     bids: Vec<LevelInfo>,
     asks: Vec<LevelInfo>,
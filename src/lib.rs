@@ -1,10 +1,37 @@
-pub use std::collections::{BTreeMap, HashMap, VecDeque};
+pub use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 pub use ordered_float::OrderedFloat;
 pub use levelinfos::{LevelInfo, OrderbookLevelInfos};
 pub use order::Order;
 pub use modifyorder::OrderModify;
 pub use trade::{Trade, TradeInfo};
 pub use orderbook::OrderBook;
+pub use darkbook::DarkBook;
+pub use pricelevel::PriceLevel;
+pub use matching::{Fifo, FifoTopProRata, MatchingPolicy, ProRata};
+pub use fees::{FeeAccumulator, FeeModel, FeeRate, FeeSchedule};
+pub use risk::{AccountExposure, RejectReason, RiskCheckedOrderBook, RiskLimits};
+pub use spot::{Asset, AssetBalance, SpotAccount, SpotOrderBook};
+pub use positions::{MarkSource, Position, PositionSnapshot, PositionTracker};
+pub use masscancel::MassCancelFilter;
+pub use sessions::{Session, SessionRegistry, SessionState};
+pub use engine::{BookUpdate, Engine};
+pub use codec::{DecodeError, Decoder, Encoder};
+pub use journal::{replay_with_hashes, Command, CommandLeg, Journal, JournalEntry, JournaledEngine};
+pub use snapshot::{SnapshotError, SnapshotKind, SNAPSHOT_VERSION};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use replication::{ReplicationBackup, ReplicationMessage, ReplicationPrimary};
+pub use gateway::{connection_sink, AdminRequest, AdminResponse, EngineHandle, EngineRequest, EngineRequests, InstrumentInfo, MatchingService, NewOrder, OrderRejectReason, OrderStatus, OrderUpdate, UpdateSink};
+pub use fix::{FixAcceptor, FixInitiator, FixMessage};
+pub use ouch::{OrderToken, OuchClient, OuchReason, OuchRequest, OuchResponse, OuchServer, TimeInForce};
+pub use itch::{ChannelSink, FeedBook, FeedError, FileSink, ItchMessage, Locate, MarketDataPublisher, MulticastReceiver, MulticastSink, PacketSink, SystemEvent};
+#[cfg(feature = "websocket")]
+pub use websocket::{Channel, ClientMessage, ServerMessage, WebSocketServer};
+#[cfg(feature = "admin")]
+pub use admin::AdminServer;
+pub use peg::{Peg, PegType};
+pub use events::OrderEvent;
+pub use triggerbook::{StopOrder, Trail, Trigger, TriggerBook};
+pub use ordergroups::{OrderGroup, OrderGroups, OrderLeg};
 
 pub use ordertypes::OrderType;
 pub use side::Side;
@@ -24,12 +51,24 @@ use std::cell::RefCell;
 pub type Price = OrderedFloat<f32>;
 pub type Quantity = u32;
 pub type OrderId = u64;
+pub type GroupId = u64;
+pub type TradeId = u64;
+pub type Timestamp = u64;
+pub type AccountId = u64;
+// instrument identifier, one orderbook per symbol
+pub type Symbol = String;
+// gateway connection an order was entered through
+pub type SessionId = u64;
+// money amounts (fees, balances, P&L)
+pub type Amount = OrderedFloat<f64>;
 // ----------------------------
 // OrderPointer is a mutable owned reference to a heap allocated variable which can be shared during runtime
 // ----------------------------
 pub type OrderPointer = Rc<RefCell<Order>>;
 pub type OrderPointers = VecDeque<OrderPointer>;
 pub type Trades = Vec<Trade>;
+// next trade id, shared to number trades across several books
+pub type TradeIds = Rc<std::cell::Cell<TradeId>>;
 
 pub mod levelinfos;
 pub mod order;
@@ -38,4 +77,31 @@ pub mod trade;
 pub mod ordertypes;
 pub mod side;
 pub mod helperfns;
-pub mod orderbook;
\ No newline at end of file
+pub mod orderbook;
+pub mod darkbook;
+pub mod pricelevel;
+pub mod matching;
+pub mod fees;
+pub mod risk;
+pub mod spot;
+pub mod positions;
+pub mod masscancel;
+pub mod sessions;
+pub mod engine;
+pub mod codec;
+pub mod journal;
+pub mod snapshot;
+pub mod clock;
+pub mod replication;
+pub mod gateway;
+pub mod fix;
+pub mod ouch;
+pub mod itch;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod peg;
+pub mod events;
+pub mod triggerbook;
+pub mod ordergroups;
\ No newline at end of file
@@ -1,4 +1,3 @@
-use std::f32::NAN;
 use std::rc::Rc;
 use std::cell::RefCell;
 use orderbook_rs::helperfns::print_trades;
@@ -9,7 +8,7 @@ fn main() {
     let order_id_1 = 1;
     let o1:Order = Order::new(order_id_1, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(100.02), 10);
     let order_id_2 = 2;
-    let o2:Order = Order::new(order_id_2, OrderType::Market, Side::Sell, OrderedFloat(NAN), 12);
+    let o2:Order = Order::new(order_id_2, OrderType::Market, Side::Sell, OrderedFloat(f32::NAN), 12);
     let order_id_3 = 3;
     let o3:Order = Order::new(order_id_3, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(100.02), 10);
     let order_id_4 = 4;
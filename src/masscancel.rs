@@ -0,0 +1,130 @@
+use super::{AccountId, Order, OrderType, Price, SessionId, Side};
+
+// ----------------------------
+// MassCancelFilter selects the orders `OrderBook::mass_cancel` cancels,
+// every criterion that's set has to match, an empty filter matches every order.
+// ----------------------------
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MassCancelFilter {
+    account_id: Option<AccountId>,
+    side: Option<Side>,
+    order_type: Option<OrderType>,
+    price_range: Option<(Price, Price)>, // inclusive, orders without a price never match it
+    session_id: Option<SessionId>,
+    persistent: Option<bool>,
+}
+impl MassCancelFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_account(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    pub fn with_side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn with_order_type(mut self, order_type: OrderType) -> Self {
+        self.order_type = Some(order_type);
+        self
+    }
+
+    pub fn with_price_range(mut self, min_price: Price, max_price: Price) -> Self {
+        self.price_range = Some((min_price, max_price));
+        self
+    }
+
+    pub fn with_session(mut self, session_id: SessionId) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    // true only matches persistent orders, false only the ones cancelled on disconnect.
+    pub fn with_persistent(mut self, persistent: bool) -> Self {
+        self.persistent = Some(persistent);
+        self
+    }
+
+    pub fn get_account_id(&self) -> Option<AccountId> {
+        self.account_id
+    }
+    pub fn get_side(&self) -> Option<Side> {
+        self.side
+    }
+    pub fn get_order_type(&self) -> Option<OrderType> {
+        self.order_type
+    }
+    pub fn get_price_range(&self) -> Option<(Price, Price)> {
+        self.price_range
+    }
+    pub fn get_session_id(&self) -> Option<SessionId> {
+        self.session_id
+    }
+    pub fn get_persistent(&self) -> Option<bool> {
+        self.persistent
+    }
+
+    pub fn matches(&self, order: &Order) -> bool {
+        if self.account_id.is_some_and(|account_id| order.get_account_id() != Some(account_id)) {
+            return false;
+        }
+        if self.side.is_some_and(|side| order.get_side() != side) {
+            return false;
+        }
+        if self.order_type.is_some_and(|order_type| order.get_order_type() != order_type) {
+            return false;
+        }
+        if self.session_id.is_some_and(|session_id| order.get_session_id() != Some(session_id)) {
+            return false;
+        }
+        if self.persistent.is_some_and(|persistent| order.is_persistent() != persistent) {
+            return false;
+        }
+        if let Some((min_price, max_price)) = self.price_range {
+            let price = order.get_price();
+            return !price.is_nan() && min_price <= price && price <= max_price;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ordered_float::OrderedFloat;
+
+    fn order(side: Side, price: f32) -> Order {
+        Order::new(1, OrderType::GoodTillCancel, side, OrderedFloat(price), 10).with_account(7)
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = MassCancelFilter::new();
+        assert!(filter.matches(&order(Side::Buy, 100.0)));
+        assert!(filter.matches(&Order::new(1, OrderType::Market, Side::Sell, OrderedFloat(f32::NAN), 10)));
+    }
+
+    #[test]
+    fn account_and_side_have_to_match() {
+        let filter = MassCancelFilter::new().with_account(7).with_side(Side::Buy);
+        assert!(filter.matches(&order(Side::Buy, 100.0)));
+        assert!(!filter.matches(&order(Side::Sell, 100.0)));
+        assert!(!filter.matches(&order(Side::Buy, 100.0).with_account(8)));
+        // orders without an account never match an account filter
+        assert!(!filter.matches(&Order::new(1, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(100.0), 10)));
+    }
+
+    #[test]
+    fn price_range_is_inclusive() {
+        let filter = MassCancelFilter::new().with_price_range(OrderedFloat(99.0), OrderedFloat(101.0));
+        for (price, matches) in [(98.5, false), (99.0, true), (100.0, true), (101.0, true), (101.5, false)] {
+            assert_eq!(filter.matches(&order(Side::Sell, price)), matches, "price {}", price);
+        }
+        assert!(!filter.matches(&Order::new(1, OrderType::Market, Side::Sell, OrderedFloat(f32::NAN), 10)));
+    }
+}
@@ -0,0 +1,166 @@
+use std::cmp::min;
+use super::Quantity;
+
+// ----------------------------
+// MatchingPolicy decides how an incoming quantity is shared among the orders
+// resting in one queue of a price level.
+// `resting` holds remaining quantities in time priority, the result has one
+// allocation per resting order, never more than its remaining quantity,
+// and never more than `quantity` in total.
+// ----------------------------
+pub trait MatchingPolicy {
+    fn allocate(&self, resting: &[Quantity], quantity: Quantity) -> Vec<Quantity>;
+}
+
+// Price-time priority, first come first served.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Fifo;
+impl MatchingPolicy for Fifo {
+    fn allocate(&self, resting: &[Quantity], quantity: Quantity) -> Vec<Quantity> {
+        allocate_fifo(resting, quantity)
+    }
+}
+
+// ----------------------------
+// Pro-rata: each order gets floor(quantity * its size / level size).
+// Allocations below `min_allocation` are dropped to 0, whatever is left
+// after rounding goes FIFO to the orders that can still take it.
+// ----------------------------
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProRata {
+    min_allocation: Quantity,
+}
+impl ProRata {
+    pub fn new(min_allocation: Quantity) -> Self {
+        Self { min_allocation }
+    }
+}
+impl MatchingPolicy for ProRata {
+    fn allocate(&self, resting: &[Quantity], quantity: Quantity) -> Vec<Quantity> {
+        allocate_pro_rata(resting, quantity, self.min_allocation)
+    }
+}
+
+// ----------------------------
+// FIFO top order then pro-rata: the first order in the queue is filled first,
+// what's left is shared pro-rata among the others as in `ProRata`.
+// ----------------------------
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FifoTopProRata {
+    min_allocation: Quantity,
+}
+impl FifoTopProRata {
+    pub fn new(min_allocation: Quantity) -> Self {
+        Self { min_allocation }
+    }
+}
+impl MatchingPolicy for FifoTopProRata {
+    fn allocate(&self, resting: &[Quantity], quantity: Quantity) -> Vec<Quantity> {
+        let Some((top, others)) = resting.split_first() else {
+            return Vec::new();
+        };
+        let top_allocation = min(*top, quantity);
+        let mut allocations = vec![top_allocation];
+        allocations.extend(allocate_pro_rata(others, quantity - top_allocation, self.min_allocation));
+        allocations
+    }
+}
+
+fn allocate_fifo(resting: &[Quantity], mut quantity: Quantity) -> Vec<Quantity> {
+    resting
+        .iter()
+        .map(|size| {
+            let allocation = min(*size, quantity);
+            quantity -= allocation;
+            allocation
+        })
+        .collect()
+}
+
+fn allocate_pro_rata(resting: &[Quantity], quantity: Quantity, min_allocation: Quantity) -> Vec<Quantity> {
+    let total: u64 = resting.iter().map(|size| *size as u64).sum();
+    if total <= quantity as u64 {
+        return resting.to_vec();
+    }
+
+    let mut allocations: Vec<Quantity> = resting
+        .iter()
+        .map(|size| {
+            let allocation = (quantity as u64 * *size as u64 / total) as Quantity;
+            if allocation < min_allocation { 0 } else { allocation }
+        })
+        .collect();
+
+    // leftover from rounding and dropped allocations, FIFO
+    let mut leftover = quantity - allocations.iter().sum::<Quantity>();
+    for (allocation, size) in allocations.iter_mut().zip(resting) {
+        if leftover == 0 {
+            break;
+        }
+        let extra = min(size - *allocation, leftover);
+        *allocation += extra;
+        leftover -= extra;
+    }
+    allocations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (resting, incoming quantity, expected allocations)
+    fn check(policy: &dyn MatchingPolicy, cases: &[(&[Quantity], Quantity, &[Quantity])]) {
+        for (resting, quantity, expected) in cases {
+            assert_eq!(policy.allocate(resting, *quantity), expected.to_vec(), "resting {:?}, quantity {}", resting, quantity);
+        }
+    }
+
+    #[test]
+    fn fifo() {
+        check(&Fifo, &[
+            (&[5, 3, 2], 7, &[5, 2, 0]),
+            (&[5, 3, 2], 5, &[5, 0, 0]),
+            (&[5, 3, 2], 20, &[5, 3, 2]),
+            (&[], 5, &[]),
+        ]);
+    }
+
+    #[test]
+    fn pro_rata() {
+        check(&ProRata::new(0), &[
+            (&[50, 30, 20], 10, &[5, 3, 2]),
+            // rounding remainder goes FIFO
+            (&[10, 10, 10], 10, &[4, 3, 3]),
+            (&[1, 1, 1], 2, &[1, 1, 0]),
+            // more than the whole level
+            (&[5, 3], 20, &[5, 3]),
+            (&[5, 3], 8, &[5, 3]),
+        ]);
+    }
+
+    #[test]
+    fn pro_rata_min_allocation() {
+        check(&ProRata::new(3), &[
+            // 1 is below the minimum and dropped, the leftover goes to the first order
+            (&[60, 30, 10], 10, &[7, 3, 0]),
+            // every share below the minimum, all of it goes FIFO
+            (&[10, 10, 10], 5, &[5, 0, 0]),
+            (&[2, 2], 10, &[2, 2]),
+        ]);
+    }
+
+    #[test]
+    fn fifo_top_pro_rata() {
+        check(&FifoTopProRata::new(0), &[
+            (&[4, 60, 40], 14, &[4, 6, 4]),
+            // top order takes everything it can first
+            (&[20, 60, 40], 14, &[14, 0, 0]),
+            (&[4, 10, 10, 10], 14, &[4, 4, 3, 3]),
+            (&[4, 6], 50, &[4, 6]),
+            (&[], 5, &[]),
+        ]);
+        check(&FifoTopProRata::new(5), &[
+            (&[2, 30, 10], 10, &[2, 8, 0]),
+        ]);
+    }
+}
@@ -1,7 +1,8 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use super::{OrderId, Price, Side, Quantity, OrderType, OrderPointer, Order};
+use super::{OrderId, Price, Side, Quantity, OrderPointer, Order};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderModify {
     order_id: OrderId,
     price: Price,
@@ -34,15 +35,11 @@ impl OrderModify {
         self.quantity
     }
 
-    // Only orders we want to modify are `GoodTillCancel`
-    // But added OrderType to support types added in future
-    pub fn to_order_pointer(&self, order_type: OrderType) -> OrderPointer {
-        Rc::new(RefCell::new(Order::new(
-            self.get_order_id(),
-            order_type,
-            self.get_side(),
-            self.get_price(),
-            self.get_quantity(),
-        )))
+    // New order keeps every attribute of the `original` order
+    // (type, peg, ...) except side, price and quantity.
+    pub fn to_order_pointer(&self, original: &Order) -> OrderPointer {
+        let mut order = original.clone();
+        order.amend(self.get_side(), self.get_price(), self.get_quantity());
+        Rc::new(RefCell::new(order))
     }
 }
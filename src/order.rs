@@ -1,13 +1,77 @@
-use super::{OrderId, OrderType, Side, Price, Quantity};
+use std::cmp::min;
+use super::{AccountId, Amount, SessionId, OrderId, OrderType, Side, Price, Quantity, Peg, GroupId};
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "OrderFields"))]
 pub struct Order {
     order_id: OrderId,
     order_type: OrderType,
     side: Side,
+    #[cfg_attr(feature = "serde", serde(with = "crate::helperfns::nan_as_null"))]
     price: Price,
     initial_quantity: Quantity,
     remaining_quantity: Quantity,
+    peg: Option<Peg>,
+    group_id: Option<GroupId>, // OCO or bracket group the order was added with
+    min_quantity: Option<Quantity>, // smallest quantity the order accepts to trade at once
+    all_or_none: bool, // only trades for its whole remaining quantity at once
+    hidden: bool, // never displayed, trades after displayed orders at the same price
+    account_id: Option<AccountId>, // participant the order belongs to
+    quote_budget: Option<Amount>, // market buys, most quote asset the order may spend
+    session_id: Option<SessionId>, // gateway session the order was entered through
+    persistent: bool, // survives its session disconnecting
 }
+
+// What deserializing an `Order` reads before it's checked, an order can't have
+// more remaining than it started with.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct OrderFields {
+    order_id: OrderId,
+    order_type: OrderType,
+    side: Side,
+    #[serde(with = "crate::helperfns::nan_as_null")]
+    price: Price,
+    initial_quantity: Quantity,
+    remaining_quantity: Quantity,
+    peg: Option<Peg>,
+    group_id: Option<GroupId>,
+    min_quantity: Option<Quantity>,
+    all_or_none: bool,
+    hidden: bool,
+    account_id: Option<AccountId>,
+    quote_budget: Option<Amount>,
+    session_id: Option<SessionId>,
+    persistent: bool,
+}
+#[cfg(feature = "serde")]
+impl TryFrom<OrderFields> for Order {
+    type Error = String;
+
+    fn try_from(fields: OrderFields) -> Result<Self, Self::Error> {
+        if fields.remaining_quantity > fields.initial_quantity {
+            return Err(format!("remaining quantity {} above initial quantity {}", fields.remaining_quantity, fields.initial_quantity));
+        }
+        Ok(Self {
+            order_id: fields.order_id,
+            order_type: fields.order_type,
+            side: fields.side,
+            price: fields.price,
+            initial_quantity: fields.initial_quantity,
+            remaining_quantity: fields.remaining_quantity,
+            peg: fields.peg,
+            group_id: fields.group_id,
+            min_quantity: fields.min_quantity,
+            all_or_none: fields.all_or_none,
+            hidden: fields.hidden,
+            account_id: fields.account_id,
+            quote_budget: fields.quote_budget,
+            session_id: fields.session_id,
+            persistent: fields.persistent,
+        })
+    }
+}
+
 impl Order {
     pub fn new(
         order_id: OrderId,
@@ -23,9 +87,63 @@ impl Order {
             price,
             initial_quantity: quantity,
             remaining_quantity: quantity,
+            peg: None,
+            group_id: None,
+            min_quantity: None,
+            all_or_none: false,
+            hidden: false,
+            account_id: None,
+            quote_budget: None,
+            session_id: None,
+            persistent: false,
         }
     }
 
+    pub fn with_peg(mut self, peg: Peg) -> Self {
+        self.peg = Some(peg);
+        self
+    }
+
+    // While resting, incoming orders too small to trade it skip it, but their remainder
+    // is cancelled rather than rest crossing it (see `OrderBook::add_order`), same for all or none.
+    pub fn with_min_quantity(mut self, min_quantity: Quantity) -> Self {
+        self.min_quantity = Some(min_quantity);
+        self
+    }
+
+    pub fn with_all_or_none(mut self) -> Self {
+        self.all_or_none = true;
+        self
+    }
+
+    pub fn with_hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    pub fn with_account(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    pub fn with_session(mut self, session_id: SessionId) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    // Persistent orders aren't cancelled when their session disconnects.
+    pub fn with_persistent(mut self) -> Self {
+        self.persistent = true;
+        self
+    }
+
+    // Caps the order by the quote amount it may spend rather than (only) by its quantity,
+    // set the quantity to Quantity::MAX to trade the whole budget.
+    pub fn with_quote_budget(mut self, quote_budget: Amount) -> Self {
+        self.quote_budget = Some(quote_budget);
+        self
+    }
+
     pub fn get_order_id(&self) -> OrderId {
         self.order_id
     }
@@ -47,6 +165,53 @@ impl Order {
     pub fn get_filled_quantity(&self) -> Quantity {
         self.get_initial_quantity() - self.get_remaining_quantity()
     }
+    pub fn get_peg(&self) -> Option<Peg> {
+        self.peg
+    }
+    pub fn get_min_quantity(&self) -> Option<Quantity> {
+        self.min_quantity
+    }
+    pub fn is_all_or_none(&self) -> bool {
+        self.all_or_none
+    }
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+    pub fn get_account_id(&self) -> Option<AccountId> {
+        self.account_id
+    }
+    pub fn get_quote_budget(&self) -> Option<Amount> {
+        self.quote_budget
+    }
+    pub fn get_session_id(&self) -> Option<SessionId> {
+        self.session_id
+    }
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+    pub fn get_group_id(&self) -> Option<GroupId> {
+        self.group_id
+    }
+    pub fn set_group_id(&mut self, group_id: GroupId) {
+        self.group_id = Some(group_id);
+    }
+    // The order's group was dissolved, it isn't linked to anything anymore.
+    pub fn clear_group_id(&mut self) {
+        self.group_id = None;
+    }
+
+    // Orders can restrict how they trade once resting:
+    // - all or none: only trades when it's completely filled in one execution.
+    // - minimum quantity: every execution is at least min(minimum quantity, remaining quantity).
+    pub fn can_trade(&self, quantity: Quantity) -> bool {
+        if self.is_all_or_none() && quantity < self.get_remaining_quantity() {
+            return false;
+        }
+        match self.get_min_quantity() {
+            Some(min_quantity) => quantity >= min(min_quantity, self.get_remaining_quantity()),
+            None => true,
+        }
+    }
 
     pub fn isfilled(&self) -> bool {
         self.get_remaining_quantity() == 0
@@ -60,4 +225,52 @@ impl Order {
         }
         self.remaining_quantity -= quantity;
     }
+
+    // Used by MarketToLimit once its market leg is done,
+    // the order keeps its id and remaining quantity but now rests at `price`.
+    pub fn to_good_till_cancel(&mut self, price: Price) {
+        self.order_type = OrderType::GoodTillCancel;
+        self.price = price;
+    }
+
+    // Used by the orderbook when re-pricing pegged orders.
+    pub fn reprice(&mut self, price: Price) {
+        self.price = price;
+    }
+
+    // Used by OrderModify, replaces side, price and quantity
+    // but keeps every other attribute (type, peg, ...) of the order.
+    pub fn amend(&mut self, side: Side, price: Price, quantity: Quantity) {
+        self.side = side;
+        self.price = price;
+        self.initial_quantity = quantity;
+        self.remaining_quantity = quantity;
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::OrderedFloat;
+
+    #[test]
+    fn market_order_price_round_trips_as_null() {
+        let order = Order::new(1, OrderType::Market, Side::Buy, OrderedFloat(f32::NAN), 10);
+        let json = serde_json::to_value(&order).unwrap();
+        assert!(json["price"].is_null());
+        let order: Order = serde_json::from_value(json).unwrap();
+        assert!(order.get_price().is_nan());
+
+        let limit = Order::new(2, OrderType::GoodTillCancel, Side::Sell, OrderedFloat(101.5), 10);
+        let limit: Order = serde_json::from_str(&serde_json::to_string(&limit).unwrap()).unwrap();
+        assert_eq!(limit.get_price(), OrderedFloat(101.5));
+    }
+
+    #[test]
+    fn remaining_above_initial_quantity_is_rejected() {
+        let mut json = serde_json::to_value(Order::new(1, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(100.0), 10)).unwrap();
+        json["remaining_quantity"] = 11.into();
+        let error = serde_json::from_value::<Order>(json).err().unwrap();
+        assert!(error.to_string().contains("remaining quantity 11 above initial quantity 10"));
+    }
 }
@@ -1,17 +1,36 @@
-use std::{cmp::{min, Reverse}, f32::NAN};
-use crate::helperfns::print_trades;
+use std::cell::{Cell, RefCell};
+use std::cmp::{min, Reverse};
+use std::rc::Rc;
 
 use super::*;
+use crate::helperfns::fnv1a64;
+use crate::snapshot::{seal, unseal, SnapshotError, SnapshotKind};
 
 pub struct OrderEntry {
     order: OrderPointer,
-    location_index: usize,
 }
 
 pub struct OrderBook {
-    orders: HashMap<OrderId, OrderEntry>, // all orders access in O(1)
-    bids: BTreeMap<Reverse<Price>, OrderPointers>, // Price-Time priority sorted high -> low
-    asks: BTreeMap<Price, OrderPointers>, // Price-Time priority sorted low -> high
+    orders: BTreeMap<OrderId, OrderEntry>, // all orders by id, ordered so nothing depends on hash order
+    bids: BTreeMap<Reverse<Price>, PriceLevel>, // Price-Time priority sorted high -> low
+    asks: BTreeMap<Price, PriceLevel>, // Price-Time priority sorted low -> high
+    pegged: Vec<OrderId>, // pegged orders in the order they entered the book
+    events: Vec<OrderEvent>,
+    triggers: TriggerBook, // stop orders waiting for the market to trade through them
+    last_trade_price: Option<Price>,
+    groups: OrderGroups, // OCO and bracket orders
+    policy: Box<dyn MatchingPolicy>, // how a level is shared among its resting orders
+    trade_ids: TradeIds, // next trade id, shared by all the books of an engine
+    fee_model: FeeModel,
+    fee_accumulator: FeeAccumulator, // fees charged per account since the last reset
+    disabled_accounts: BTreeSet<AccountId>, // kill switch, no new orders from these accounts
+    halted: bool, // trading halt, nothing new enters the book until `resume`
+    clock: Rc<dyn Clock>, // trade timestamps
+}
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl OrderBook {
     fn can_match(&self, side: Side, price: Price) -> bool {
@@ -20,311 +39,1632 @@ impl OrderBook {
                 return false;
             }
             let (best_ask, _) = self.asks.iter().next().unwrap();
-            return price >= *best_ask;
+            price >= *best_ask
         } else {
             if self.bids.is_empty() {
                 return false;
             }
             let (Reverse(best_bid), _) = self.bids.iter().next().unwrap();
-            return price <= *best_bid;
+            price <= *best_bid
         }
     }
 
-    fn match_orders(&mut self) -> Trades {
-        let mut trades: Trades = Vec::with_capacity(self.orders.len());
+    // Shares `quantity` among one queue of a level with the matching policy.
+    // The first resting order that can't trade its allocation is left out and the
+    // queue is allocated again without it, it keeps its place in the queue.
+    fn allocate_queue(&self, orders: &OrderPointers, quantity: Quantity) -> Vec<(OrderPointer, Quantity)> {
+        let mut candidates: Vec<&OrderPointer> = orders.iter().collect();
+        loop {
+            let sizes: Vec<Quantity> = candidates.iter().map(|order| order.borrow().get_remaining_quantity()).collect();
+            let allocations = self.policy.allocate(&sizes, quantity);
 
-        while !self.bids.is_empty() && !self.asks.is_empty() {
-            let Reverse(best_bid_price) = *self.bids.keys().next().unwrap();
-            let best_ask_price = *self.asks.keys().next().unwrap();
-
-            if *best_bid_price < *best_ask_price {
-                println!("Can't match order: best bid price < best ask price");
-                break;
+            let ineligible = candidates
+                .iter()
+                .zip(&allocations)
+                .position(|(order, allocation)| *allocation > 0 && !order.borrow().can_trade(*allocation));
+            match ineligible {
+                Some(index) => {
+                    candidates.remove(index);
+                }
+                None => {
+                    return candidates
+                        .into_iter()
+                        .zip(allocations)
+                        .filter(|(_, allocation)| *allocation > 0)
+                        .map(|(order, allocation)| (order.clone(), allocation))
+                        .collect();
+                }
             }
+        }
+    }
+
+    // Walks the opposite side in price priority and returns the fills `order` would get,
+    // each level's displayed queue is allocated before its hidden queue.
+    // MarketToLimit stops after the best level.
+    fn plan_fills(&self, order: &Order) -> Vec<(OrderPointer, Quantity)> {
+        let is_market = matches!(order.get_order_type(), OrderType::Market | OrderType::MarketToLimit);
+        let best_level_only = order.get_order_type() == OrderType::MarketToLimit;
+        let limit_price = order.get_price();
+        let mut remaining_quantity = order.get_remaining_quantity();
+        let mut remaining_budget = order.get_quote_budget();
+        let mut fills = Vec::new();
+
+        let levels: Box<dyn Iterator<Item = (Price, &PriceLevel)>> = if order.get_side() == Side::Buy {
+            Box::new(self.asks.iter().map(|(price, orders)| (*price, orders)).take_while(|(price, _)| is_market || *price <= limit_price))
+        } else {
+            Box::new(self.bids.iter().map(|(Reverse(price), orders)| (*price, orders)).take_while(|(price, _)| is_market || *price >= limit_price))
+        };
 
-            let bids = self.bids.get_mut(&Reverse(best_bid_price)).unwrap();
-            let asks = self.asks.get_mut(&best_ask_price).unwrap();
-
-            // Process one bid and one ask at a time
-            if let (Some(bid_rc), Some(ask_rc)) = (bids.front(), asks.front()) {
-                let mut bid = bid_rc.borrow_mut();
-                let mut ask = ask_rc.borrow_mut();
-
-                let quantity = min(bid.get_remaining_quantity(), ask.get_remaining_quantity());
-
-                // Create trade
-                let trade = Trade::new(
-                    TradeInfo::new(
-                        bid.get_order_id(),
-                        bid.get_price(),
-                        quantity,
-                    ),
-                    TradeInfo::new(
-                        ask.get_order_id(),
-                        ask.get_price(),
-                        quantity,
-                    ),
-                );
-                trades.push(trade);
-
-                // Fill orders
-                bid.fill(quantity);
-                ask.fill(quantity);
-
-                drop(bid);
-                drop(ask);
-
-                // Remove filled orders from the order book
-                if bid_rc.borrow().isfilled() {
-                    self.orders.remove(&bid_rc.borrow().get_order_id());
-                    bids.pop_front();
+        for (price, level) in levels {
+            for orders in [level.get_displayed(), level.get_hidden()] {
+                // a quote budget caps the quantity the order can afford at this price
+                let quantity = match remaining_budget {
+                    Some(budget) => min(remaining_quantity, (budget.into_inner() / price.into_inner() as f64) as Quantity),
+                    None => remaining_quantity,
+                };
+                if quantity == 0 {
+                    return fills;
                 }
-                if ask_rc.borrow().isfilled() {
-                    self.orders.remove(&ask_rc.borrow().get_order_id());
-                    asks.pop_front();
+                let queue_fills = self.allocate_queue(orders, quantity);
+                let filled_quantity = queue_fills.iter().map(|(_, quantity)| quantity).sum::<Quantity>();
+                remaining_quantity -= filled_quantity;
+                if let Some(budget) = remaining_budget.as_mut() {
+                    *budget -= filled_quantity as f64 * price.into_inner() as f64;
                 }
+                fills.extend(queue_fills);
             }
+            if best_level_only {
+                break;
+            }
+        }
+        fills
+    }
+
+    // Matches the incoming `order` against the opposite side, it isn't in the book yet.
+    // The incoming order's own restrictions apply to everything it can trade right now:
+    // all or none needs its whole quantity, minimum quantity needs at least that much,
+    // otherwise nothing trades.
+    fn match_orders(&mut self, order: &OrderPointer) -> Trades {
+        let fills = self.plan_fills(&order.borrow());
+        let planned_quantity: Quantity = fills.iter().map(|(_, quantity)| quantity).sum();
 
-            // Clean up empty price levels
-            if self.bids.get(&Reverse(best_bid_price)).unwrap().is_empty() {
-                self.bids.remove(&Reverse(best_bid_price));
+        let required_quantity = {
+            let order = order.borrow();
+            if order.is_all_or_none() {
+                order.get_remaining_quantity()
+            } else {
+                min(order.get_min_quantity().unwrap_or(0), order.get_remaining_quantity())
             }
-            if self.asks.get(&best_ask_price).unwrap().is_empty() {
-                self.asks.remove(&best_ask_price);
+        };
+        if planned_quantity == 0 || planned_quantity < required_quantity {
+            return Vec::new();
+        }
+
+        let mut trades: Trades = Vec::with_capacity(fills.len());
+        let timestamp = self.clock.now();
+        for (resting_rc, quantity) in fills {
+            let mut incoming = order.borrow_mut();
+            let mut resting = resting_rc.borrow_mut();
+            incoming.fill(quantity);
+            resting.fill(quantity);
+
+            // incoming order is the aggressor (taker), it always trades at the resting order's (maker) price
+            let price = resting.get_price();
+            let incoming_trade = TradeInfo::new(incoming.get_order_id(), price, quantity)
+                .with_account(incoming.get_account_id())
+                .with_fee(self.fee_model.get_taker_fee(incoming.get_account_id(), price, quantity));
+            let resting_trade = TradeInfo::new(resting.get_order_id(), price, quantity)
+                .with_account(resting.get_account_id())
+                .with_fee(self.fee_model.get_maker_fee(resting.get_account_id(), price, quantity));
+            let aggressor_side = incoming.get_side();
+            let (bid_trade, ask_trade) = if aggressor_side == Side::Buy {
+                (incoming_trade, resting_trade)
+            } else {
+                (resting_trade, incoming_trade)
+            };
+            let trade_id = self.trade_ids.get();
+            self.trade_ids.set(trade_id + 1);
+            let trade = Trade::new(trade_id, aggressor_side, timestamp, bid_trade, ask_trade);
+            self.fee_accumulator.add_trade(&trade);
+            trades.push(trade);
+
+            // Remove filled orders from the order book
+            if resting.isfilled() {
+                let (side, price, order_id) = (resting.get_side(), resting.get_price(), resting.get_order_id());
+                drop(resting);
+                self.orders.remove(&order_id);
+                self.remove_from_level(side, price, order_id);
             }
         }
 
-        // After matching we need to kill orders of type FillAndKill
-        if !self.bids.is_empty() {
-            let mut should_cancel = false;
-            let mut order_id = 0;
-            {
-                let (_, bids) = self.bids.iter().next().unwrap();
-                let bid = bids.front().unwrap().borrow();
-                should_cancel = bid.get_order_type() == OrderType::FillAndKill;
-                order_id = bid.get_order_id();
+        trades
+    }
+
+    fn insert_order(&mut self, order: &OrderPointer) {
+        if order.borrow().get_side() == Side::Buy {
+            let orders = self.bids.entry(Reverse(order.borrow().get_price())).or_default();
+            orders.push_back(order.clone());
+        } else {
+            let orders = self.asks.entry(order.borrow().get_price()).or_default();
+            orders.push_back(order.clone());
+        }
+
+        self.orders.insert(
+            order.borrow().get_order_id(),
+            OrderEntry {
+                order: order.clone(),
+            }
+        );
+    }
+
+    // Removes the order from its price level only, `orders` is left untouched.
+    fn remove_from_level(&mut self, side: Side, price: Price, order_id: OrderId) {
+        if side == Side::Sell {
+            let orders = self.asks.get_mut(&price).unwrap();
+            orders.remove(order_id);
+
+            // if all orders at a price level are matched, remove that level
+            if orders.is_empty() {
+                self.asks.remove(&price);
             }
-            if should_cancel {
-                self.cancel_order(order_id);
+        } else {
+            let orders = self.bids.get_mut(&Reverse(price)).unwrap();
+            orders.remove(order_id);
+
+            // if all orders at a price level are matched, remove that level
+            if orders.is_empty() {
+                self.bids.remove(&Reverse(price));
             }
         }
+    }
+
+    // Best price on `side` among levels displaying at least one unpegged order,
+    // this is the reference pegged orders are priced from, hidden orders never leak into it.
+    fn best_unpegged_price(&self, side: Side) -> Option<Price> {
+        fn has_unpegged(level: &PriceLevel) -> bool {
+            level.get_displayed().iter().any(|order| order.borrow().get_peg().is_none())
+        }
+
+        if side == Side::Buy {
+            self.bids.iter().find(|(_, orders)| has_unpegged(orders)).map(|(Reverse(price), _)| *price)
+        } else {
+            self.asks.iter().find(|(_, orders)| has_unpegged(orders)).map(|(price, _)| *price)
+        }
+    }
+
+    // ----------------------------
+    // Pegged orders re-pricing priority rules:
+    // - reference prices come from the best levels holding at least one unpegged order,
+    //   so pegged orders never peg to each other.
+    // - pegged orders are re-priced in the order they entered the book.
+    // - a re-priced order goes to the back of its new price level, losing its time priority.
+    // - an order keeps its current price when its reference side is empty or when
+    //   the new price would cross the opposite side, re-pricing never trades.
+    // - pegged orders never trade on entry either, one priced across the opposite side is refused
+    //   (a market peg needs an offset taking it off the opposite best, negative for buys).
+    // - every re-price emits an `OrderEvent::Modified`.
+    // ----------------------------
+    fn reprice_pegged_orders(&mut self) {
+        self.pegged.retain(|order_id| self.orders.contains_key(order_id));
+        if self.pegged.is_empty() {
+            return;
+        }
+
+        let best_bid = self.best_unpegged_price(Side::Buy);
+        let best_ask = self.best_unpegged_price(Side::Sell);
 
-        if !self.asks.is_empty() {
-            let mut should_cancel = false;
-            let mut order_id = 0;
-            {
-                let (_, asks) = self.asks.iter().next().unwrap();
-                let ask = asks.front().unwrap().borrow();
-                should_cancel = ask.get_order_type() == OrderType::FillAndKill;
-                order_id = ask.get_order_id();
+        for order_id in self.pegged.clone() {
+            let order = self.orders.get(&order_id).unwrap().order.clone();
+            let (side, old_price, peg) = {
+                let order = order.borrow();
+                (order.get_side(), order.get_price(), order.get_peg().unwrap())
+            };
+
+            let Some(new_price) = peg.get_pegged_price(side, best_bid, best_ask) else {
+                continue;
+            };
+            if new_price == old_price || self.can_match(side, new_price) {
+                continue;
+            }
+
+            self.remove_from_level(side, old_price, order_id);
+            order.borrow_mut().reprice(new_price);
+            self.insert_order(&order);
+
+            self.events.push(OrderEvent::Modified {
+                order_id,
+                side,
+                old_price,
+                new_price,
+                remaining_quantity: order.borrow().get_remaining_quantity(),
+            });
+        }
+    }
+
+    // Reacts to the first `count` trades of a single add_order,
+    // linked orders first so a filled OCO leg cancels its stop sibling before the same prints can trigger it.
+    fn process_trades(&mut self, trades: &mut Trades, count: usize) {
+        self.process_groups(trades, count);
+        self.process_triggers(trades, count);
+    }
+
+    fn process_groups(&mut self, trades: &mut Trades, count: usize) {
+        let mut filled_order_ids = Vec::with_capacity(count * 2);
+        for trade in &trades[..count] {
+            filled_order_ids.push(trade.get_bid_trade().order_id);
+            filled_order_ids.push(trade.get_ask_trade().order_id);
+        }
+
+        for order_id in filled_order_ids {
+            let group_trades = self.on_order_filled(order_id);
+            trades.extend(group_trades);
+        }
+    }
+
+    // Prints are fed to the trigger book one by one and the stops they
+    // trigger are sent to add_order, their trades are appended to `trades`.
+    fn process_triggers(&mut self, trades: &mut Trades, count: usize) {
+        let mut triggered = Vec::new();
+        for trade in &trades[..count] {
+            let price = trade.get_price();
+            self.last_trade_price = Some(price);
+            triggered.extend(self.triggers.on_trade(price));
+        }
+
+        for order in triggered {
+            if let Some(triggered_trades) = self.place_activated_order(order) {
+                trades.extend(triggered_trades);
+            }
+        }
+    }
+
+    // A linked order traded (partially or fully).
+    fn on_order_filled(&mut self, order_id: OrderId) -> Trades {
+        let Some(group_id) = self.groups.get_group_id(order_id) else {
+            return Vec::new();
+        };
+
+        match self.groups.get(group_id).unwrap() {
+            OrderGroup::OneCancelsOther { first, second } => {
+                let other_order_id = if *first == order_id { *second } else { *first };
+                self.dissolve_group(group_id);
+                self.cancel_linked_order(other_order_id);
+                Vec::new()
             }
-            if should_cancel {
-                self.cancel_order(order_id);
+            OrderGroup::Bracket { entry, .. } => {
+                if entry.borrow().isfilled() {
+                    self.activate_bracket(group_id, None)
+                } else {
+                    Vec::new()
+                }
             }
         }
+    }
 
+    // A linked order left (or never entered) the book without being completely filled.
+    // - OCO: the other order is cancelled.
+    // - Bracket entry: take-profit and stop-loss are activated for the quantity
+    //   the entry got filled for, the whole group is dropped if it didn't fill at all.
+    // - Bracket take-profit or stop-loss (not activated yet): the entry is cancelled as well.
+    fn on_order_closed(&mut self, order_id: OrderId) -> Trades {
+        let Some(group_id) = self.groups.get_group_id(order_id) else {
+            return Vec::new();
+        };
+
+        match self.groups.get(group_id).unwrap() {
+            OrderGroup::OneCancelsOther { first, second } => {
+                let other_order_id = if *first == order_id { *second } else { *first };
+                self.dissolve_group(group_id);
+                self.cancel_linked_order(other_order_id);
+                Vec::new()
+            }
+            OrderGroup::Bracket { entry, .. } => {
+                let (entry_order_id, filled_quantity) = {
+                    let entry = entry.borrow();
+                    (entry.get_order_id(), entry.get_filled_quantity())
+                };
+                if entry_order_id == order_id && filled_quantity > 0 {
+                    return self.activate_bracket(group_id, Some(filled_quantity));
+                }
+                self.dissolve_group(group_id);
+                self.cancel_linked_order(entry_order_id);
+                Vec::new()
+            }
+        }
+    }
+
+    // Turns a bracket into its OCO pair and adds both legs,
+    // `quantity` resizes the legs when the entry was only partially filled.
+    fn activate_bracket(&mut self, group_id: GroupId, quantity: Option<Quantity>) -> Trades {
+        let Some(OrderGroup::Bracket { take_profit, stop_loss, .. }) = self.groups.remove(group_id) else {
+            return Vec::new();
+        };
+
+        if let Some(quantity) = quantity {
+            for leg in [&take_profit, &stop_loss] {
+                let mut order = leg.get_order().borrow_mut();
+                let (side, price) = (order.get_side(), order.get_price());
+                order.amend(side, price, quantity);
+            }
+        }
+
+        let first = take_profit.get_order().borrow().get_order_id();
+        let second = stop_loss.get_order().borrow().get_order_id();
+        self.groups.insert(group_id, OrderGroup::OneCancelsOther { first, second });
+
+        // stop-loss goes first, so a take-profit filling right away can cancel it.
+        // A leg the book refuses (e.g. its account is disabled) takes the other one along.
+        let Some(mut trades) = self.activate_order_leg(stop_loss) else {
+            self.dissolve_group(group_id);
+            return Vec::new();
+        };
+        if self.groups.contains(group_id) {
+            match self.activate_order_leg(take_profit) {
+                Some(take_profit_trades) => trades.extend(take_profit_trades),
+                None => {
+                    self.dissolve_group(group_id);
+                    self.cancel_linked_order(second);
+                }
+            }
+        }
         trades
     }
 
+    // Removes a group for good, its orders still around aren't linked to anything anymore.
+    fn dissolve_group(&mut self, group_id: GroupId) -> Option<OrderGroup> {
+        let group = self.groups.remove(group_id)?;
+        let members = match &group {
+            OrderGroup::OneCancelsOther { first, second } => [*first, *second].into_iter().filter_map(|order_id| self.get_order(order_id)).collect(),
+            OrderGroup::Bracket { entry, take_profit, stop_loss } => vec![entry.clone(), take_profit.get_order().clone(), stop_loss.get_order().clone()],
+        };
+        for order in members {
+            order.borrow_mut().clear_group_id();
+        }
+        Some(group)
+    }
+
+    fn add_order_leg(&mut self, leg: OrderLeg) -> Option<Trades> {
+        match leg {
+            OrderLeg::Limit(order) => self.place_order(order),
+            OrderLeg::Stop(order, trigger) => self.place_stop_order(order, trigger),
+        }
+    }
+
+    // add_order_leg for a bracket leg, a limit leg emits an `OrderEvent::Activated`.
+    fn activate_order_leg(&mut self, leg: OrderLeg) -> Option<Trades> {
+        match leg {
+            OrderLeg::Limit(order) => self.place_activated_order(order),
+            OrderLeg::Stop(order, trigger) => self.place_stop_order(order, trigger),
+        }
+    }
+
+    // place_order for a triggered stop or an activated bracket leg, emits an `OrderEvent::Activated`
+    // unless the book refused it.
+    fn place_activated_order(&mut self, order: OrderPointer) -> Option<Trades> {
+        let (order_id, side) = {
+            let order = order.borrow();
+            (order.get_order_id(), order.get_side())
+        };
+        let trades = self.place_order(order)?;
+        self.events.push(OrderEvent::Activated { order_id, side });
+        Some(trades)
+    }
+
+    // remove_order for the linked order of an order that traded or left the book,
+    // emits an `OrderEvent::Cancelled` when there was such an order.
+    fn cancel_linked_order(&mut self, order_id: OrderId) {
+        if let Some(order) = self.get_order(order_id) {
+            let order = order.borrow();
+            self.events.push(OrderEvent::Cancelled {
+                order_id,
+                side: order.get_side(),
+                price: order.get_price(),
+                remaining_quantity: order.get_remaining_quantity(),
+            });
+        }
+        self.remove_order(order_id);
+    }
+
+    fn is_known_order(&self, order_id: OrderId) -> bool {
+        self.orders.contains_key(&order_id)
+            || self.triggers.contains(order_id)
+            || self.groups.get_group_id(order_id).is_some()
+    }
+
+    // Removes a resting or stop order without reacting to it (no linked order is touched),
+    // returns false when there's no such order.
+    fn remove_order(&mut self, order_id: OrderId) -> bool {
+        let removed = self.detach_order(order_id);
+        if removed {
+            self.reprice_pegged_orders();
+        }
+        removed
+    }
+
+    // remove_order without re-pricing pegged orders, for removing many orders at once.
+    fn detach_order(&mut self, order_id: OrderId) -> bool {
+        if self.triggers.remove(order_id).is_some() {
+            return true;
+        }
+
+        if !self.orders.contains_key(&order_id){
+            return false;
+        } 
+
+        let (_, order_entry) = self.orders.remove_entry(&order_id).unwrap(); 
+        let (side, price) = {
+            let order = order_entry.order.borrow();
+            (order.get_side(), order.get_price())
+        };
+        self.remove_from_level(side, price, order_id);
+        true
+    }
+
+    fn is_account_disabled(&self, order: &Order) -> bool {
+        order.get_account_id().is_some_and(|account_id| self.disabled_accounts.contains(&account_id))
+    }
+
     pub fn new() -> Self {
-        Self { orders: HashMap::new() , bids: BTreeMap::new(), asks: BTreeMap::new() }
+        Self::with_policy(Box::new(Fifo))
+    }
+
+    // Matching policy is chosen per instrument, `new` is price-time FIFO.
+    pub fn with_policy(policy: Box<dyn MatchingPolicy>) -> Self {
+        Self {
+            orders: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            pegged: Vec::new(),
+            events: Vec::new(),
+            triggers: TriggerBook::new(),
+            last_trade_price: None,
+            groups: OrderGroups::new(),
+            policy,
+            trade_ids: Rc::new(Cell::new(1)),
+            fee_model: FeeModel::default(),
+            fee_accumulator: FeeAccumulator::new(),
+            disabled_accounts: BTreeSet::new(),
+            halted: false,
+            clock: Rc::new(SystemClock),
+        }
     }
 
-    pub fn add_order(&mut self, order:OrderPointer) -> Option<Trades> {
-        if self.orders.contains_key(&order.borrow().get_order_id()){
+    // Refused while the book is halted.
+    pub fn add_order(&mut self, order: OrderPointer) -> Option<Trades> {
+        if self.halted {
+            return None;
+        }
+        self.place_order(order)
+    }
+
+    // add_order without the halt check, for orders the book itself activates
+    // (triggered stops, bracket legs) which keep working during a halt.
+    fn place_order(&mut self, order:OrderPointer) -> Option<Trades> {
+        let order_id = order.borrow().get_order_id();
+        if self.orders.contains_key(&order_id) || self.triggers.contains(order_id) {
             return None;
         } 
+        if self.is_account_disabled(&order.borrow()) {
+            return None;
+        }
 
-        if order.borrow().get_order_type() != OrderType::Market && order.borrow().get_price() == NAN {
+        let order_type = order.borrow().get_order_type();
+        let peg = order.borrow().get_peg();
+        if let Some(peg) = peg {
+            // only resting limit orders can be pegged
+            if order_type != OrderType::GoodTillCancel {
+                return None;
+            }
+            let side = order.borrow().get_side();
+            let best_bid = self.best_unpegged_price(Side::Buy);
+            let best_ask = self.best_unpegged_price(Side::Sell);
+            // if there's no reference yet the order enters at its own price
+            if let Some(price) = peg.get_pegged_price(side, best_bid, best_ask) {
+                order.borrow_mut().reprice(price);
+            }
+            // pegged orders only ever rest, one that would trade on entry is refused
+            if self.can_match(side, order.borrow().get_price()) {
+                return None;
+            }
+        }
+
+        if !matches!(order_type, OrderType::Market | OrderType::MarketToLimit) && order.borrow().get_price().is_nan() {
             return None;
         }
-        
-        if order.borrow().get_order_type() == OrderType::Market {
-            if order.borrow().get_side() == Side::Buy {
-                let mut trades: Trades = Vec::with_capacity(self.orders.len());
-                while !self.asks.is_empty() {
-                    if order.borrow().get_remaining_quantity() == 0 {
-                        break;
-                    }
-                    {
-                        let (_, asks) = self.asks.iter().next().unwrap();
-                        let mut best_ask = asks.front().unwrap().borrow_mut();
-                        let quantity = min(best_ask.get_remaining_quantity(), order.borrow().get_remaining_quantity());
-                        best_ask.fill(quantity);
-                        order.borrow_mut().fill(quantity);
-
-                        let bid_trade = TradeInfo::new(order.borrow().get_order_id(), best_ask.get_price(), quantity);
-                        let ask_trade = TradeInfo::new(best_ask.get_order_id(), best_ask.get_price(), quantity);
-                        trades.push(Trade::new(bid_trade, ask_trade));
-
-                        if best_ask.isfilled() {
-                            self.orders.remove(&best_ask.get_order_id());
-                        }
-                    }
-                    let mut is_level_empty = false;
-                    {
-                        let (_, asks) = self.asks.iter_mut().next().unwrap();
-                        if asks.front().unwrap().borrow().isfilled() {
-                            asks.pop_front();
-                        }
-                        if asks.is_empty() {
-                            is_level_empty = true;
-                        }
-                    }
-                    let best_ask_price = *self.asks.keys().next().unwrap();
-                    if is_level_empty {
-                        self.asks.remove(&best_ask_price);
+
+        if order_type == OrderType::FillAndKill && !self.can_match(order.borrow().get_side(), order.borrow().get_price()) {
+            return None;
+        }
+
+        let mut trades = self.match_orders(&order);
+
+        // MarketToLimit is refused when nothing traded at the best level,
+        // otherwise what's left rests there from now on as a GoodTillCancel order.
+        let order_type = if order_type == OrderType::MarketToLimit {
+            let last_price = trades.last()?.get_price();
+            order.borrow_mut().to_good_till_cancel(last_price);
+            OrderType::GoodTillCancel
+        } else {
+            order_type
+        };
+
+        // What's left still crossing the opposite side can't trade with the orders there
+        // (its own or their all or none / minimum quantity), resting it would cross the book:
+        // the order is refused when nothing traded, its remainder is cancelled otherwise.
+        // Matching already walked past the ineligible orders (to deeper levels within the limit),
+        // so while a resting all or none / minimum quantity order sits at a price, smaller
+        // flow crossing it only gets what the eligible orders can give and never rests there.
+        let crosses = {
+            let order = order.borrow();
+            !order.isfilled() && order_type == OrderType::GoodTillCancel && self.can_match(order.get_side(), order.get_price())
+        };
+        if crosses && trades.is_empty() {
+            return None;
+        }
+
+        if !order.borrow().isfilled() && !crosses {
+            match order_type {
+                OrderType::GoodTillCancel => {
+                    self.insert_order(&order);
+                    if peg.is_some() {
+                        self.pegged.push(order_id);
                     }
                 }
-                return Some(trades);
-            } else {
-                let mut trades: Trades = Vec::with_capacity(self.orders.len());
-                while !self.bids.is_empty() {
-                    if order.borrow().get_remaining_quantity() == 0 {
-                        break;
-                    }
-                    {
-                        let (_, bids) = self.bids.iter().next().unwrap();
-                        let mut best_bid = bids.front().unwrap().borrow_mut();
-                        let quantity = min(best_bid.get_remaining_quantity(), order.borrow().get_remaining_quantity());
-                        best_bid.fill(quantity);
-                        order.borrow_mut().fill(quantity);
-
-                        let bid_trade = TradeInfo::new(order.borrow().get_order_id(), best_bid.get_price(), quantity);
-                        let ask_trade = TradeInfo::new(best_bid.get_order_id(), best_bid.get_price(), quantity);
-                        trades.push(Trade::new(bid_trade, ask_trade));
-
-                        if best_bid.isfilled() {
-                            self.orders.remove(&best_bid.get_order_id());
-                        }
-                    }
+                // FillAndKill and Market remaining quantity is cancelled,
+                // MarketToLimit became GoodTillCancel above
+                OrderType::FillAndKill | OrderType::Market | OrderType::MarketToLimit => {}
+            }
+        }
 
-                    let mut is_level_empty = false;
-                    {
-                        let (_, bids) = self.bids.iter_mut().next().unwrap();
-                        if bids.front().unwrap().borrow().isfilled() {
-                            bids.pop_front();
-                        }
-                        if bids.is_empty() {
-                            is_level_empty = true;
-                        }
-                    }
+        let closed = !order.borrow().isfilled() && !self.orders.contains_key(&order_id);
+        self.reprice_pegged_orders();
+        let count = trades.len();
+        self.process_trades(&mut trades, count);
+        if closed {
+            trades.extend(self.on_order_closed(order_id));
+        }
+        Some(trades)
+    }
 
-                    let best_bid_price = *self.bids.keys().next().unwrap();
-                    if is_level_empty {
-                        self.bids.remove(&best_bid_price);
-                    }
+    // Holds `order` in the trigger book until the market trades through its trigger,
+    // then it's sent to add_order as is (Market for a stop, GoodTillCancel for a stop limit).
+    // A stop whose trigger is already reached fires right away.
+    // Refused while the book is halted.
+    pub fn add_stop_order(&mut self, order: OrderPointer, trigger: Trigger) -> Option<Trades> {
+        if self.halted {
+            return None;
+        }
+        self.place_stop_order(order, trigger)
+    }
+
+    fn place_stop_order(&mut self, order: OrderPointer, trigger: Trigger) -> Option<Trades> {
+        let order_id = order.borrow().get_order_id();
+        if self.orders.contains_key(&order_id) || self.triggers.contains(order_id) {
+            return None;
+        }
+        if self.is_account_disabled(&order.borrow()) {
+            return None;
+        }
+
+        if !self.triggers.insert(order, trigger, self.last_trade_price) {
+            return None;
+        }
+
+        let mut trades: Trades = Vec::new();
+        if let Some(last_price) = self.last_trade_price {
+            for order in self.triggers.on_trade(last_price) {
+                if let Some(triggered_trades) = self.place_activated_order(order) {
+                    trades.extend(triggered_trades);
                 }
-                return Some(trades);
             }
         }
+        Some(trades)
+    }
 
-        if order.borrow().get_order_type() == OrderType::FillAndKill && !self.can_match(order.borrow().get_side(), order.borrow().get_price()) {
+    // Adds two linked orders, as soon as one of them trades or is cancelled the other is cancelled.
+    // If the first order fills completely right away the second one is never added,
+    // if the second one is rejected the first one is cancelled.
+    pub fn add_oco_orders(&mut self, first: OrderLeg, second: OrderLeg) -> Option<(GroupId, Trades)> {
+        if self.halted {
             return None;
         }
-        
-        let mut order_index:usize = 0;
-        if order.borrow().get_side() == Side::Buy {
-            let orders = self.bids.entry(Reverse(order.borrow().get_price())).or_default();
-            orders.push_back(order.clone());
-            order_index = orders.len() - 1;
-        } else {
-            let orders = self.asks.entry(order.borrow().get_price()).or_default();
-            orders.push_back(order.clone());
-            order_index = orders.len() - 1;
+        let first_order_id = first.get_order().borrow().get_order_id();
+        let second_order_id = second.get_order().borrow().get_order_id();
+        if first_order_id == second_order_id || self.is_known_order(first_order_id) || self.is_known_order(second_order_id) {
+            return None;
         }
-        
-        self.orders.insert(
-            order.borrow().get_order_id(), 
-            OrderEntry { 
-                order: order.clone(), 
-                location_index: order_index 
+
+        let group_id = self.groups.add(OrderGroup::OneCancelsOther { first: first_order_id, second: second_order_id });
+        first.get_order().borrow_mut().set_group_id(group_id);
+        second.get_order().borrow_mut().set_group_id(group_id);
+
+        let Some(mut trades) = self.add_order_leg(first) else {
+            self.dissolve_group(group_id);
+            return None;
+        };
+        if self.groups.contains(group_id) {
+            match self.add_order_leg(second) {
+                Some(second_trades) => trades.extend(second_trades),
+                None => {
+                    self.dissolve_group(group_id);
+                    self.remove_order(first_order_id);
+                }
             }
-        );
+        }
+        Some((group_id, trades))
+    }
 
-        // let orderlevelinfos= self.get_orderlevelinfos();
-        // let bidslevel = orderlevelinfos.get_bids();
-        // let askslevel = orderlevelinfos.get_asks();
-        
-        // println!("Bids:");
-        // let lvl = 0;
-        // for bidlvl in bidslevel {
-        //     println!("Level: {} -> Price: {}, Quantity: {}", lvl, bidlvl.price, bidlvl.quantity);
-        // };
+    // Adds `entry` to the book, take_profit and stop_loss are held until entry is completely filled
+    // and then added as an OCO pair. If entry is cancelled after a partial fill the legs are activated
+    // for the filled quantity, cancelling a leg before activation cancels the entry.
+    pub fn add_bracket_order(&mut self, entry: OrderPointer, take_profit: OrderLeg, stop_loss: OrderLeg) -> Option<(GroupId, Trades)> {
+        if self.halted {
+            return None;
+        }
+        let order_ids = [
+            entry.borrow().get_order_id(),
+            take_profit.get_order().borrow().get_order_id(),
+            stop_loss.get_order().borrow().get_order_id(),
+        ];
+        if order_ids[0] == order_ids[1] || order_ids[0] == order_ids[2] || order_ids[1] == order_ids[2] {
+            return None;
+        }
+        if order_ids.iter().any(|order_id| self.is_known_order(*order_id)) {
+            return None;
+        }
 
-        // println!();
-        // println!("Asks:");
-        // let lvl = 0;
-        // for asklvl in askslevel {
-        //     println!("Level: {} -> Price: {}, Quantity: {}", lvl, asklvl.price, asklvl.quantity);
-        // };
+        let members = [entry.clone(), take_profit.get_order().clone(), stop_loss.get_order().clone()];
+        let group_id = self.groups.add(OrderGroup::Bracket { entry: entry.clone(), take_profit, stop_loss });
+        for order in members {
+            order.borrow_mut().set_group_id(group_id);
+        }
 
-        return Some(self.match_orders());
+        let Some(trades) = self.place_order(entry) else {
+            self.dissolve_group(group_id);
+            return None;
+        };
+        Some((group_id, trades))
     }
 
-    pub fn cancel_order(&mut self, order_id:OrderId) {
-        
-        if !self.orders.contains_key(&order_id){
-            return ;
-        } 
+    // Cancelling a linked order cancels (or activates) the rest of its group,
+    // returned trades are from bracket legs activated by the cancel.
+    pub fn cancel_order(&mut self, order_id:OrderId) -> Trades {
+        self.remove_order(order_id);
+        self.on_order_closed(order_id)
+    }
 
-        let (_, order_entry) = self.orders.remove_entry(&order_id).unwrap(); 
-        if order_entry.order.borrow().get_side() == Side::Sell {
-            let orders = self.asks.get_mut(&order_entry.order.borrow().get_price()).unwrap();
-            orders.remove(order_entry.location_index);
+    // Refused while the book is halted, the order stays as it is.
+    // A linked order stays in its group, a bracket entry only until it first trades
+    // (its legs are sized on what it filled). If the book refuses the replacement
+    // the order is gone and its group reacts like to a cancel.
+    pub fn modify_order(&mut self, order: OrderModify) -> Option<Trades> {
+        if self.halted || !(self.orders.contains_key(&order.get_order_id())) {
+            return None;
+        }
 
-            // if all orders at a price level are matched, remove that level
-            if orders.is_empty() {
-                self.asks.remove(&order_entry.order.borrow().get_price());
+        let order_id = order.get_order_id();
+        let (_, order_entry) = self.orders.get_key_value(&order_id).unwrap();
+        if self.is_account_disabled(&order_entry.order.borrow()) {
+            return None;
+        }
+        let group_id = self.groups.get_group_id(order_id);
+        let is_bracket_entry = group_id.is_some_and(|group_id| matches!(self.groups.get(group_id), Some(OrderGroup::Bracket { .. })));
+        if is_bracket_entry && order_entry.order.borrow().get_filled_quantity() > 0 {
+            return None;
+        }
+        let modified_order = order.to_order_pointer(&order_entry.order.borrow());
+
+        self.remove_order(order_id);
+        if let Some(group_id) = group_id
+            && is_bracket_entry
+        {
+            self.groups.replace_entry(group_id, modified_order.clone());
+        }
+
+        let trades = self.place_order(modified_order);
+        if trades.is_none() {
+            self.on_order_closed(order_id);
+        }
+        trades
+    }
+
+    // Resting and stop orders matching `filter`, resting ones in price-time priority
+    // (bids then asks), stop ones in firing order.
+    fn find_orders(&self, filter: &MassCancelFilter) -> Vec<OrderId> {
+        let mut order_ids = Vec::new();
+        let mut collect = |level: &PriceLevel| {
+            for order in level.iter() {
+                let order = order.borrow();
+                if filter.matches(&order) {
+                    order_ids.push(order.get_order_id());
+                }
             }
-        } else {
-            let orders = self.bids.get_mut(&Reverse(order_entry.order.borrow().get_price())).unwrap();
-            orders.remove(order_entry.location_index);
+        };
 
-            // if all orders at a price level are matched, remove that level
-            if orders.is_empty() {
-                self.bids.remove(&Reverse(order_entry.order.borrow().get_price()));
+        let (min_price, max_price) = filter.get_price_range().unwrap_or((Price::from(f32::MIN), Price::from(f32::MAX)));
+        if min_price <= max_price {
+            if filter.get_side() != Some(Side::Sell) {
+                self.bids.range(Reverse(max_price)..=Reverse(min_price)).for_each(|(_, level)| collect(level));
+            }
+            if filter.get_side() != Some(Side::Buy) {
+                self.asks.range(min_price..=max_price).for_each(|(_, level)| collect(level));
             }
         }
+
+        for stop_order in self.triggers.iter() {
+            let order = stop_order.get_order().borrow();
+            if filter.matches(&order) {
+                order_ids.push(order.get_order_id());
+            }
+        }
+        order_ids
     }
 
-    pub fn modify_order(&mut self, order: OrderModify) -> Option<Trades> {
-        if !(self.orders.contains_key(&order.get_order_id())) {
-            return None;
+    // Cancels every resting and stop order matching `filter` in one go, each one emits an `OrderEvent::Cancelled`,
+    // linked orders react like they do to cancel_order once all of them are gone, except that a
+    // bracket entry whose pending take-profit and stop-loss match `filter` too takes them along
+    // instead of activating them (a disconnected session or disabled account gets no new orders).
+    // Returns the cancelled order ids and the trades of bracket legs activated by the cancels.
+    pub fn mass_cancel(&mut self, filter: &MassCancelFilter) -> (Vec<OrderId>, Trades) {
+        let order_ids = self.find_orders(filter);
+        for order_id in &order_ids {
+            if let Some(order) = self.get_order(*order_id) {
+                let order = order.borrow();
+                self.events.push(OrderEvent::Cancelled {
+                    order_id: *order_id,
+                    side: order.get_side(),
+                    price: order.get_price(),
+                    remaining_quantity: order.get_remaining_quantity(),
+                });
+            }
+            self.detach_order(*order_id);
+        }
+        self.reprice_pegged_orders();
+
+        let mut trades: Trades = Vec::new();
+        for order_id in &order_ids {
+            if !self.drop_pending_legs(*order_id, filter) {
+                trades.extend(self.on_order_closed(*order_id));
+            }
+        }
+        (order_ids, trades)
+    }
+
+    // Removes the bracket of `entry_order_id` when both its pending legs match `filter`,
+    // returns false (nothing done) otherwise.
+    fn drop_pending_legs(&mut self, entry_order_id: OrderId, filter: &MassCancelFilter) -> bool {
+        let Some(group_id) = self.groups.get_group_id(entry_order_id) else {
+            return false;
+        };
+        let Some(OrderGroup::Bracket { entry, take_profit, stop_loss }) = self.groups.get(group_id) else {
+            return false;
+        };
+        if entry.borrow().get_order_id() != entry_order_id || ![take_profit, stop_loss].iter().all(|leg| filter.matches(&leg.get_order().borrow())) {
+            return false;
         }
+        self.dissolve_group(group_id);
+        true
+    }
+
+    // Kill switch: cancels every order of the account and rejects its new orders
+    // (and modifies) until `enable_account` is called.
+    pub fn disable_account(&mut self, account_id: AccountId) -> (Vec<OrderId>, Trades) {
+        self.disabled_accounts.insert(account_id);
+        self.mass_cancel(&MassCancelFilter::new().with_account(account_id))
+    }
 
-        let (_, order_entry) = self.orders.get_key_value(&order.get_order_id()).unwrap();
-        let order_type_clone = order_entry.order.borrow().get_order_type();
-        
-        self.cancel_order(order.get_order_id());
-        
-        return self.add_order(order.to_order_pointer(order_type_clone));
+    pub fn enable_account(&mut self, account_id: AccountId) {
+        self.disabled_accounts.remove(&account_id);
+    }
+
+    pub fn is_account_enabled(&self, account_id: AccountId) -> bool {
+        !self.disabled_accounts.contains(&account_id)
+    }
+
+    // Trading halt: new orders, stop orders, linked orders and modifies are refused
+    // until `resume`, resting orders stay and can still be cancelled.
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Events collected since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<OrderEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Resting, stop or not yet activated bracket order.
+    pub fn get_order(&self, order_id: OrderId) -> Option<OrderPointer> {
+        if let Some(order_entry) = self.orders.get(&order_id) {
+            return Some(order_entry.order.clone());
+        }
+        if let Some(stop_order) = self.triggers.get(order_id) {
+            return Some(stop_order.get_order().clone());
+        }
+        self.groups.get_pending_order(order_id)
+    }
+
+    // Order resting on the book (displayed or hidden), not a stop or pending bracket order.
+    pub fn get_resting_order(&self, order_id: OrderId) -> Option<OrderPointer> {
+        self.orders.get(&order_id).map(|entry| entry.order.clone())
+    }
+
+    pub fn get_group_id(&self, order_id: OrderId) -> Option<GroupId> {
+        self.groups.get_group_id(order_id)
+    }
+
+    // Current trigger price of a stop order still waiting in the trigger book.
+    pub fn get_trigger_price(&self, order_id: OrderId) -> Option<Price> {
+        self.triggers.get_trigger_price(order_id)
+    }
+
+    // Trades are timestamped with `clock`, the default is the system clock.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn get_clock(&self) -> Rc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    // Books sharing a counter hand out trade ids unique across all of them,
+    // a book on its own starts at 1.
+    pub fn set_trade_ids(&mut self, trade_ids: TradeIds) {
+        self.trade_ids = trade_ids;
+    }
+
+    pub fn get_trade_ids(&self) -> TradeIds {
+        self.trade_ids.clone()
+    }
+
+    // Fees of the trades from now on, the default model charges nothing.
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    pub fn get_fee_model(&self) -> &FeeModel {
+        &self.fee_model
+    }
+
+    // Fees (net of rebates) charged to `account_id` since the last reset.
+    pub fn get_accumulated_fees(&self, account_id: AccountId) -> Amount {
+        self.fee_accumulator.get_fees(account_id)
+    }
+
+    pub fn reset_accumulated_fees(&mut self, account_id: AccountId) -> Amount {
+        self.fee_accumulator.reset(account_id)
+    }
+
+    pub fn reset_all_accumulated_fees(&mut self) {
+        self.fee_accumulator.reset_all();
+    }
+
+    pub fn get_last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
     }
 
     pub fn size(&self) -> usize {
-        return self.orders.len();
+        self.orders.len()
+    }
+
+    // Resting orders by order id, displayed and hidden.
+    pub fn iter_resting_orders(&self) -> impl Iterator<Item = &OrderPointer> {
+        self.orders.values().map(|entry| &entry.order)
+    }
+
+    // ----------------------------
+    // Snapshots hold everything but the matching policy and clock (the book restored into keeps its own)
+    // and pending events (take them before snapshotting).
+    // Resting orders are written in price-time priority, displayed before hidden per level,
+    // so inserting them back in that order rebuilds every queue position.
+    // ----------------------------
+    pub fn write_snapshot(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        self.encode_state(&mut encoder);
+        seal(SnapshotKind::Book, encoder.get_bytes())
+    }
+
+    // Replaces the whole state of the book with the snapshot's,
+    // the book is left as it was when the snapshot can't be read.
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut decoder = Decoder::new(unseal(SnapshotKind::Book, bytes)?);
+        let book = Self::decode_state(&mut decoder)?;
+        if !decoder.is_finished() {
+            return Err(SnapshotError::TrailingBytes(decoder.get_remaining()));
+        }
+        self.replace_state(book);
+        Ok(())
+    }
+
+    // Takes the state of `book` (decoded from a snapshot), keeps this book's matching policy and clock.
+    // The trade id counter is kept too (it may be shared) but moved to the snapshot's next id.
+    pub fn replace_state(&mut self, mut book: OrderBook) {
+        book.policy = std::mem::replace(&mut self.policy, Box::new(Fifo));
+        book.clock = self.clock.clone();
+        self.trade_ids.set(book.trade_ids.get());
+        book.trade_ids = self.trade_ids.clone();
+        *self = book;
+    }
+
+    // Stable hash of the whole state (what a snapshot holds), two books that went through
+    // the same commands with the same clock readings hash the same on any machine.
+    pub fn state_hash(&self) -> u64 {
+        let mut encoder = Encoder::new();
+        self.encode_state(&mut encoder);
+        fnv1a64(encoder.get_bytes())
+    }
+
+    pub fn encode_state(&self, encoder: &mut Encoder) {
+        fn put_leg(encoder: &mut Encoder, leg: &OrderLeg) {
+            match leg {
+                OrderLeg::Limit(order) => {
+                    encoder.put_u8(0);
+                    encoder.put_order(&order.borrow());
+                }
+                OrderLeg::Stop(order, trigger) => {
+                    encoder.put_u8(1);
+                    encoder.put_order(&order.borrow());
+                    encoder.put_trigger(*trigger);
+                }
+            }
+        }
+
+        encoder.put_u64(self.trade_ids.get());
+        encoder.put_option(self.last_trade_price, Encoder::put_price);
+
+        encoder.put_u32(self.orders.len() as u32);
+        let levels = self.bids.values().chain(self.asks.values());
+        for order in levels.flat_map(|level| level.iter()) {
+            encoder.put_order(&order.borrow());
+        }
+
+        encoder.put_u32(self.pegged.len() as u32);
+        for order_id in &self.pegged {
+            encoder.put_u64(*order_id);
+        }
+
+        encoder.put_u32(self.triggers.size() as u32);
+        for stop_order in self.triggers.iter() {
+            encoder.put_order(&stop_order.get_order().borrow());
+            encoder.put_trigger(stop_order.get_trigger());
+            encoder.put_price(stop_order.get_trigger_price());
+        }
+
+        encoder.put_u64(self.groups.get_next_group_id());
+        encoder.put_u32(self.groups.size() as u32);
+        for (group_id, group) in self.groups.iter() {
+            encoder.put_u64(group_id);
+            match group {
+                OrderGroup::OneCancelsOther { first, second } => {
+                    encoder.put_u8(0);
+                    encoder.put_u64(*first);
+                    encoder.put_u64(*second);
+                }
+                OrderGroup::Bracket { entry, take_profit, stop_loss } => {
+                    encoder.put_u8(1);
+                    encoder.put_u64(entry.borrow().get_order_id());
+                    put_leg(encoder, take_profit);
+                    put_leg(encoder, stop_loss);
+                }
+            }
+        }
+
+        encoder.put_fee_model(&self.fee_model);
+        let fees = self.fee_accumulator.get_all_fees();
+        encoder.put_u32(fees.len() as u32);
+        for (account_id, fees) in fees {
+            encoder.put_u64(account_id);
+            encoder.put_f64(fees.into_inner());
+        }
+
+        encoder.put_u32(self.disabled_accounts.len() as u32);
+        for account_id in &self.disabled_accounts {
+            encoder.put_u64(*account_id);
+        }
+        encoder.put_bool(self.halted);
+    }
+
+    // A book holding the decoded state, with a FIFO policy and the system clock
+    // until `replace_state` moves the state into the book it belongs to.
+    pub fn decode_state(decoder: &mut Decoder) -> Result<OrderBook, SnapshotError> {
+        fn get_leg(decoder: &mut Decoder) -> Result<OrderLeg, SnapshotError> {
+            match decoder.get_u8()? {
+                0 => Ok(OrderLeg::Limit(Rc::new(RefCell::new(decoder.get_order()?)))),
+                1 => Ok(OrderLeg::Stop(Rc::new(RefCell::new(decoder.get_order()?)), decoder.get_trigger()?)),
+                tag => Err(DecodeError::InvalidTag(tag).into()),
+            }
+        }
+
+        let mut book = Self::new();
+        book.trade_ids.set(decoder.get_u64()?);
+        book.last_trade_price = decoder.get_option(Decoder::get_price)?;
+
+        for _ in 0..decoder.get_u32()? {
+            let order = Rc::new(RefCell::new(decoder.get_order()?));
+            book.insert_order(&order);
+        }
+
+        for _ in 0..decoder.get_u32()? {
+            let order_id = decoder.get_u64()?;
+            if !book.orders.contains_key(&order_id) {
+                return Err(SnapshotError::UnknownOrder(order_id));
+            }
+            book.pegged.push(order_id);
+        }
+
+        for _ in 0..decoder.get_u32()? {
+            let order = Rc::new(RefCell::new(decoder.get_order()?));
+            let trigger = decoder.get_trigger()?;
+            let trigger_price = decoder.get_price()?;
+            book.triggers.restore(order, trigger, trigger_price);
+        }
+
+        book.groups.set_next_group_id(decoder.get_u64()?);
+        for _ in 0..decoder.get_u32()? {
+            let group_id = decoder.get_u64()?;
+            let group = match decoder.get_u8()? {
+                0 => OrderGroup::OneCancelsOther { first: decoder.get_u64()?, second: decoder.get_u64()? },
+                1 => {
+                    let entry_order_id = decoder.get_u64()?;
+                    let Some(entry) = book.orders.get(&entry_order_id) else {
+                        return Err(SnapshotError::UnknownOrder(entry_order_id));
+                    };
+                    let entry = entry.order.clone();
+                    OrderGroup::Bracket { entry, take_profit: get_leg(decoder)?, stop_loss: get_leg(decoder)? }
+                }
+                tag => return Err(DecodeError::InvalidTag(tag).into()),
+            };
+            book.groups.insert(group_id, group);
+        }
+
+        book.fee_model = decoder.get_fee_model()?;
+        for _ in 0..decoder.get_u32()? {
+            let account_id = decoder.get_u64()?;
+            book.fee_accumulator.add_fees(account_id, Amount::from(decoder.get_f64()?));
+        }
+
+        for _ in 0..decoder.get_u32()? {
+            book.disabled_accounts.insert(decoder.get_u64()?);
+        }
+        book.halted = decoder.get_bool()?;
+        Ok(book)
     }
 
     pub fn get_orderlevelinfos(&self) -> OrderbookLevelInfos {
         let mut bid_infos = Vec::with_capacity(self.orders.len());
         let mut ask_infos = Vec::with_capacity(self.orders.len());
 
-        fn create_level_info(price: Price, orders: &OrderPointers) -> LevelInfo {
-            let mut quantity = 0;
-            for order in orders.iter() {
-                quantity += order.borrow().get_remaining_quantity();
-            }
-            LevelInfo::new(price, quantity)
+        // hidden orders are never displayed, a level holding only hidden orders doesn't show up
+        fn create_level_info(price: Price, level: &PriceLevel) -> Option<LevelInfo> {
+            let quantity = level.get_displayed_quantity();
+            (quantity > 0).then(|| LevelInfo::new(price, quantity))
+        }
+
+        for (Reverse(price), level) in &self.bids {
+            bid_infos.extend(create_level_info(*price, level));
+        }
+
+        for (price, level) in &self.asks {
+            ask_infos.extend(create_level_info(*price, level));
         }
 
-        for (Reverse(price), orders) in &self.bids {
-            bid_infos.push(create_level_info(*price, &orders));
+        OrderbookLevelInfos::new(bid_infos, ask_infos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(order_id: OrderId, side: Side, price: f32, quantity: Quantity) -> Order {
+        Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity)
+    }
+
+    fn add(book: &mut OrderBook, order: Order) -> Option<Trades> {
+        book.add_order(Rc::new(RefCell::new(order)))
+    }
+
+    fn fills(trades: &Trades) -> Vec<(OrderId, Quantity)> {
+        trades.iter().map(|trade| (trade.get_passive_order_id(), trade.get_quantity())).collect()
+    }
+
+    fn remaining(book: &OrderBook, order_id: OrderId) -> Option<Quantity> {
+        book.get_order(order_id).map(|order| order.borrow().get_remaining_quantity())
+    }
+
+    // sells at 100, in time priority: all or none 10, minimum quantity 5 (of 8), plain 4
+    fn mixed_queue() -> OrderBook {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 10).with_all_or_none()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 100.0, 8).with_min_quantity(5)).unwrap();
+        add(&mut book, limit(3, Side::Sell, 100.0, 4)).unwrap();
+        book
+    }
+
+    #[test]
+    fn mixed_queue_skips_all_or_none_and_minimum_quantity() {
+        let mut book = mixed_queue();
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 4)).unwrap();
+        assert_eq!(fills(&trades), vec![(3, 4)]);
+        assert_eq!(remaining(&book, 1), Some(10));
+        assert_eq!(remaining(&book, 2), Some(8));
+        assert_eq!(remaining(&book, 3), None);
+    }
+
+    #[test]
+    fn mixed_queue_minimum_quantity_trades_once_reached() {
+        let mut book = mixed_queue();
+        // the all or none order is skipped, the minimum quantity order keeps its priority over the plain one
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 6)).unwrap();
+        assert_eq!(fills(&trades), vec![(2, 6)]);
+        assert_eq!(remaining(&book, 1), Some(10));
+        assert_eq!(remaining(&book, 2), Some(2));
+        assert_eq!(remaining(&book, 3), Some(4));
+    }
+
+    #[test]
+    fn mixed_queue_later_order_fills_past_skipped_ones() {
+        let mut book = mixed_queue();
+        // all or none fills completely, the 2 left are below the minimum quantity and go to the plain order
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 12)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 10), (3, 2)]);
+        assert_eq!(remaining(&book, 2), Some(8));
+        assert_eq!(remaining(&book, 3), Some(2));
+    }
+
+    #[test]
+    fn incoming_all_or_none_that_cant_fill_doesnt_rest_crossed() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 5)).unwrap();
+        assert!(add(&mut book, limit(2, Side::Buy, 101.0, 10).with_all_or_none()).is_none());
+        assert!(add(&mut book, limit(3, Side::Buy, 101.0, 10).with_min_quantity(6)).is_none());
+        assert!(book.get_order(2).is_none() && book.get_order(3).is_none());
+        assert_eq!(remaining(&book, 1), Some(5));
+
+        // not crossing, it rests
+        assert_eq!(add(&mut book, limit(4, Side::Buy, 99.0, 10).with_all_or_none()), Some(Vec::new()));
+        assert_eq!(remaining(&book, 4), Some(10));
+    }
+
+    #[test]
+    fn remainder_crossing_skipped_orders_is_cancelled() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 10).with_all_or_none()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 100.0, 3)).unwrap();
+        // trades 3 with the plain order, the 2 left can't trade with the all or none order in front
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 5)).unwrap();
+        assert_eq!(fills(&trades), vec![(2, 3)]);
+        assert_eq!(remaining(&book, 10), None);
+        assert_eq!(remaining(&book, 1), Some(10));
+        assert!(book.get_orderlevelinfos().get_bids().is_empty());
+    }
+
+    #[test]
+    fn pro_rata_book_shares_the_level() {
+        let mut book = OrderBook::with_policy(Box::new(ProRata::new(0)));
+        add(&mut book, limit(1, Side::Sell, 100.0, 30)).unwrap();
+        add(&mut book, limit(2, Side::Sell, 100.0, 10)).unwrap();
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 8)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 6), (2, 2)]);
+    }
+
+    fn market_to_limit(order_id: OrderId, side: Side, quantity: Quantity) -> Order {
+        Order::new(order_id, OrderType::MarketToLimit, side, OrderedFloat(f32::NAN), quantity)
+    }
+
+    #[test]
+    fn market_to_limit_takes_the_best_level_and_rests_there() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 3)).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 5)).unwrap();
+
+        let trades = add(&mut book, market_to_limit(10, Side::Buy, 6)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 3)]);
+        assert_eq!(remaining(&book, 2), Some(5));
+        let order = book.get_order(10).unwrap();
+        assert_eq!(order.borrow().get_order_type(), OrderType::GoodTillCancel);
+        assert_eq!((order.borrow().get_price(), order.borrow().get_remaining_quantity()), (OrderedFloat(100.0), 3));
+    }
+
+    #[test]
+    fn market_to_limit_without_a_fill_is_rejected() {
+        let mut book = OrderBook::new();
+        assert!(add(&mut book, market_to_limit(10, Side::Buy, 6)).is_none());
+
+        // the best level only has an all or none order it can't fill
+        add(&mut book, limit(1, Side::Sell, 100.0, 10).with_all_or_none()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 5)).unwrap();
+        assert!(add(&mut book, market_to_limit(11, Side::Buy, 6)).is_none());
+        assert_eq!(book.size(), 2);
+    }
+
+    #[test]
+    fn traded_oco_order_is_unlinked() {
+        let mut book = OrderBook::new();
+        let (group_id, _) = book.add_oco_orders(OrderLeg::Limit(Rc::new(RefCell::new(limit(1, Side::Sell, 101.0, 10)))), OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 105.0, 10))))).unwrap();
+        assert_eq!(book.get_order(1).unwrap().borrow().get_group_id(), Some(group_id));
+
+        add(&mut book, limit(3, Side::Buy, 101.0, 4)).unwrap();
+        assert_eq!(remaining(&book, 1), Some(6));
+        assert!(book.get_order(2).is_none());
+        assert_eq!(book.get_group_id(1), None);
+        assert_eq!(book.get_order(1).unwrap().borrow().get_group_id(), None);
+    }
+
+    #[test]
+    fn rejected_bracket_leg_dissolves_the_group() {
+        let mut book = OrderBook::new();
+        let entry = Rc::new(RefCell::new(limit(1, Side::Buy, 100.0, 10).with_account(7)));
+        let take_profit = OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 110.0, 10).with_account(8))));
+        let stop_loss = OrderLeg::Stop(Rc::new(RefCell::new(limit(3, Side::Sell, 90.0, 10).with_account(8))), Trigger::Stop(OrderedFloat(95.0)));
+        book.add_bracket_order(entry.clone(), take_profit, stop_loss).unwrap();
+        // the legs wait outside the book, the kill switch doesn't see them
+        book.disable_account(8);
+
+        add(&mut book, limit(4, Side::Sell, 100.0, 10)).unwrap();
+        assert!(entry.borrow().isfilled());
+        for order_id in 1..=3 {
+            assert!(book.get_order(order_id).is_none(), "order {} still in the book", order_id);
+            assert_eq!(book.get_group_id(order_id), None);
         }
+        assert_eq!(book.size(), 0);
+    }
+
+    #[test]
+    fn modified_bracket_entry_activates_its_legs() {
+        let mut book = OrderBook::new();
+        let entry = Rc::new(RefCell::new(limit(1, Side::Buy, 99.0, 10)));
+        let take_profit = OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 110.0, 10))));
+        let stop_loss = OrderLeg::Stop(Rc::new(RefCell::new(limit(3, Side::Sell, 90.0, 10))), Trigger::Stop(OrderedFloat(95.0)));
+        let (group_id, _) = book.add_bracket_order(entry, take_profit, stop_loss).unwrap();
+
+        book.modify_order(OrderModify::new(1, Side::Buy, OrderedFloat(100.0), 10)).unwrap();
+        assert_eq!(book.get_group_id(1), Some(group_id));
+        add(&mut book, limit(4, Side::Sell, 100.0, 10)).unwrap();
+
+        assert!(book.get_order(1).is_none());
+        assert_eq!(remaining(&book, 2), Some(10));
+        assert_eq!(book.get_trigger_price(3), Some(OrderedFloat(95.0)));
+        assert_eq!(book.get_group_id(2), Some(group_id));
+        assert_eq!(book.get_group_id(3), Some(group_id));
+    }
+
+    #[test]
+    fn partially_filled_bracket_entry_cant_be_modified() {
+        let mut book = OrderBook::new();
+        let entry = Rc::new(RefCell::new(limit(1, Side::Buy, 100.0, 10)));
+        let take_profit = OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 110.0, 10))));
+        let stop_loss = OrderLeg::Stop(Rc::new(RefCell::new(limit(3, Side::Sell, 90.0, 10))), Trigger::Stop(OrderedFloat(95.0)));
+        book.add_bracket_order(entry, take_profit, stop_loss).unwrap();
+        add(&mut book, limit(4, Side::Sell, 100.0, 4)).unwrap();
+
+        assert!(book.modify_order(OrderModify::new(1, Side::Buy, OrderedFloat(101.0), 6)).is_none());
+        assert_eq!(remaining(&book, 1), Some(6));
+        assert_eq!(book.get_order(1).unwrap().borrow().get_price(), OrderedFloat(100.0));
+    }
+
+    #[test]
+    fn modified_oco_order_keeps_its_sibling() {
+        let mut book = OrderBook::new();
+        book.add_oco_orders(OrderLeg::Limit(Rc::new(RefCell::new(limit(1, Side::Sell, 102.0, 10)))), OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 105.0, 10))))).unwrap();
+        book.modify_order(OrderModify::new(1, Side::Sell, OrderedFloat(101.0), 10)).unwrap();
+
+        // trading the modified order cancels order 2
+        add(&mut book, limit(3, Side::Buy, 101.0, 2)).unwrap();
+        assert!(book.get_order(2).is_none());
+        assert_eq!(remaining(&book, 1), Some(8));
+    }
+
+    #[test]
+    fn refused_oco_modify_cancels_the_sibling() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(9, Side::Buy, 100.0, 5).with_all_or_none()).unwrap();
+        book.add_oco_orders(OrderLeg::Limit(Rc::new(RefCell::new(limit(1, Side::Sell, 102.0, 10)))), OrderLeg::Limit(Rc::new(RefCell::new(limit(2, Side::Sell, 105.0, 10))))).unwrap();
+
+        // 3 can't fill the all or none bid and would rest crossing it, the book refuses it
+        assert!(book.modify_order(OrderModify::new(1, Side::Sell, OrderedFloat(100.0), 3)).is_none());
+        assert!(book.get_order(1).is_none());
+        assert!(book.get_order(2).is_none());
+        assert_eq!(book.get_group_id(2), None);
+    }
+
+    fn pointer(order: Order) -> OrderPointer {
+        Rc::new(RefCell::new(order))
+    }
+
+    // resting, hidden, pegged, stop, trailing stop, OCO and bracket orders, fees and a disabled account
+    fn busy_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.set_fee_model(FeeModel::new(FeeSchedule::new(FeeRate::BasisPoints(-1.0), FeeRate::BasisPoints(2.0))));
+        add(&mut book, limit(1, Side::Sell, 101.0, 10).with_account(1)).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 5)).unwrap();
+        add(&mut book, limit(3, Side::Sell, 101.0, 7).with_hidden()).unwrap();
+        add(&mut book, limit(4, Side::Buy, 99.0, 8).with_min_quantity(3)).unwrap();
+        add(&mut book, limit(5, Side::Buy, 98.0, 6).with_all_or_none()).unwrap();
+        add(&mut book, limit(6, Side::Buy, 0.0, 2).with_peg(Peg::new(PegType::Primary, OrderedFloat(0.0)))).unwrap();
+        add(&mut book, limit(7, Side::Buy, 101.0, 4).with_account(2)).unwrap();
+        book.add_stop_order(pointer(limit(8, Side::Sell, 95.0, 3)), Trigger::Stop(OrderedFloat(96.0))).unwrap();
+        book.add_stop_order(pointer(limit(9, Side::Buy, 110.0, 3)), Trigger::TrailingStop(Trail::Amount(OrderedFloat(3.0)))).unwrap();
+        book.add_oco_orders(OrderLeg::Limit(pointer(limit(10, Side::Sell, 104.0, 2))), OrderLeg::Stop(pointer(limit(11, Side::Sell, 90.0, 2)), Trigger::Stop(OrderedFloat(92.0)))).unwrap();
+        let take_profit = OrderLeg::Limit(pointer(limit(13, Side::Sell, 108.0, 5)));
+        let stop_loss = OrderLeg::Stop(pointer(limit(14, Side::Sell, 93.0, 5)), Trigger::Stop(OrderedFloat(94.0)));
+        book.add_bracket_order(pointer(limit(12, Side::Buy, 97.0, 5)), take_profit, stop_loss).unwrap();
+        book.disable_account(3);
+        book
+    }
+
+    #[test]
+    fn snapshot_restores_the_book_exactly() {
+        let clock = Rc::new(ManualClock::new(1_000));
+        let mut book = busy_book();
+        book.set_clock(clock.clone());
+        book.take_events();
+        let bytes = book.write_snapshot();
+        let mut restored = OrderBook::new();
+        restored.set_clock(clock);
+        restored.restore_snapshot(&bytes).unwrap();
 
-        for (price, orders) in &self.asks {
-            ask_infos.push(create_level_info(*price, &orders));
+        assert_eq!(restored.write_snapshot(), bytes);
+        assert_eq!(restored.state_hash(), book.state_hash());
+        assert_eq!(restored.get_orderlevelinfos(), book.get_orderlevelinfos());
+        assert_eq!(restored.get_trigger_price(9), book.get_trigger_price(9));
+        assert_eq!(restored.get_group_id(13), book.get_group_id(13));
+        assert_eq!(restored.get_accumulated_fees(2), book.get_accumulated_fees(2));
+
+        // the restored book goes on exactly like the original: queue positions, hidden orders last,
+        // pegged re-pricing, triggered stops, linked orders and trade ids
+        for commands in [
+            |book: &mut OrderBook| add(book, limit(20, Side::Buy, 101.0, 18)).unwrap(),
+            |book: &mut OrderBook| add(book, limit(21, Side::Sell, 92.0, 30)).unwrap(),
+            |book: &mut OrderBook| add(book, limit(22, Side::Buy, 104.0, 40)).unwrap(),
+        ] {
+            let trades = commands(&mut book);
+            assert!(!trades.is_empty());
+            assert_eq!(commands(&mut restored), trades);
+            assert_eq!(restored.take_events(), book.take_events());
+            assert_eq!(restored.state_hash(), book.state_hash());
         }
+    }
+
+    #[test]
+    fn unreadable_snapshot_leaves_the_book_alone() {
+        let mut book = busy_book();
+        let state_hash = book.state_hash();
+        let bytes = OrderBook::new().write_snapshot();
+        // sealed, but it ends in the middle of the state
+        let payload = crate::snapshot::unseal(SnapshotKind::Book, &busy_book().write_snapshot()).unwrap().to_vec();
+        let truncated = seal(SnapshotKind::Book, &payload[..payload.len() / 2]);
+
+        assert!(book.restore_snapshot(&truncated).is_err());
+        assert_eq!(book.state_hash(), state_hash);
+        book.restore_snapshot(&bytes).unwrap();
+        assert_eq!(book.size(), 0);
+    }
+
+    #[test]
+    fn oco_legs_emit_no_events() {
+        let mut book = OrderBook::new();
+        book.add_oco_orders(OrderLeg::Limit(pointer(limit(1, Side::Sell, 104.0, 2))), OrderLeg::Limit(pointer(limit(2, Side::Sell, 106.0, 2)))).unwrap();
+        assert!(book.take_events().is_empty());
+    }
+
+    fn pegged(order_id: OrderId, side: Side, peg_type: PegType, offset: f32) -> Order {
+        limit(order_id, side, 0.0, 5).with_peg(Peg::new(peg_type, OrderedFloat(offset)))
+    }
+
+    fn price(book: &OrderBook, order_id: OrderId) -> Price {
+        book.get_order(order_id).unwrap().borrow().get_price()
+    }
+
+    #[test]
+    fn market_peg_never_trades_on_entry() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 101.0, 5)).unwrap();
+        add(&mut book, limit(2, Side::Buy, 99.0, 5)).unwrap();
+
+        // at the best ask it would take it, so would a primary peg offset across the spread
+        assert!(add(&mut book, pegged(3, Side::Buy, PegType::Market, 0.0)).is_none());
+        assert!(add(&mut book, pegged(4, Side::Buy, PegType::Primary, 2.0)).is_none());
+        assert_eq!(remaining(&book, 1), Some(5));
+        assert!(book.get_order(3).is_none() && book.get_order(4).is_none());
+
+        assert_eq!(add(&mut book, pegged(5, Side::Buy, PegType::Market, -0.5)), Some(Vec::new()));
+        assert_eq!(price(&book, 5), OrderedFloat(100.5));
+        book.take_events();
+
+        // follows the ask down, and back up once it's gone
+        add(&mut book, limit(6, Side::Sell, 100.75, 5)).unwrap();
+        assert_eq!(price(&book, 5), OrderedFloat(100.25));
+        assert_eq!(book.take_events(), vec![OrderEvent::Modified {
+            order_id: 5,
+            side: Side::Buy,
+            old_price: OrderedFloat(100.5),
+            new_price: OrderedFloat(100.25),
+            remaining_quantity: 5,
+        }]);
+        book.cancel_order(6);
+        assert_eq!(price(&book, 5), OrderedFloat(100.5));
+    }
+
+    #[test]
+    fn repricing_never_trades() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 101.0, 5)).unwrap();
+        add(&mut book, limit(2, Side::Buy, 99.0, 5)).unwrap();
+        add(&mut book, pegged(3, Side::Buy, PegType::Primary, 1.0)).unwrap();
+        assert_eq!(price(&book, 3), OrderedFloat(100.0));
+
+        // the bid moves to 100.5, 101.5 would cross the ask so the order keeps 100
+        add(&mut book, limit(4, Side::Buy, 100.5, 5)).unwrap();
+        assert_eq!(price(&book, 3), OrderedFloat(100.0));
+        assert_eq!(remaining(&book, 1), Some(5));
+        assert_eq!(remaining(&book, 3), Some(5));
+    }
+
+    #[test]
+    fn midpoint_peg_follows_both_sides() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 101.0, 5)).unwrap();
+        add(&mut book, limit(2, Side::Buy, 99.0, 5)).unwrap();
+        add(&mut book, pegged(3, Side::Buy, PegType::Midpoint, 0.0)).unwrap();
+        assert_eq!(price(&book, 3), OrderedFloat(100.0));
+
+        add(&mut book, limit(4, Side::Sell, 100.5, 5)).unwrap();
+        assert_eq!(price(&book, 3), OrderedFloat(99.75));
+        add(&mut book, limit(5, Side::Buy, 99.5, 5)).unwrap();
+        assert_eq!(price(&book, 3), OrderedFloat(100.0));
+
+        // no bid left to take the midpoint of, the order stays where it is
+        book.cancel_order(2);
+        book.cancel_order(5);
+        assert_eq!(price(&book, 3), OrderedFloat(100.0));
+    }
+
+    #[test]
+    fn repriced_order_loses_its_priority() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Buy, 99.0, 5)).unwrap();
+        add(&mut book, pegged(2, Side::Buy, PegType::Primary, 0.0)).unwrap();
+        add(&mut book, limit(3, Side::Buy, 99.5, 5)).unwrap();
+        assert_eq!(price(&book, 2), OrderedFloat(99.5));
+
+        // order 2 joined 99.5 after order 3
+        let trades = add(&mut book, limit(4, Side::Sell, 99.5, 5)).unwrap();
+        assert_eq!(fills(&trades), vec![(3, 5)]);
+
+        // and went back to 99 behind order 1
+        assert_eq!(price(&book, 2), OrderedFloat(99.0));
+        let trades = add(&mut book, limit(5, Side::Sell, 99.0, 7)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 5), (2, 2)]);
+    }
+
+    #[test]
+    fn hidden_orders_fill_after_displayed_ones_at_their_price() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 5).with_hidden()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 100.0, 3)).unwrap();
+        add(&mut book, limit(3, Side::Sell, 100.0, 4).with_hidden()).unwrap();
+        add(&mut book, limit(4, Side::Sell, 100.0, 2)).unwrap();
+
+        // displayed orders first in time priority, then the hidden ones in theirs
+        let trades = add(&mut book, limit(10, Side::Buy, 100.0, 12)).unwrap();
+        assert_eq!(fills(&trades), vec![(2, 3), (4, 2), (1, 5), (3, 2)]);
+        assert_eq!(remaining(&book, 3), Some(2));
+    }
+
+    #[test]
+    fn hidden_order_at_a_better_price_fills_first() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 101.0, 5)).unwrap();
+        add(&mut book, limit(2, Side::Sell, 100.0, 5).with_hidden()).unwrap();
+
+        let trades = add(&mut book, limit(10, Side::Buy, 101.0, 7)).unwrap();
+        assert_eq!(fills(&trades), vec![(2, 5), (1, 2)]);
+    }
+
+    #[test]
+    fn hidden_orders_arent_displayed() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 5).with_hidden()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 3)).unwrap();
+        add(&mut book, limit(3, Side::Sell, 101.0, 4).with_hidden()).unwrap();
+        add(&mut book, limit(4, Side::Buy, 99.0, 6).with_hidden()).unwrap();
+
+        let infos = book.get_orderlevelinfos();
+        assert!(infos.get_bids().is_empty());
+        assert_eq!(infos.get_asks(), &vec![LevelInfo::new(OrderedFloat(101.0), 3)]);
+        assert_eq!(book.size(), 4);
+
+        // nor used as a peg reference
+        add(&mut book, pegged(5, Side::Sell, PegType::Primary, 0.0)).unwrap();
+        assert_eq!(price(&book, 5), OrderedFloat(101.0));
+    }
+
+    #[test]
+    fn mass_cancel_filters_by_account_side_and_price() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Buy, 98.0, 5).with_account(1)).unwrap();
+        add(&mut book, limit(2, Side::Buy, 99.0, 5).with_account(1)).unwrap();
+        add(&mut book, limit(3, Side::Buy, 99.0, 5).with_account(2)).unwrap();
+        add(&mut book, limit(4, Side::Sell, 101.0, 5).with_account(1)).unwrap();
+        add(&mut book, limit(5, Side::Sell, 102.0, 5).with_account(1)).unwrap();
+        add(&mut book, limit(6, Side::Sell, 103.0, 5).with_account(1)).unwrap();
+
+        // in price-time priority, best bid first
+        let (cancelled, _) = book.mass_cancel(&MassCancelFilter::new().with_account(1).with_side(Side::Buy));
+        assert_eq!(cancelled, vec![2, 1]);
+        let (cancelled, _) = book.mass_cancel(&MassCancelFilter::new().with_price_range(OrderedFloat(101.0), OrderedFloat(102.0)));
+        assert_eq!(cancelled, vec![4, 5]);
+        assert_eq!(book.take_events().len(), 4);
+        assert_eq!(book.size(), 2);
+        assert_eq!(remaining(&book, 3), Some(5));
+        assert_eq!(remaining(&book, 6), Some(5));
+    }
+
+    #[test]
+    fn disabled_account_cant_enter_orders() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Buy, 99.0, 5).with_account(1)).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 5).with_account(2)).unwrap();
+
+        let (cancelled, _) = book.disable_account(1);
+        assert_eq!(cancelled, vec![1]);
+        assert!(!book.is_account_enabled(1));
+        assert!(add(&mut book, limit(3, Side::Buy, 101.0, 5).with_account(1)).is_none());
+        assert!(book.add_stop_order(pointer(limit(4, Side::Buy, 105.0, 5).with_account(1)), Trigger::Stop(OrderedFloat(104.0))).is_none());
+        assert!(book.add_oco_orders(OrderLeg::Limit(pointer(limit(5, Side::Buy, 98.0, 5).with_account(1))), OrderLeg::Limit(pointer(limit(6, Side::Buy, 97.0, 5).with_account(1)))).is_none());
+        assert_eq!(book.size(), 1);
+        assert_eq!(remaining(&book, 2), Some(5));
+
+        // other accounts trade as usual, and the account can trade again once enabled
+        assert_eq!(add(&mut book, limit(7, Side::Buy, 101.0, 2).with_account(3)).unwrap().len(), 1);
+        book.enable_account(1);
+        assert_eq!(add(&mut book, limit(3, Side::Buy, 101.0, 3).with_account(1)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn smaller_flow_walks_past_all_or_none_to_deeper_levels() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Sell, 100.0, 10).with_all_or_none()).unwrap();
+        add(&mut book, limit(2, Side::Sell, 101.0, 5)).unwrap();
+
+        // trades at 101 past the all or none order, the 3 left would cross it at 100
+        let trades = add(&mut book, limit(10, Side::Buy, 101.0, 8)).unwrap();
+        assert_eq!(fills(&trades), vec![(2, 5)]);
+        assert_eq!(remaining(&book, 10), None);
+        assert_eq!(remaining(&book, 1), Some(10));
+
+        // nothing to trade and crossing, refused; below the all or none order it rests
+        assert!(add(&mut book, limit(11, Side::Buy, 100.0, 4)).is_none());
+        assert_eq!(add(&mut book, limit(12, Side::Buy, 99.0, 4)), Some(Vec::new()));
+        assert_eq!(remaining(&book, 12), Some(4));
+
+        // enough quantity fills it
+        let trades = add(&mut book, limit(13, Side::Buy, 100.0, 10)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 10)]);
+    }
+
+    #[test]
+    fn minimum_quantity_order_blocks_only_flow_below_its_minimum() {
+        let mut book = OrderBook::new();
+        add(&mut book, limit(1, Side::Buy, 100.0, 10).with_min_quantity(4)).unwrap();
+
+        assert!(add(&mut book, limit(10, Side::Sell, 100.0, 3)).is_none());
+        assert_eq!(remaining(&book, 1), Some(10));
+        let trades = add(&mut book, limit(11, Side::Sell, 100.0, 4)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 4)]);
 
-        return OrderbookLevelInfos::new(bid_infos, ask_infos);
+        // the 6 left trade at once, the rest of the sell rests at 99
+        let trades = add(&mut book, limit(12, Side::Sell, 99.0, 8)).unwrap();
+        assert_eq!(fills(&trades), vec![(1, 6)]);
+        assert_eq!(remaining(&book, 12), Some(2));
+        assert!(book.get_order(1).is_none());
     }
 }
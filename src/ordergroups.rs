@@ -0,0 +1,133 @@
+use super::{BTreeMap, GroupId, OrderId, OrderPointer, Trigger};
+
+// ----------------------------
+// OrderLeg is one order of a group, either a regular order sent to `add_order`
+// or a stop order held in the trigger book until `Trigger` is reached.
+// ----------------------------
+pub enum OrderLeg {
+    Limit(OrderPointer),
+    Stop(OrderPointer, Trigger),
+}
+impl OrderLeg {
+    pub fn get_order(&self) -> &OrderPointer {
+        match self {
+            OrderLeg::Limit(order) | OrderLeg::Stop(order, _) => order,
+        }
+    }
+}
+
+pub enum OrderGroup {
+    // a fill or a cancel of either order cancels the other one.
+    OneCancelsOther {
+        first: OrderId,
+        second: OrderId,
+    },
+    // take_profit and stop_loss wait outside the book until entry is filled,
+    // then they're added and become a OneCancelsOther pair under the same group id.
+    Bracket {
+        entry: OrderPointer,
+        take_profit: OrderLeg,
+        stop_loss: OrderLeg,
+    },
+}
+impl OrderGroup {
+    pub fn get_order_ids(&self) -> Vec<OrderId> {
+        match self {
+            OrderGroup::OneCancelsOther { first, second } => vec![*first, *second],
+            OrderGroup::Bracket { entry, take_profit, stop_loss } => vec![
+                entry.borrow().get_order_id(),
+                take_profit.get_order().borrow().get_order_id(),
+                stop_loss.get_order().borrow().get_order_id(),
+            ],
+        }
+    }
+}
+
+// ----------------------------
+// OrderGroups is the registry of linked orders of an orderbook,
+// `members` maps every order of a live group to its group for fast lookups on fills and cancels.
+// ----------------------------
+#[derive(Default)]
+pub struct OrderGroups {
+    groups: BTreeMap<GroupId, OrderGroup>,
+    members: BTreeMap<OrderId, GroupId>,
+    next_group_id: GroupId,
+}
+impl OrderGroups {
+    pub fn new() -> Self {
+        Self { groups: BTreeMap::new(), members: BTreeMap::new(), next_group_id: 1 }
+    }
+
+    pub fn add(&mut self, group: OrderGroup) -> GroupId {
+        let group_id = self.next_group_id;
+        self.next_group_id += 1;
+        self.insert(group_id, group);
+        group_id
+    }
+
+    // (re)registers `group` under an existing id, used when a bracket turns into its OCO pair.
+    pub fn insert(&mut self, group_id: GroupId, group: OrderGroup) {
+        for order_id in group.get_order_ids() {
+            self.members.insert(order_id, group_id);
+        }
+        self.groups.insert(group_id, group);
+    }
+
+    pub fn remove(&mut self, group_id: GroupId) -> Option<OrderGroup> {
+        let group = self.groups.remove(&group_id)?;
+        for order_id in group.get_order_ids() {
+            self.members.remove(&order_id);
+        }
+        Some(group)
+    }
+
+    // Live groups in group id order.
+    pub fn iter(&self) -> impl Iterator<Item = (GroupId, &OrderGroup)> {
+        self.groups.iter().map(|(group_id, group)| (*group_id, group))
+    }
+
+    pub fn get_next_group_id(&self) -> GroupId {
+        self.next_group_id
+    }
+    pub fn set_next_group_id(&mut self, next_group_id: GroupId) {
+        self.next_group_id = next_group_id;
+    }
+
+    pub fn get(&self, group_id: GroupId) -> Option<&OrderGroup> {
+        self.groups.get(&group_id)
+    }
+
+    pub fn get_group_id(&self, order_id: OrderId) -> Option<GroupId> {
+        self.members.get(&order_id).copied()
+    }
+
+    // take-profit or stop-loss of a bracket that isn't activated yet.
+    pub fn get_pending_order(&self, order_id: OrderId) -> Option<OrderPointer> {
+        let group_id = self.get_group_id(order_id)?;
+        let OrderGroup::Bracket { take_profit, stop_loss, .. } = self.groups.get(&group_id)? else {
+            return None;
+        };
+        [take_profit, stop_loss]
+            .into_iter()
+            .map(|leg| leg.get_order())
+            .find(|order| order.borrow().get_order_id() == order_id)
+            .cloned()
+    }
+
+    // A modified bracket entry is a new order under the same id, returns false if `group_id` isn't a bracket.
+    pub fn replace_entry(&mut self, group_id: GroupId, order: OrderPointer) -> bool {
+        let Some(OrderGroup::Bracket { entry, .. }) = self.groups.get_mut(&group_id) else {
+            return false;
+        };
+        *entry = order;
+        true
+    }
+
+    pub fn contains(&self, group_id: GroupId) -> bool {
+        self.groups.contains_key(&group_id)
+    }
+
+    pub fn size(&self) -> usize {
+        self.groups.len()
+    }
+}
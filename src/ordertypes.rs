@@ -1,8 +1,10 @@
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderType {
     GoodTillCancel,   // persist until filled or canceled.
     FillAndKill,      // fill as much as possible immediately, any remaining qunatity is cancelled.
     // FillOrKill,       // completely filled immediately or cancel entire order.
     // GoodForDay,       // active for current trading day, automatically cancelled if not filled by end of day.
     Market,           // whatever the best market price is just buy/sell.
+    MarketToLimit,    // execute against the best opposite level only, remaining quantity rests there as GoodTillCancel.
 }
@@ -0,0 +1,622 @@
+use super::*;
+use crate::codec::{DecodeError, Decoder, Encoder};
+use crate::helperfns::get_timestamp;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// symbols are space padded to a fixed width on the wire.
+pub const SYMBOL_WIDTH: usize = 8;
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// responses queued per connection, a client that lets more pile up is disconnected.
+const MAX_QUEUED_RESPONSES: usize = 4096;
+
+// Client chosen order identifier, unique per connection.
+pub type OrderToken = u64;
+
+// ----------------------------
+// Framing: every message is a u16 little endian payload length followed by the payload.
+// ----------------------------
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(payload.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload too long for a frame"))?;
+    let mut frame = Vec::with_capacity(2 + payload.len());
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    reader.read_exact(&mut length)?;
+    let mut payload = vec![0; u16::from_le_bytes(length) as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimeInForce {
+    Day,                // rests until cancelled.
+    ImmediateOrCancel,  // whatever doesn't trade right away is cancelled.
+    FillOrKill,         // trades completely right away or not at all.
+}
+
+// Why an order was rejected or cancelled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OuchReason {
+    UserRequested,
+    Expired,            // immediate or cancel remainder, or cancelled by the engine.
+    UnknownSymbol,
+    InvalidQuantity,
+    DuplicateToken,
+    UnknownToken,
+    Other,
+}
+impl OuchReason {
+    fn to_byte(self) -> u8 {
+        match self {
+            OuchReason::UserRequested => b'U',
+            OuchReason::Expired => b'I',
+            OuchReason::UnknownSymbol => b'S',
+            OuchReason::InvalidQuantity => b'Z',
+            OuchReason::DuplicateToken => b'D',
+            OuchReason::UnknownToken => b'T',
+            OuchReason::Other => b'O',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, DecodeError> {
+        match byte {
+            b'U' => Ok(OuchReason::UserRequested),
+            b'I' => Ok(OuchReason::Expired),
+            b'S' => Ok(OuchReason::UnknownSymbol),
+            b'Z' => Ok(OuchReason::InvalidQuantity),
+            b'D' => Ok(OuchReason::DuplicateToken),
+            b'T' => Ok(OuchReason::UnknownToken),
+            b'O' => Ok(OuchReason::Other),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn from_gateway(reason: OrderRejectReason) -> Self {
+        match reason {
+            OrderRejectReason::UnknownSymbol => OuchReason::UnknownSymbol,
+            OrderRejectReason::InvalidQuantity | OrderRejectReason::QuantityNotAboveFilled => OuchReason::InvalidQuantity,
+            OrderRejectReason::DuplicateClientOrderId => OuchReason::DuplicateToken,
+            OrderRejectReason::UnknownOrder => OuchReason::UnknownToken,
+            OrderRejectReason::TradingHalted | OrderRejectReason::RejectedByBook | OrderRejectReason::JournalUnavailable => OuchReason::Other,
+        }
+    }
+}
+
+fn put_symbol(encoder: &mut Encoder, symbol: &str) -> io::Result<()> {
+    if symbol.len() > SYMBOL_WIDTH || !symbol.is_ascii() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("symbol {:?} isn't {} ascii characters or less", symbol, SYMBOL_WIDTH)));
+    }
+    encoder.put_bytes(format!("{:<width$}", symbol, width = SYMBOL_WIDTH).as_bytes());
+    Ok(())
+}
+
+fn get_symbol(decoder: &mut Decoder) -> Result<Symbol, DecodeError> {
+    let bytes = decoder.get_bytes(SYMBOL_WIDTH)?;
+    let symbol = std::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)?;
+    Ok(symbol.trim_end_matches(' ').to_string())
+}
+
+fn put_time_in_force(encoder: &mut Encoder, time_in_force: TimeInForce) {
+    encoder.put_u8(match time_in_force {
+        TimeInForce::Day => 0,
+        TimeInForce::ImmediateOrCancel => 1,
+        TimeInForce::FillOrKill => 2,
+    });
+}
+
+fn get_time_in_force(decoder: &mut Decoder) -> Result<TimeInForce, DecodeError> {
+    match decoder.get_u8()? {
+        0 => Ok(TimeInForce::Day),
+        1 => Ok(TimeInForce::ImmediateOrCancel),
+        2 => Ok(TimeInForce::FillOrKill),
+        tag => Err(DecodeError::InvalidTag(tag)),
+    }
+}
+
+// ----------------------------
+// OuchRequest: what a client sends, every message type has a fixed width.
+// A NaN price enters a market order, `account_id` 0 means no account.
+// Bytes after the known fields are ignored so fields can be appended later.
+// ----------------------------
+#[derive(Clone, Debug, PartialEq)]
+pub enum OuchRequest {
+    EnterOrder { token: OrderToken, side: Side, quantity: Quantity, symbol: Symbol, price: Price, time_in_force: TimeInForce, account_id: AccountId },
+    ReplaceOrder { existing_token: OrderToken, replacement_token: OrderToken, quantity: Quantity, price: Price },
+    CancelOrder { token: OrderToken },
+}
+impl OuchRequest {
+    pub fn encode(&self, encoder: &mut Encoder) -> io::Result<()> {
+        match self {
+            OuchRequest::EnterOrder { token, side, quantity, symbol, price, time_in_force, account_id } => {
+                encoder.put_u8(b'O');
+                encoder.put_u64(*token);
+                encoder.put_side(*side);
+                encoder.put_u32(*quantity);
+                put_symbol(encoder, symbol)?;
+                encoder.put_price(*price);
+                put_time_in_force(encoder, *time_in_force);
+                encoder.put_u64(*account_id);
+            }
+            OuchRequest::ReplaceOrder { existing_token, replacement_token, quantity, price } => {
+                encoder.put_u8(b'U');
+                encoder.put_u64(*existing_token);
+                encoder.put_u64(*replacement_token);
+                encoder.put_u32(*quantity);
+                encoder.put_price(*price);
+            }
+            OuchRequest::CancelOrder { token } => {
+                encoder.put_u8(b'X');
+                encoder.put_u64(*token);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.get_u8()? {
+            b'O' => Ok(OuchRequest::EnterOrder {
+                token: decoder.get_u64()?,
+                side: decoder.get_side()?,
+                quantity: decoder.get_u32()?,
+                symbol: get_symbol(decoder)?,
+                price: decoder.get_price()?,
+                time_in_force: get_time_in_force(decoder)?,
+                account_id: decoder.get_u64()?,
+            }),
+            b'U' => Ok(OuchRequest::ReplaceOrder {
+                existing_token: decoder.get_u64()?,
+                replacement_token: decoder.get_u64()?,
+                quantity: decoder.get_u32()?,
+                price: decoder.get_price()?,
+            }),
+            b'X' => Ok(OuchRequest::CancelOrder { token: decoder.get_u64()? }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder)?;
+        write_frame(writer, encoder.get_bytes())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let payload = read_frame(reader)?;
+        Self::decode(&mut Decoder::new(&payload)).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+// ----------------------------
+// OuchResponse: what the server sends back, `timestamp` is nanoseconds since the epoch.
+// `order_reference` is the engine's order id, `match_number` the trade id (unique across the engine's books).
+// A refused replace is Rejected with the replacement token, a refused cancel is CancelRejected.
+// ----------------------------
+#[derive(Clone, Debug, PartialEq)]
+pub enum OuchResponse {
+    Accepted { timestamp: Timestamp, token: OrderToken, side: Side, quantity: Quantity, symbol: Symbol, price: Price, order_reference: OrderId },
+    Replaced { timestamp: Timestamp, replacement_token: OrderToken, previous_token: OrderToken, leaves_quantity: Quantity, price: Price, order_reference: OrderId },
+    Executed { timestamp: Timestamp, token: OrderToken, quantity: Quantity, price: Price, match_number: TradeId },
+    Cancelled { timestamp: Timestamp, token: OrderToken, decrement_quantity: Quantity, reason: OuchReason },
+    Rejected { timestamp: Timestamp, token: OrderToken, reason: OuchReason },
+    CancelRejected { timestamp: Timestamp, token: OrderToken, reason: OuchReason },
+}
+impl OuchResponse {
+    pub fn encode(&self, encoder: &mut Encoder) -> io::Result<()> {
+        match self {
+            OuchResponse::Accepted { timestamp, token, side, quantity, symbol, price, order_reference } => {
+                encoder.put_u8(b'A');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*token);
+                encoder.put_side(*side);
+                encoder.put_u32(*quantity);
+                put_symbol(encoder, symbol)?;
+                encoder.put_price(*price);
+                encoder.put_u64(*order_reference);
+            }
+            OuchResponse::Replaced { timestamp, replacement_token, previous_token, leaves_quantity, price, order_reference } => {
+                encoder.put_u8(b'U');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*replacement_token);
+                encoder.put_u64(*previous_token);
+                encoder.put_u32(*leaves_quantity);
+                encoder.put_price(*price);
+                encoder.put_u64(*order_reference);
+            }
+            OuchResponse::Executed { timestamp, token, quantity, price, match_number } => {
+                encoder.put_u8(b'E');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*token);
+                encoder.put_u32(*quantity);
+                encoder.put_price(*price);
+                encoder.put_u64(*match_number);
+            }
+            OuchResponse::Cancelled { timestamp, token, decrement_quantity, reason } => {
+                encoder.put_u8(b'C');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*token);
+                encoder.put_u32(*decrement_quantity);
+                encoder.put_u8(reason.to_byte());
+            }
+            OuchResponse::Rejected { timestamp, token, reason } => {
+                encoder.put_u8(b'J');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*token);
+                encoder.put_u8(reason.to_byte());
+            }
+            OuchResponse::CancelRejected { timestamp, token, reason } => {
+                encoder.put_u8(b'I');
+                encoder.put_u64(*timestamp);
+                encoder.put_u64(*token);
+                encoder.put_u8(reason.to_byte());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.get_u8()? {
+            b'A' => Ok(OuchResponse::Accepted {
+                timestamp: decoder.get_u64()?,
+                token: decoder.get_u64()?,
+                side: decoder.get_side()?,
+                quantity: decoder.get_u32()?,
+                symbol: get_symbol(decoder)?,
+                price: decoder.get_price()?,
+                order_reference: decoder.get_u64()?,
+            }),
+            b'U' => Ok(OuchResponse::Replaced {
+                timestamp: decoder.get_u64()?,
+                replacement_token: decoder.get_u64()?,
+                previous_token: decoder.get_u64()?,
+                leaves_quantity: decoder.get_u32()?,
+                price: decoder.get_price()?,
+                order_reference: decoder.get_u64()?,
+            }),
+            b'E' => Ok(OuchResponse::Executed {
+                timestamp: decoder.get_u64()?,
+                token: decoder.get_u64()?,
+                quantity: decoder.get_u32()?,
+                price: decoder.get_price()?,
+                match_number: decoder.get_u64()?,
+            }),
+            b'C' => Ok(OuchResponse::Cancelled {
+                timestamp: decoder.get_u64()?,
+                token: decoder.get_u64()?,
+                decrement_quantity: decoder.get_u32()?,
+                reason: OuchReason::from_byte(decoder.get_u8()?)?,
+            }),
+            b'J' => Ok(OuchResponse::Rejected {
+                timestamp: decoder.get_u64()?,
+                token: decoder.get_u64()?,
+                reason: OuchReason::from_byte(decoder.get_u8()?)?,
+            }),
+            b'I' => Ok(OuchResponse::CancelRejected {
+                timestamp: decoder.get_u64()?,
+                token: decoder.get_u64()?,
+                reason: OuchReason::from_byte(decoder.get_u8()?)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder)?;
+        write_frame(writer, encoder.get_bytes())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let payload = read_frame(reader)?;
+        Self::decode(&mut Decoder::new(&payload)).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    // None for updates that don't come from a token of ours.
+    fn from_update(update: OrderUpdate) -> Option<Self> {
+        let token = |client_order_id: &str| client_order_id.parse::<OrderToken>().ok();
+        let timestamp = get_timestamp();
+        Some(match update {
+            OrderUpdate::Accepted(status) => OuchResponse::Accepted {
+                timestamp,
+                token: token(&status.client_order_id)?,
+                side: status.side,
+                quantity: status.quantity,
+                symbol: status.symbol,
+                price: status.price,
+                order_reference: status.order_id,
+            },
+            OrderUpdate::Rejected { order, reason } => OuchResponse::Rejected { timestamp, token: token(&order.client_order_id)?, reason: OuchReason::from_gateway(reason) },
+            OrderUpdate::Filled { status, trade_id, price, quantity } => {
+                OuchResponse::Executed { timestamp, token: token(&status.client_order_id)?, quantity, price, match_number: trade_id }
+            }
+            OrderUpdate::Cancelled { status, original_client_order_id } => OuchResponse::Cancelled {
+                timestamp,
+                token: token(&status.client_order_id)?,
+                decrement_quantity: status.quantity - status.cumulative_quantity,
+                reason: if original_client_order_id.is_some() { OuchReason::UserRequested } else { OuchReason::Expired },
+            },
+            OrderUpdate::Replaced { status, original_client_order_id } => OuchResponse::Replaced {
+                timestamp,
+                replacement_token: token(&status.client_order_id)?,
+                previous_token: token(&original_client_order_id)?,
+                leaves_quantity: status.leaves_quantity,
+                price: status.price,
+                order_reference: status.order_id,
+            },
+            OrderUpdate::CancelRejected { client_order_id, original_client_order_id, is_replace, reason, .. } => {
+                let reason = OuchReason::from_gateway(reason);
+                if is_replace {
+                    OuchResponse::Rejected { timestamp, token: token(&client_order_id)?, reason }
+                } else {
+                    OuchResponse::CancelRejected { timestamp, token: token(&original_client_order_id)?, reason }
+                }
+            }
+        })
+    }
+}
+
+// ----------------------------
+// OuchServer: binary order entry over TCP, every connection is a session of the
+// matching service (its orders are cancelled when it drops). Requests are read on the
+// connection's thread, responses are queued by the matching thread's sink and written
+// on a writer thread of the connection's own, so a slow client never holds up matching
+// (one that lets MAX_QUEUED_RESPONSES pile up is disconnected).
+// ----------------------------
+pub struct OuchServer {
+    local_address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+impl OuchServer {
+    pub fn bind(address: impl ToSocketAddrs, handle: EngineHandle) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept_shutdown = shutdown.clone();
+        thread::spawn(move || {
+            while !accept_shutdown.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let handle = handle.clone();
+                        thread::spawn(move || {
+                            let _ = Self::serve(stream, handle);
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Self { local_address, shutdown })
+    }
+
+    pub fn get_local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    fn serve(mut stream: TcpStream, handle: EngineHandle) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        let session_id = handle.new_session_id();
+        let mut writer = stream.try_clone()?;
+        let (sender, responses) = mpsc::sync_channel::<OuchResponse>(MAX_QUEUED_RESPONSES);
+        // ends once the session's sink is dropped, a failed write drops the connection
+        thread::spawn(move || {
+            for response in responses {
+                if response.write_to(&mut writer).is_err() {
+                    let _ = writer.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        handle.send(EngineRequest::Connect { session_id, sink: connection_sink(sender, stream.try_clone()?, OuchResponse::from_update) });
+
+        // a malformed message ends the session like a dropped connection
+        let result = loop {
+            let request = match OuchRequest::read_from(&mut stream) {
+                Ok(request) => request,
+                Err(error) => break Err(error),
+            };
+            let request = match request {
+                OuchRequest::EnterOrder { token, side, quantity, symbol, price, time_in_force, account_id } => {
+                    let (order_type, all_or_none) = match (time_in_force, price.is_nan()) {
+                        (TimeInForce::FillOrKill, true) => (OrderType::Market, true),
+                        (_, true) => (OrderType::Market, false),
+                        (TimeInForce::Day, false) => (OrderType::GoodTillCancel, false),
+                        (TimeInForce::ImmediateOrCancel, false) => (OrderType::FillAndKill, false),
+                        (TimeInForce::FillOrKill, false) => (OrderType::FillAndKill, true),
+                    };
+                    let order = NewOrder {
+                        client_order_id: token.to_string(),
+                        symbol,
+                        side,
+                        order_type,
+                        price,
+                        quantity,
+                        all_or_none,
+                        account_id: (account_id != 0).then_some(account_id),
+                    };
+                    EngineRequest::NewOrder { session_id, order }
+                }
+                OuchRequest::ReplaceOrder { existing_token, replacement_token, quantity, price } => EngineRequest::ReplaceOrder {
+                    session_id,
+                    client_order_id: replacement_token.to_string(),
+                    original_client_order_id: existing_token.to_string(),
+                    price,
+                    quantity,
+                },
+                OuchRequest::CancelOrder { token } => {
+                    EngineRequest::CancelOrder { session_id, client_order_id: token.to_string(), original_client_order_id: token.to_string() }
+                }
+            };
+            if !handle.send(request) {
+                break Ok(());
+            }
+        };
+        handle.send(EngineRequest::Disconnect { session_id });
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    }
+}
+impl Drop for OuchServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+// ----------------------------
+// OuchClient: the client side, responses are read on a thread of their own
+// so `receive` can wait with a timeout.
+// ----------------------------
+pub struct OuchClient {
+    stream: TcpStream,
+    responses: Receiver<io::Result<OuchResponse>>,
+}
+impl OuchClient {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || {
+            loop {
+                let response = OuchResponse::read_from(&mut reader);
+                let failed = response.is_err();
+                if sender.send(response).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Self { stream, responses })
+    }
+
+    pub fn send(&mut self, request: &OuchRequest) -> io::Result<()> {
+        request.write_to(&mut self.stream)
+    }
+
+    pub fn enter_order(&mut self, token: OrderToken, symbol: &str, side: Side, quantity: Quantity, price: Price, time_in_force: TimeInForce) -> io::Result<()> {
+        self.send(&OuchRequest::EnterOrder { token, side, quantity, symbol: symbol.to_string(), price, time_in_force, account_id: 0 })
+    }
+
+    pub fn replace_order(&mut self, existing_token: OrderToken, replacement_token: OrderToken, quantity: Quantity, price: Price) -> io::Result<()> {
+        self.send(&OuchRequest::ReplaceOrder { existing_token, replacement_token, quantity, price })
+    }
+
+    pub fn cancel_order(&mut self, token: OrderToken) -> io::Result<()> {
+        self.send(&OuchRequest::CancelOrder { token })
+    }
+
+    // Next response, None when nothing came within `timeout`.
+    pub fn receive(&mut self, timeout: Duration) -> io::Result<Option<OuchResponse>> {
+        match self.responses.recv_timeout(timeout) {
+            Ok(response) => response.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SYMBOL: &str = "DEMO";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn receive(client: &mut OuchClient) -> OuchResponse {
+        client.receive(TIMEOUT).unwrap().expect("no response from the server")
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, b"hello").unwrap();
+        write_frame(&mut bytes, b"").unwrap();
+        assert_eq!(bytes[..2], 5u16.to_le_bytes());
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(read_frame(&mut reader).unwrap(), b"hello");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"");
+        assert_eq!(read_frame(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let error = write_frame(&mut Vec::new(), &vec![0; u16::MAX as usize + 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let requests = [
+            OuchRequest::EnterOrder { token: 1, side: Side::Buy, quantity: 10, symbol: SYMBOL.to_string(), price: OrderedFloat(100.5), time_in_force: TimeInForce::Day, account_id: 7 },
+            OuchRequest::ReplaceOrder { existing_token: 1, replacement_token: 2, quantity: 8, price: OrderedFloat(99.0) },
+            OuchRequest::CancelOrder { token: 2 },
+        ];
+        for request in requests {
+            let mut bytes = Vec::new();
+            request.write_to(&mut bytes).unwrap();
+            assert_eq!(OuchRequest::read_from(&mut Cursor::new(bytes)).unwrap(), request);
+        }
+
+        let responses = [
+            OuchResponse::Executed { timestamp: 1, token: 1, quantity: 4, price: OrderedFloat(100.5), match_number: 3 },
+            OuchResponse::Cancelled { timestamp: 2, token: 1, decrement_quantity: 6, reason: OuchReason::UserRequested },
+            OuchResponse::CancelRejected { timestamp: 3, token: 9, reason: OuchReason::UnknownToken },
+        ];
+        for response in responses {
+            let mut bytes = Vec::new();
+            response.write_to(&mut bytes).unwrap();
+            assert_eq!(OuchResponse::read_from(&mut Cursor::new(bytes)).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn enter_execute_and_cancel() {
+        let (handle, requests) = EngineHandle::channel();
+        let path = std::env::temp_dir().join(format!("orderbook-rs-ouch-loopback-{}.journal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.add_instrument(SYMBOL, OrderBook::new());
+            MatchingService::new(JournaledEngine::open(&path, engine).unwrap(), requests).run();
+        });
+        let server = OuchServer::bind("127.0.0.1:0", handle.clone()).unwrap();
+        let mut alice = OuchClient::connect(server.get_local_address()).unwrap();
+        let mut bob = OuchClient::connect(server.get_local_address()).unwrap();
+
+        alice.enter_order(1, SYMBOL, Side::Sell, 10, OrderedFloat(101.0), TimeInForce::Day).unwrap();
+        assert!(matches!(receive(&mut alice), OuchResponse::Accepted { token: 1, quantity: 10, .. }));
+
+        bob.enter_order(1, SYMBOL, Side::Buy, 4, OrderedFloat(101.0), TimeInForce::ImmediateOrCancel).unwrap();
+        assert!(matches!(receive(&mut bob), OuchResponse::Accepted { token: 1, .. }));
+        let OuchResponse::Executed { token: 1, quantity: 4, price, match_number, .. } = receive(&mut bob) else { panic!("buy not executed") };
+        assert_eq!(price, OrderedFloat(101.0));
+        let OuchResponse::Executed { token: 1, quantity: 4, match_number: alice_match, .. } = receive(&mut alice) else { panic!("sell not executed") };
+        assert_eq!(alice_match, match_number);
+
+        alice.cancel_order(1).unwrap();
+        assert!(matches!(receive(&mut alice), OuchResponse::Cancelled { token: 1, decrement_quantity: 6, reason: OuchReason::UserRequested, .. }));
+        alice.cancel_order(1).unwrap();
+        assert!(matches!(receive(&mut alice), OuchResponse::CancelRejected { token: 1, reason: OuchReason::UnknownToken, .. }));
+        alice.enter_order(2, "OTHER", Side::Buy, 4, OrderedFloat(101.0), TimeInForce::Day).unwrap();
+        assert!(matches!(receive(&mut alice), OuchResponse::Rejected { token: 2, reason: OuchReason::UnknownSymbol, .. }));
+        alice.enter_order(3, SYMBOL, Side::Buy, 0, OrderedFloat(101.0), TimeInForce::Day).unwrap();
+        assert!(matches!(receive(&mut alice), OuchResponse::Rejected { token: 3, reason: OuchReason::InvalidQuantity, .. }));
+
+        alice.close().unwrap();
+        bob.close().unwrap();
+        handle.send(EngineRequest::Shutdown);
+    }
+}
@@ -0,0 +1,44 @@
+use super::{Price, Side};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PegType {
+    Primary,    // same side best price, buy -> best bid, sell -> best ask.
+    Market,     // opposite side best price, buy -> best ask, sell -> best bid, only rests with an offset off it.
+    Midpoint,   // midpoint of best bid and best ask, needs both sides.
+}
+
+// ----------------------------
+// Peg: an order with a peg has no fixed price, the orderbook
+// re-prices it to `reference + offset` whenever the reference moves.
+// offset is signed and added as is for both sides.
+// ----------------------------
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Peg {
+    peg_type: PegType,
+    offset: Price,
+}
+impl Peg {
+    pub fn new(peg_type: PegType, offset: Price) -> Self {
+        Self { peg_type, offset }
+    }
+
+    pub fn get_peg_type(&self) -> PegType {
+        self.peg_type
+    }
+    pub fn get_offset(&self) -> Price {
+        self.offset
+    }
+
+    // best_bid and best_ask are the reference prices of the book,
+    // returns None when the side(s) this peg refers to are empty.
+    pub fn get_pegged_price(&self, side: Side, best_bid: Option<Price>, best_ask: Option<Price>) -> Option<Price> {
+        let reference = match (self.peg_type, side) {
+            (PegType::Primary, Side::Buy) | (PegType::Market, Side::Sell) => best_bid?,
+            (PegType::Primary, Side::Sell) | (PegType::Market, Side::Buy) => best_ask?,
+            (PegType::Midpoint, _) => (best_bid? + best_ask?) / 2.0,
+        };
+        Some(reference + self.offset)
+    }
+}
@@ -0,0 +1,238 @@
+use super::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarkSource {
+    LastTrade,  // last trade price of the instrument.
+    Midpoint,   // midpoint of the instrument's best bid and ask, last trade while there's none.
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    quantity: i64, // net position, buys positive
+    average_entry_price: Amount,
+    realized_pnl: Amount,
+    fees: Amount, // fees paid (rebates negative), not part of realized_pnl
+}
+impl Position {
+    // Average cost: adding to the position moves the average entry price,
+    // reducing it realizes (price - average entry price) on the closed quantity,
+    // going through flat opens the rest at the trade price.
+    fn apply(&mut self, side: Side, price: Price, quantity: Quantity, fee: Amount) {
+        let price = price.into_inner() as f64;
+        let quantity = quantity as i64;
+        let signed_quantity = if side == Side::Buy { quantity } else { -quantity };
+        self.fees += fee;
+
+        if self.quantity == 0 || self.quantity.signum() == signed_quantity.signum() {
+            let size = self.quantity.abs() as f64;
+            self.average_entry_price = Amount::from((self.average_entry_price.into_inner() * size + price * quantity as f64) / (size + quantity as f64));
+            self.quantity += signed_quantity;
+            return;
+        }
+
+        let closed_quantity = quantity.min(self.quantity.abs());
+        self.realized_pnl += (price - self.average_entry_price.into_inner()) * (closed_quantity * self.quantity.signum()) as f64;
+        self.quantity += signed_quantity;
+        if self.quantity == 0 {
+            self.average_entry_price = Amount::from(0.0);
+        } else if self.quantity.signum() == signed_quantity.signum() {
+            self.average_entry_price = Amount::from(price);
+        }
+    }
+
+    pub fn get_quantity(&self) -> i64 {
+        self.quantity
+    }
+    pub fn get_average_entry_price(&self) -> Amount {
+        self.average_entry_price
+    }
+    pub fn get_realized_pnl(&self) -> Amount {
+        self.realized_pnl
+    }
+    pub fn get_fees(&self) -> Amount {
+        self.fees
+    }
+    pub fn get_unrealized_pnl(&self, mark_price: Price) -> Amount {
+        Amount::from((mark_price.into_inner() as f64 - self.average_entry_price.into_inner()) * self.quantity as f64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PositionSnapshot {
+    pub account_id: AccountId,
+    pub symbol: Symbol,
+    pub position: Position,
+    pub mark_price: Option<Price>,
+    pub unrealized_pnl: Amount, // 0 while the instrument has no mark price
+}
+
+#[derive(Default)]
+struct Marks {
+    last_trade_price: Option<Price>,
+    midpoint: Option<Price>,
+}
+
+// ----------------------------
+// PositionTracker consumes the trades of every orderbook of the engine
+// (what add_order, modify_order and cancel_order return) and keeps
+// a position per account and instrument, orders without an account are ignored.
+// ----------------------------
+pub struct PositionTracker {
+    mark_source: MarkSource,
+    positions: BTreeMap<(AccountId, Symbol), Position>,
+    marks: BTreeMap<Symbol, Marks>,
+}
+impl PositionTracker {
+    pub fn new(mark_source: MarkSource) -> Self {
+        Self { mark_source, positions: BTreeMap::new(), marks: BTreeMap::new() }
+    }
+
+    pub fn on_trade(&mut self, symbol: &str, trade: &Trade) {
+        for (side, trade_info) in [(Side::Buy, trade.get_bid_trade()), (Side::Sell, trade.get_ask_trade())] {
+            if let Some(account_id) = trade_info.account_id {
+                self.positions
+                    .entry((account_id, symbol.to_string()))
+                    .or_default()
+                    .apply(side, trade.get_price(), trade_info.quantity, trade_info.fee);
+            }
+        }
+        self.marks.entry(symbol.to_string()).or_default().last_trade_price = Some(trade.get_price());
+    }
+
+    pub fn on_trades(&mut self, symbol: &str, trades: &Trades) {
+        for trade in trades {
+            self.on_trade(symbol, trade);
+        }
+    }
+
+    // Feeds the best bid and ask of the instrument, used when marking to the midpoint.
+    pub fn on_quote(&mut self, symbol: &str, best_bid: Option<Price>, best_ask: Option<Price>) {
+        let midpoint = match (best_bid, best_ask) {
+            (Some(best_bid), Some(best_ask)) => Some((best_bid + best_ask) / 2.0),
+            _ => None,
+        };
+        self.marks.entry(symbol.to_string()).or_default().midpoint = midpoint;
+    }
+
+    pub fn get_mark_price(&self, symbol: &str) -> Option<Price> {
+        let marks = self.marks.get(symbol)?;
+        match self.mark_source {
+            MarkSource::LastTrade => marks.last_trade_price,
+            MarkSource::Midpoint => marks.midpoint.or(marks.last_trade_price),
+        }
+    }
+
+    fn snapshot(&self, account_id: AccountId, symbol: &Symbol, position: &Position) -> PositionSnapshot {
+        let mark_price = self.get_mark_price(symbol);
+        PositionSnapshot {
+            account_id,
+            symbol: symbol.clone(),
+            position: *position,
+            mark_price,
+            unrealized_pnl: mark_price.map(|mark_price| position.get_unrealized_pnl(mark_price)).unwrap_or_default(),
+        }
+    }
+
+    pub fn get_position(&self, account_id: AccountId, symbol: &str) -> Option<PositionSnapshot> {
+        let key = (account_id, symbol.to_string());
+        self.positions.get(&key).map(|position| self.snapshot(account_id, &key.1, position))
+    }
+
+    // Every instrument the account traded, sorted by symbol.
+    pub fn get_positions(&self, account_id: AccountId) -> Vec<PositionSnapshot> {
+        self.positions
+            .iter()
+            .filter(|((position_account_id, _), _)| *position_account_id == account_id)
+            .map(|((account_id, symbol), position)| self.snapshot(*account_id, symbol, position))
+            .collect()
+    }
+
+    // Every position, sorted by account then symbol.
+    pub fn get_snapshot(&self) -> Vec<PositionSnapshot> {
+        self.positions
+            .iter()
+            .map(|((account_id, symbol), position)| self.snapshot(*account_id, symbol, position))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL: &str = "DEMO";
+
+    fn trade(buyer: AccountId, seller: AccountId, price: f32, quantity: Quantity) -> Trade {
+        let price = OrderedFloat(price);
+        let bid_trade = TradeInfo::new(1, price, quantity).with_account(Some(buyer));
+        let ask_trade = TradeInfo::new(2, price, quantity).with_account(Some(seller));
+        Trade::new(1, Side::Buy, 0, bid_trade, ask_trade)
+    }
+
+    fn position(tracker: &PositionTracker, account_id: AccountId) -> PositionSnapshot {
+        tracker.get_position(account_id, SYMBOL).unwrap()
+    }
+
+    #[test]
+    fn adds_move_the_average_entry_price_reduces_dont() {
+        let mut tracker = PositionTracker::new(MarkSource::LastTrade);
+        tracker.on_trades(SYMBOL, &vec![trade(1, 2, 100.0, 10), trade(1, 2, 110.0, 10)]);
+        let long = position(&tracker, 1).position;
+        assert_eq!((long.get_quantity(), long.get_average_entry_price()), (20, Amount::from(105.0)));
+        let short = position(&tracker, 2).position;
+        assert_eq!((short.get_quantity(), short.get_average_entry_price()), (-20, Amount::from(105.0)));
+
+        tracker.on_trade(SYMBOL, &trade(2, 1, 120.0, 5));
+        let long = position(&tracker, 1).position;
+        assert_eq!((long.get_quantity(), long.get_average_entry_price()), (15, Amount::from(105.0)));
+        assert_eq!(long.get_realized_pnl(), Amount::from(75.0));
+        assert_eq!(position(&tracker, 2).position.get_realized_pnl(), Amount::from(-75.0));
+    }
+
+    #[test]
+    fn position_flips_through_flat() {
+        let mut tracker = PositionTracker::new(MarkSource::LastTrade);
+        tracker.on_trades(SYMBOL, &vec![trade(1, 2, 100.0, 10), trade(2, 1, 90.0, 25)]);
+
+        // 10 closed at a loss of 10 each, the other 15 open a short at 90
+        let flipped = position(&tracker, 1).position;
+        assert_eq!((flipped.get_quantity(), flipped.get_average_entry_price()), (-15, Amount::from(90.0)));
+        assert_eq!(flipped.get_realized_pnl(), Amount::from(-100.0));
+        let flipped = position(&tracker, 2).position;
+        assert_eq!((flipped.get_quantity(), flipped.get_average_entry_price()), (15, Amount::from(90.0)));
+        assert_eq!(flipped.get_realized_pnl(), Amount::from(100.0));
+
+        // back to flat, nothing left to mark
+        tracker.on_trade(SYMBOL, &trade(1, 2, 80.0, 15));
+        let flat = position(&tracker, 1);
+        assert_eq!((flat.position.get_quantity(), flat.position.get_average_entry_price()), (0, Amount::from(0.0)));
+        assert_eq!(flat.position.get_realized_pnl(), Amount::from(50.0));
+        assert_eq!(flat.unrealized_pnl, Amount::from(0.0));
+    }
+
+    #[test]
+    fn unrealized_pnl_follows_the_mark() {
+        let mut tracker = PositionTracker::new(MarkSource::Midpoint);
+        tracker.on_trades(SYMBOL, &vec![trade(1, 2, 100.0, 10), trade(2, 1, 104.0, 4)]);
+
+        // no quote yet, marked to the last trade
+        let long = position(&tracker, 1);
+        assert_eq!(long.mark_price, Some(OrderedFloat(104.0)));
+        assert_eq!(long.position.get_realized_pnl(), Amount::from(16.0));
+        assert_eq!(long.unrealized_pnl, Amount::from(24.0));
+
+        tracker.on_quote(SYMBOL, Some(OrderedFloat(97.0)), Some(OrderedFloat(99.0)));
+        let long = position(&tracker, 1);
+        assert_eq!(long.unrealized_pnl, Amount::from(-12.0));
+        assert_eq!(long.position.get_realized_pnl(), Amount::from(16.0));
+        assert_eq!(position(&tracker, 2).unrealized_pnl, Amount::from(12.0));
+
+        // a one sided book falls back to the last trade
+        tracker.on_quote(SYMBOL, None, Some(OrderedFloat(99.0)));
+        assert_eq!(position(&tracker, 1).unrealized_pnl, Amount::from(24.0));
+        assert!(tracker.get_position(3, SYMBOL).is_none());
+    }
+}
@@ -0,0 +1,54 @@
+use super::{OrderId, OrderPointer, OrderPointers, Quantity};
+
+// ----------------------------
+// PriceLevel holds every order resting at one price.
+// Displayed orders always have priority over hidden orders at the same price,
+// each queue on its own is time priority.
+// ----------------------------
+#[derive(Default)]
+pub struct PriceLevel {
+    displayed: OrderPointers,
+    hidden: OrderPointers,
+}
+impl PriceLevel {
+    pub fn new() -> Self {
+        Self { displayed: OrderPointers::new(), hidden: OrderPointers::new() }
+    }
+
+    pub fn push_back(&mut self, order: OrderPointer) {
+        if order.borrow().is_hidden() {
+            self.hidden.push_back(order);
+        } else {
+            self.displayed.push_back(order);
+        }
+    }
+
+    pub fn remove(&mut self, order_id: OrderId) -> Option<OrderPointer> {
+        for orders in [&mut self.displayed, &mut self.hidden] {
+            if let Some(index) = orders.iter().position(|order| order.borrow().get_order_id() == order_id) {
+                return orders.remove(index);
+            }
+        }
+        None
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.displayed.is_empty() && self.hidden.is_empty()
+    }
+
+    // Every order of the level in matching priority, displayed first.
+    pub fn iter(&self) -> impl Iterator<Item = &OrderPointer> {
+        self.displayed.iter().chain(self.hidden.iter())
+    }
+
+    pub fn get_displayed(&self) -> &OrderPointers {
+        &self.displayed
+    }
+    pub fn get_hidden(&self) -> &OrderPointers {
+        &self.hidden
+    }
+
+    pub fn get_displayed_quantity(&self) -> Quantity {
+        self.displayed.iter().map(|order| order.borrow().get_remaining_quantity()).sum()
+    }
+}
@@ -0,0 +1,473 @@
+use super::*;
+use crate::codec::{DecodeError, Decoder, Encoder};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// primary sends one when it has nothing new, so an idle backup still notices it fell behind.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// longest message accepted, a longer length prefix is an InvalidData error before anything is allocated.
+const MAX_FRAME_LENGTH: usize = 1 << 20;
+// latest entries the primary keeps in memory unless `with_max_buffered_entries` says otherwise.
+const DEFAULT_MAX_BUFFERED_ENTRIES: usize = 10_000;
+// entries read from the journal file at once by a backup catching up.
+const CATCH_UP_BATCH: usize = 1_000;
+
+// ----------------------------
+// Replication protocol, every message is a u32 length followed by the encoded message:
+// - backup -> primary: Hello (once per connection) and Retransmit.
+// - primary -> backup: Entry (one journaled command) and Heartbeat.
+// Entries are streamed in sequence order starting at the backup's Hello.
+// ----------------------------
+#[derive(Clone)]
+pub enum ReplicationMessage {
+    Hello { next_sequence: u64 },
+    Entry(Box<JournalEntry>),
+    Heartbeat { last_sequence: u64 },
+    Retransmit { from_sequence: u64 },
+}
+impl ReplicationMessage {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            ReplicationMessage::Hello { next_sequence } => {
+                encoder.put_u8(0);
+                encoder.put_u64(*next_sequence);
+            }
+            ReplicationMessage::Entry(entry) => {
+                encoder.put_u8(1);
+                encoder.put_u64(entry.sequence);
+                encoder.put_u64(entry.timestamp);
+                entry.command.encode(encoder);
+            }
+            ReplicationMessage::Heartbeat { last_sequence } => {
+                encoder.put_u8(2);
+                encoder.put_u64(*last_sequence);
+            }
+            ReplicationMessage::Retransmit { from_sequence } => {
+                encoder.put_u8(3);
+                encoder.put_u64(*from_sequence);
+            }
+        }
+    }
+
+    pub fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.get_u8()? {
+            0 => Ok(ReplicationMessage::Hello { next_sequence: decoder.get_u64()? }),
+            1 => Ok(ReplicationMessage::Entry(Box::new(JournalEntry {
+                sequence: decoder.get_u64()?,
+                timestamp: decoder.get_u64()?,
+                command: Command::decode(decoder)?,
+            }))),
+            2 => Ok(ReplicationMessage::Heartbeat { last_sequence: decoder.get_u64()? }),
+            3 => Ok(ReplicationMessage::Retransmit { from_sequence: decoder.get_u64()? }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn to_frame(&self) -> Vec<u8> {
+        let mut payload = Encoder::new();
+        self.encode(&mut payload);
+        let mut frame = Encoder::new();
+        frame.put_u32(payload.len() as u32);
+        frame.put_bytes(payload.get_bytes());
+        frame.into_bytes()
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        stream.write_all(&self.to_frame())
+    }
+
+    fn read_from(stream: &mut impl Read) -> io::Result<Self> {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length)?;
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_FRAME_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too long", length)));
+        }
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload)?;
+        Self::decode(&mut Decoder::new(&payload)).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+// The latest entry frames, `frames[0]` is `first_sequence`.
+struct ReplicationLog {
+    first_sequence: u64,
+    frames: VecDeque<Arc<Vec<u8>>>,
+    max_frames: usize,
+}
+impl ReplicationLog {
+    fn get_last_sequence(&self) -> u64 {
+        (self.first_sequence + self.frames.len() as u64).saturating_sub(1)
+    }
+}
+
+struct SharedLog {
+    log: Mutex<ReplicationLog>,
+    path: PathBuf, // the primary's journal, older entries are read from it
+    appended: Condvar,
+    shutdown: AtomicBool,
+}
+
+// ----------------------------
+// ReplicationPrimary serves the journal to any number of backups over TCP,
+// one thread per backup streams entries as they're published, the matching thread
+// only encodes the entry and never waits on the network.
+// Only the latest entries are kept in memory, a backup further behind catches up
+// from the journal file on its own thread.
+// ----------------------------
+pub struct ReplicationPrimary {
+    shared: Arc<SharedLog>,
+    local_address: std::net::SocketAddr,
+}
+impl ReplicationPrimary {
+    // `path` is the primary's journal, entries published from now on have to be appended to it first.
+    pub fn bind(address: impl ToSocketAddrs, path: impl AsRef<Path>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        let log = ReplicationLog { first_sequence: 0, frames: VecDeque::new(), max_frames: DEFAULT_MAX_BUFFERED_ENTRIES };
+        let shared = Arc::new(SharedLog {
+            log: Mutex::new(log),
+            path: path.as_ref().to_path_buf(),
+            appended: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let accept_shared = shared.clone();
+        thread::spawn(move || Self::accept_backups(listener, accept_shared));
+        Ok(Self { shared, local_address })
+    }
+
+    pub fn with_max_buffered_entries(self, max_buffered_entries: usize) -> Self {
+        self.shared.log.lock().unwrap().max_frames = max_buffered_entries.max(1);
+        self
+    }
+
+    pub fn get_local_address(&self) -> std::net::SocketAddr {
+        self.local_address
+    }
+
+    // Queues an entry for every backup, entries must be published in sequence order.
+    pub fn publish(&self, entry: &JournalEntry) {
+        let frame = Arc::new(ReplicationMessage::Entry(Box::new(entry.clone())).to_frame());
+        let mut log = self.shared.log.lock().unwrap();
+        if log.frames.is_empty() {
+            log.first_sequence = entry.sequence;
+        }
+        debug_assert_eq!(entry.sequence, log.first_sequence + log.frames.len() as u64);
+        log.frames.push_back(frame);
+        if log.frames.len() > log.max_frames {
+            log.frames.pop_front();
+            log.first_sequence += 1;
+        }
+        drop(log);
+        self.shared.appended.notify_all();
+    }
+
+    fn accept_backups(listener: TcpListener, shared: Arc<SharedLog>) {
+        while !shared.shutdown.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                        let _ = Self::serve_backup(stream, shared);
+                    });
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn serve_backup(mut stream: TcpStream, shared: Arc<SharedLog>) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        let ReplicationMessage::Hello { next_sequence } = ReplicationMessage::read_from(&mut stream)? else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "expected hello"));
+        };
+
+        // retransmit requests come in on their own thread and rewind the position
+        let position = Arc::new(AtomicU64::new(next_sequence));
+        let mut reader = stream.try_clone()?;
+        let reader_position = position.clone();
+        let reader_shared = shared.clone();
+        thread::spawn(move || {
+            while let Ok(message) = ReplicationMessage::read_from(&mut reader) {
+                if let ReplicationMessage::Retransmit { from_sequence } = message {
+                    reader_position.store(from_sequence, Ordering::SeqCst);
+                    reader_shared.appended.notify_all();
+                }
+            }
+        });
+
+        loop {
+            if shared.shutdown.load(Ordering::Relaxed) {
+                return stream.shutdown(Shutdown::Both);
+            }
+            let from_sequence = position.load(Ordering::SeqCst);
+            let in_memory = {
+                let log = shared.log.lock().unwrap();
+                !log.frames.is_empty() && from_sequence >= log.first_sequence
+            };
+            // entries are journaled before they're published, the file has everything memory has
+            if !in_memory {
+                let entries = Journal::read_range(&shared.path, from_sequence, CATCH_UP_BATCH)?;
+                if !entries.is_empty() {
+                    for entry in &entries {
+                        stream.write_all(&ReplicationMessage::Entry(Box::new(entry.clone())).to_frame())?;
+                    }
+                    let _ = position.compare_exchange(from_sequence, from_sequence + entries.len() as u64, Ordering::SeqCst, Ordering::SeqCst);
+                    continue;
+                }
+            }
+
+            let (frames, last_sequence) = {
+                let log = shared.log.lock().unwrap();
+                let log = if log.frames.is_empty() || log.get_last_sequence() < from_sequence {
+                    shared.appended.wait_timeout(log, HEARTBEAT_INTERVAL).unwrap().0
+                } else {
+                    log
+                };
+                if from_sequence < log.first_sequence {
+                    continue; // fell out of memory meanwhile, back to the file
+                }
+                let start = (from_sequence - log.first_sequence) as usize;
+                (log.frames.range(start.min(log.frames.len())..).cloned().collect::<Vec<_>>(), log.get_last_sequence())
+            };
+
+            if frames.is_empty() {
+                ReplicationMessage::Heartbeat { last_sequence }.write_to(&mut stream)?;
+                continue;
+            }
+            for frame in &frames {
+                stream.write_all(frame)?;
+            }
+            // a retransmit request that came in meanwhile wins
+            let _ = position.compare_exchange(from_sequence, from_sequence + frames.len() as u64, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+}
+impl Drop for ReplicationPrimary {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        self.shared.appended.notify_all();
+    }
+}
+
+// ----------------------------
+// ReplicationBackup is a hot standby: it journals and applies the primary's entries
+// to its own engine in sequence order, so it's always one promotion away from taking over.
+// An entry past the next expected sequence (or a heartbeat showing the primary is ahead)
+// is a gap, out of order entries are held back and the missing ones are asked for again.
+// The network is read on its own thread, `poll` applies what arrived on the caller's thread.
+// ----------------------------
+pub struct ReplicationBackup {
+    engine: JournaledEngine,
+    stream: TcpStream,
+    receiver: Receiver<ReplicationMessage>,
+    pending: BTreeMap<u64, JournalEntry>, // received ahead of a gap
+    requested_from: Option<u64>,          // last retransmit request, not repeated until a heartbeat
+}
+impl ReplicationBackup {
+    // Opens (and replays) the backup's own journal at `path` into `engine`,
+    // then asks the primary at `address` for everything after it.
+    pub fn connect(address: impl ToSocketAddrs, path: impl AsRef<Path>, engine: Engine) -> io::Result<Self> {
+        let engine = JournaledEngine::open(path, engine)?;
+        let (stream, receiver) = Self::open_stream(address, engine.get_journal().get_next_sequence())?;
+        Ok(Self { engine, stream, receiver, pending: BTreeMap::new(), requested_from: None })
+    }
+
+    // Reconnects after the primary went away, picking up at the next expected sequence.
+    pub fn reconnect(&mut self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let (stream, receiver) = Self::open_stream(address, self.get_next_sequence())?;
+        self.stream = stream;
+        self.receiver = receiver;
+        self.pending.clear();
+        self.requested_from = None;
+        Ok(())
+    }
+
+    fn open_stream(address: impl ToSocketAddrs, next_sequence: u64) -> io::Result<(TcpStream, Receiver<ReplicationMessage>)> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        ReplicationMessage::Hello { next_sequence }.write_to(&mut stream)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            while let Ok(message) = ReplicationMessage::read_from(&mut reader) {
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok((stream, receiver))
+    }
+
+    // Applies everything received so far without waiting, returns how many entries were applied.
+    // An error means the connection to the primary is gone.
+    pub fn poll(&mut self) -> io::Result<usize> {
+        let mut applied = 0;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => applied += self.on_message(message)?,
+                Err(TryRecvError::Empty) => return Ok(applied),
+                Err(TryRecvError::Disconnected) => return Err(io::ErrorKind::ConnectionAborted.into()),
+            }
+        }
+    }
+
+    // Same as poll but waits up to `timeout` for the first message.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> io::Result<usize> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => Ok(self.on_message(message)? + self.poll()?),
+            Err(RecvTimeoutError::Timeout) => Ok(0),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+
+    fn on_message(&mut self, message: ReplicationMessage) -> io::Result<usize> {
+        match message {
+            ReplicationMessage::Entry(entry) => {
+                let next_sequence = self.get_next_sequence();
+                if entry.sequence < next_sequence {
+                    return Ok(0); // duplicate of a retransmission
+                }
+                if entry.sequence > next_sequence {
+                    self.pending.insert(entry.sequence, *entry);
+                    if self.requested_from != Some(next_sequence) {
+                        self.request_retransmit(next_sequence)?;
+                    }
+                    return Ok(0);
+                }
+                self.engine.apply_replicated(&entry)?;
+                let mut applied = 1;
+                while let Some(entry) = self.pending.remove(&self.get_next_sequence()) {
+                    self.engine.apply_replicated(&entry)?;
+                    applied += 1;
+                }
+                self.pending.retain(|sequence, _| *sequence >= self.engine.get_journal().get_next_sequence());
+                if self.pending.is_empty() {
+                    self.requested_from = None;
+                }
+                Ok(applied)
+            }
+            ReplicationMessage::Heartbeat { last_sequence } => {
+                let next_sequence = self.get_next_sequence();
+                if last_sequence >= next_sequence {
+                    self.request_retransmit(next_sequence)?;
+                }
+                Ok(0)
+            }
+            ReplicationMessage::Hello { .. } | ReplicationMessage::Retransmit { .. } => Ok(0),
+        }
+    }
+
+    fn request_retransmit(&mut self, from_sequence: u64) -> io::Result<()> {
+        self.requested_from = Some(from_sequence);
+        ReplicationMessage::Retransmit { from_sequence }.write_to(&mut self.stream)
+    }
+
+    pub fn get_next_sequence(&self) -> u64 {
+        self.engine.get_journal().get_next_sequence()
+    }
+
+    pub fn get_state_hash(&self) -> u64 {
+        self.engine.get_state_hash()
+    }
+
+    pub fn get_engine(&self) -> &Engine {
+        self.engine.get_engine()
+    }
+
+    // Failover: stops following the primary, entries held back behind a gap are dropped.
+    // The returned engine journals its own commands from the next sequence on,
+    // give it a ReplicationPrimary to serve backups of its own.
+    pub fn promote(self) -> JournaledEngine {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    const SYMBOL: &str = "DEMO";
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("orderbook-rs-{}-{}.journal", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn new_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.add_instrument(SYMBOL, OrderBook::new());
+        engine
+    }
+
+    fn add_order(engine: &mut JournaledEngine, order_id: OrderId) {
+        let side = if order_id.is_multiple_of(2) { Side::Buy } else { Side::Sell };
+        let price = 100.0 + (order_id % 3) as f32 - 1.0;
+        engine.add_order(SYMBOL, Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), 1 + (order_id % 4) as Quantity)).unwrap();
+    }
+
+    fn catch_up(backup: &mut ReplicationBackup, primary: &JournaledEngine) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backup.get_next_sequence() < primary.get_journal().get_next_sequence() {
+            assert!(Instant::now() < deadline, "backup stuck at {}", backup.get_next_sequence());
+            backup.poll_timeout(Duration::from_millis(100)).unwrap();
+        }
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let mut frame = (MAX_FRAME_LENGTH as u32 + 1).to_le_bytes().to_vec();
+        frame.extend_from_slice(&[0; 16]);
+        let error = ReplicationMessage::read_from(&mut Cursor::new(frame)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let frame = ReplicationMessage::Heartbeat { last_sequence: 7 }.to_frame();
+        assert!(matches!(ReplicationMessage::read_from(&mut Cursor::new(frame)), Ok(ReplicationMessage::Heartbeat { last_sequence: 7 })));
+    }
+
+    #[test]
+    fn backup_follows_the_primary() {
+        let primary_path = journal_path("replication-primary");
+        let mut primary = JournaledEngine::open(&primary_path, new_engine()).unwrap();
+        // journaled before there's a primary to publish them, and more than it keeps in memory
+        for order_id in 1..=4 {
+            add_order(&mut primary, order_id);
+        }
+        let replication = ReplicationPrimary::bind("127.0.0.1:0", &primary_path).unwrap().with_max_buffered_entries(2);
+        let address = replication.get_local_address();
+        primary.set_replication(replication);
+        for order_id in 5..=9 {
+            add_order(&mut primary, order_id);
+        }
+
+        let mut backup = ReplicationBackup::connect(address, journal_path("replication-backup"), new_engine()).unwrap();
+        catch_up(&mut backup, &primary);
+        assert_eq!(backup.get_state_hash(), primary.get_state_hash());
+
+        for order_id in 10..=12 {
+            add_order(&mut primary, order_id);
+        }
+        primary.cancel_order(SYMBOL, 9).unwrap();
+        catch_up(&mut backup, &primary);
+        assert_eq!(backup.get_state_hash(), primary.get_state_hash());
+        assert_eq!(backup.get_next_sequence(), 14);
+    }
+}
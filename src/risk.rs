@@ -0,0 +1,411 @@
+use super::*;
+
+const ONE_SECOND: Timestamp = 1_000_000_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RejectReason {
+    MissingAccount,     // every order has to belong to an account.
+    UnknownOrder,       // cancel or modify of an order this layer doesn't know about.
+    MaxOpenOrders,
+    MaxGrossNotional,
+    MaxPosition,
+    MaxOrderRate,
+    NoReferencePrice,   // order has no price and nothing traded yet to value it at.
+    RejectedByBook,     // passed the risk checks, but the orderbook refused it.
+    InsufficientBalance,
+    MissingQuoteBudget, // market buys of a spot book have to say how much quote they may spend.
+    AccountMismatch,    // orders of one group have to belong to the same account.
+}
+
+// ----------------------------
+// RiskLimits of one account:
+// - max_open_orders: resting and stop orders at the same time.
+// - max_gross_notional: sum of price * remaining quantity of open orders.
+// - max_position: absolute net position, counting every open order on the same side as filled.
+// - max_orders_per_second: new and modified orders over the last second.
+// ----------------------------
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RiskLimits {
+    max_open_orders: usize,
+    max_gross_notional: f64,
+    max_position: i64,
+    max_orders_per_second: usize,
+}
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self::new(usize::MAX, f64::INFINITY, i64::MAX, usize::MAX)
+    }
+}
+impl RiskLimits {
+    pub fn new(max_open_orders: usize, max_gross_notional: f64, max_position: i64, max_orders_per_second: usize) -> Self {
+        Self { max_open_orders, max_gross_notional, max_position, max_orders_per_second }
+    }
+    pub fn get_max_open_orders(&self) -> usize {
+        self.max_open_orders
+    }
+    pub fn get_max_gross_notional(&self) -> f64 {
+        self.max_gross_notional
+    }
+    pub fn get_max_position(&self) -> i64 {
+        self.max_position
+    }
+    pub fn get_max_orders_per_second(&self) -> usize {
+        self.max_orders_per_second
+    }
+}
+
+#[derive(Default)]
+pub struct AccountExposure {
+    open_orders: BTreeSet<OrderId>,
+    position: i64, // net filled quantity, buys positive
+    order_times: VecDeque<Timestamp>, // accepted orders of the last second
+}
+impl AccountExposure {
+    pub fn get_open_orders(&self) -> &BTreeSet<OrderId> {
+        &self.open_orders
+    }
+    pub fn get_position(&self) -> i64 {
+        self.position
+    }
+}
+
+// ----------------------------
+// RiskCheckedOrderBook: every order goes through the account's limits before
+// it reaches the orderbook. Positions follow the trades, open orders follow
+// what's still in the book after every call (fills, cancels, linked orders).
+// OCO and bracket orders are checked as a whole when they're submitted and every order
+// of the group counts as open from then on, bracket legs included before they're activated.
+// ----------------------------
+pub struct RiskCheckedOrderBook {
+    book: OrderBook,
+    default_limits: RiskLimits,
+    limits: HashMap<AccountId, RiskLimits>,
+    exposures: HashMap<AccountId, AccountExposure>,
+    owners: HashMap<OrderId, AccountId>,
+}
+impl RiskCheckedOrderBook {
+    pub fn new(book: OrderBook, default_limits: RiskLimits) -> Self {
+        Self {
+            book,
+            default_limits,
+            limits: HashMap::new(),
+            exposures: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, account_id: AccountId, limits: RiskLimits) {
+        self.limits.insert(account_id, limits);
+    }
+
+    pub fn get_limits(&self, account_id: AccountId) -> RiskLimits {
+        self.limits.get(&account_id).copied().unwrap_or(self.default_limits)
+    }
+
+    pub fn get_exposure(&self, account_id: AccountId) -> Option<&AccountExposure> {
+        self.exposures.get(&account_id)
+    }
+
+    pub fn get_book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn into_book(self) -> OrderBook {
+        self.book
+    }
+
+    // Open orders are valued at their own price, orders without one (market, stop)
+    // at the last trade price.
+    fn get_order_price(&self, order: &Order) -> Option<Price> {
+        let price = order.get_price();
+        if price.is_nan() { self.book.get_last_trade_price() } else { Some(price) }
+    }
+
+    fn get_open_notional(&self, exposure: &AccountExposure, excluded_order_id: Option<OrderId>) -> f64 {
+        exposure
+            .open_orders
+            .iter()
+            .filter(|order_id| Some(**order_id) != excluded_order_id)
+            .filter_map(|order_id| self.book.get_order(*order_id))
+            .map(|order| {
+                let order = order.borrow();
+                let price = self.get_order_price(&order).map(|price| price.into_inner() as f64).unwrap_or(0.0);
+                price * order.get_remaining_quantity() as f64
+            })
+            .sum()
+    }
+
+    fn get_open_quantity(&self, exposure: &AccountExposure, side: Side, excluded_order_id: Option<OrderId>) -> i64 {
+        exposure
+            .open_orders
+            .iter()
+            .filter(|order_id| Some(**order_id) != excluded_order_id)
+            .filter_map(|order_id| self.book.get_order(*order_id))
+            .filter(|order| order.borrow().get_side() == side)
+            .map(|order| order.borrow().get_remaining_quantity() as i64)
+            .sum()
+    }
+
+    // `orders` enter the book together (one order or a whole group) and are checked as if all of them were open.
+    // `replaced_order_id` is the order a modify replaces, it doesn't count against the limits.
+    fn check(&self, account_id: AccountId, orders: &[&Order], replaced_order_id: Option<OrderId>, now: Timestamp) -> Result<(), RejectReason> {
+        let limits = self.get_limits(account_id);
+        let empty = AccountExposure::default();
+        let exposure = self.exposures.get(&account_id).unwrap_or(&empty);
+
+        let recent_orders = exposure.order_times.iter().filter(|time| now.saturating_sub(**time) < ONE_SECOND).count();
+        if recent_orders + orders.len() > limits.max_orders_per_second {
+            return Err(RejectReason::MaxOrderRate);
+        }
+
+        let open_orders = exposure.open_orders.len().saturating_sub(usize::from(replaced_order_id.is_some()));
+        if open_orders + orders.len() > limits.max_open_orders {
+            return Err(RejectReason::MaxOpenOrders);
+        }
+
+        let mut notional = self.get_open_notional(exposure, replaced_order_id);
+        for order in orders {
+            let Some(price) = self.get_order_price(order) else {
+                return Err(RejectReason::NoReferencePrice);
+            };
+            notional += price.into_inner() as f64 * order.get_remaining_quantity() as f64;
+        }
+        if notional > limits.max_gross_notional {
+            return Err(RejectReason::MaxGrossNotional);
+        }
+
+        for side in [Side::Buy, Side::Sell] {
+            let mut orders_on_side = orders.iter().filter(|order| order.get_side() == side).peekable();
+            if orders_on_side.peek().is_none() {
+                continue;
+            }
+            let quantity: i64 = orders_on_side.map(|order| order.get_remaining_quantity() as i64).sum();
+            let open_quantity = self.get_open_quantity(exposure, side, replaced_order_id) + quantity;
+            let worst_position = match side {
+                Side::Buy => exposure.position + open_quantity,
+                Side::Sell => exposure.position - open_quantity,
+            };
+            if worst_position.abs() > limits.max_position {
+                return Err(RejectReason::MaxPosition);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_group_account(orders: &[&OrderPointer]) -> Result<AccountId, RejectReason> {
+        let account_id = orders[0].borrow().get_account_id().ok_or(RejectReason::MissingAccount)?;
+        for order in &orders[1..] {
+            match order.borrow().get_account_id() {
+                None => return Err(RejectReason::MissingAccount),
+                Some(other_account_id) if other_account_id != account_id => return Err(RejectReason::AccountMismatch),
+                Some(_) => {}
+            }
+        }
+        Ok(account_id)
+    }
+
+    // Positions from the trades, open orders of `account_id` and of every account
+    // that traded are checked against the book again.
+    fn update_exposures(&mut self, account_id: AccountId, trades: &Trades) {
+        let mut accounts = BTreeSet::from([account_id]);
+        for trade in trades {
+            let bid_trade = trade.get_bid_trade();
+            let ask_trade = trade.get_ask_trade();
+            if let Some(bid_account_id) = bid_trade.account_id {
+                self.exposures.entry(bid_account_id).or_default().position += bid_trade.quantity as i64;
+                accounts.insert(bid_account_id);
+            }
+            if let Some(ask_account_id) = ask_trade.account_id {
+                self.exposures.entry(ask_account_id).or_default().position -= ask_trade.quantity as i64;
+                accounts.insert(ask_account_id);
+            }
+        }
+
+        for account_id in accounts {
+            let Some(exposure) = self.exposures.get_mut(&account_id) else {
+                continue;
+            };
+            let book = &self.book;
+            let owners = &mut self.owners;
+            exposure.open_orders.retain(|order_id| {
+                let is_open = book.get_order(*order_id).is_some();
+                if !is_open {
+                    owners.remove(order_id);
+                }
+                is_open
+            });
+        }
+    }
+
+    fn record_order(&mut self, account_id: AccountId, order_id: OrderId, now: Timestamp) {
+        let exposure = self.exposures.entry(account_id).or_default();
+        while exposure.order_times.front().is_some_and(|time| now.saturating_sub(*time) >= ONE_SECOND) {
+            exposure.order_times.pop_front();
+        }
+        exposure.order_times.push_back(now);
+        exposure.open_orders.insert(order_id);
+        self.owners.insert(order_id, account_id);
+    }
+
+    pub fn add_order(&mut self, order: OrderPointer) -> Result<Trades, RejectReason> {
+        self.add(order, None)
+    }
+
+    pub fn add_stop_order(&mut self, order: OrderPointer, trigger: Trigger) -> Result<Trades, RejectReason> {
+        self.add(order, Some(trigger))
+    }
+
+    fn add(&mut self, order: OrderPointer, trigger: Option<Trigger>) -> Result<Trades, RejectReason> {
+        let (account_id, order_id) = {
+            let order = order.borrow();
+            (order.get_account_id().ok_or(RejectReason::MissingAccount)?, order.get_order_id())
+        };
+        let now = self.book.get_clock().now();
+        self.check(account_id, &[&order.borrow()], None, now)?;
+
+        let trades = match trigger {
+            Some(trigger) => self.book.add_stop_order(order, trigger),
+            None => self.book.add_order(order),
+        }
+        .ok_or(RejectReason::RejectedByBook)?;
+
+        self.record_order(account_id, order_id, now);
+        self.update_exposures(account_id, &trades);
+        Ok(trades)
+    }
+
+    pub fn add_oco_orders(&mut self, first: OrderLeg, second: OrderLeg) -> Result<(GroupId, Trades), RejectReason> {
+        let orders = [first.get_order().clone(), second.get_order().clone()];
+        let account_id = Self::get_group_account(&[&orders[0], &orders[1]])?;
+        let now = self.book.get_clock().now();
+        self.check(account_id, &[&orders[0].borrow(), &orders[1].borrow()], None, now)?;
+
+        let (group_id, trades) = self.book.add_oco_orders(first, second).ok_or(RejectReason::RejectedByBook)?;
+        for order in &orders {
+            self.record_order(account_id, order.borrow().get_order_id(), now);
+        }
+        self.update_exposures(account_id, &trades);
+        Ok((group_id, trades))
+    }
+
+    pub fn add_bracket_order(&mut self, entry: OrderPointer, take_profit: OrderLeg, stop_loss: OrderLeg) -> Result<(GroupId, Trades), RejectReason> {
+        let orders = [entry.clone(), take_profit.get_order().clone(), stop_loss.get_order().clone()];
+        let account_id = Self::get_group_account(&[&orders[0], &orders[1], &orders[2]])?;
+        let now = self.book.get_clock().now();
+        self.check(account_id, &[&orders[0].borrow(), &orders[1].borrow(), &orders[2].borrow()], None, now)?;
+
+        let (group_id, trades) = self.book.add_bracket_order(entry, take_profit, stop_loss).ok_or(RejectReason::RejectedByBook)?;
+        for order in &orders {
+            self.record_order(account_id, order.borrow().get_order_id(), now);
+        }
+        self.update_exposures(account_id, &trades);
+        Ok((group_id, trades))
+    }
+
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<Trades, RejectReason> {
+        let account_id = *self.owners.get(&order_id).ok_or(RejectReason::UnknownOrder)?;
+        let trades = self.book.cancel_order(order_id);
+        self.update_exposures(account_id, &trades);
+        Ok(trades)
+    }
+
+    pub fn modify_order(&mut self, order: OrderModify) -> Result<Trades, RejectReason> {
+        let order_id = order.get_order_id();
+        let account_id = *self.owners.get(&order_id).ok_or(RejectReason::UnknownOrder)?;
+        let original = self.book.get_order(order_id).ok_or(RejectReason::UnknownOrder)?;
+        let modified = order.to_order_pointer(&original.borrow());
+
+        let now = self.book.get_clock().now();
+        self.check(account_id, &[&modified.borrow()], Some(order_id), now)?;
+
+        let trades = self.book.modify_order(order).ok_or(RejectReason::RejectedByBook)?;
+        self.record_order(account_id, order_id, now);
+        self.update_exposures(account_id, &trades);
+        Ok(trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn clock_going_back_doesnt_break_the_order_rate() {
+        let clock = Rc::new(ManualClock::new(10 * ONE_SECOND));
+        let mut book = OrderBook::new();
+        book.set_clock(clock.clone());
+        let mut risk = RiskCheckedOrderBook::new(book, RiskLimits::new(10, f64::MAX, i64::MAX, 2));
+        let order = |order_id: OrderId| Rc::new(RefCell::new(Order::new(order_id, OrderType::GoodTillCancel, Side::Buy, OrderedFloat(100.0), 1).with_account(1)));
+
+        risk.add_order(order(1)).unwrap();
+        // e.g. the wall clock was stepped back, earlier orders still count as recent
+        clock.set(5 * ONE_SECOND);
+        risk.add_order(order(2)).unwrap();
+        assert_eq!(risk.add_order(order(3)).err(), Some(RejectReason::MaxOrderRate));
+
+        clock.set(12 * ONE_SECOND);
+        risk.add_order(order(3)).unwrap();
+    }
+
+    fn limit(order_id: OrderId, account_id: AccountId, side: Side, price: f32, quantity: Quantity) -> OrderPointer {
+        Rc::new(RefCell::new(Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity).with_account(account_id)))
+    }
+
+    fn open_orders(risk: &RiskCheckedOrderBook, account_id: AccountId) -> Vec<OrderId> {
+        risk.get_exposure(account_id).unwrap().get_open_orders().iter().copied().collect()
+    }
+
+    #[test]
+    fn oco_orders_are_checked_together() {
+        let mut risk = RiskCheckedOrderBook::new(OrderBook::new(), RiskLimits::new(2, f64::MAX, 5, usize::MAX));
+        let oco = |first: OrderId, second: OrderId| (OrderLeg::Limit(limit(first, 1, Side::Sell, 104.0, 3)), OrderLeg::Limit(limit(second, 1, Side::Sell, 106.0, 3)));
+
+        // 6 to sell if both filled
+        let (first, second) = oco(1, 2);
+        assert_eq!(risk.add_oco_orders(first, second).err(), Some(RejectReason::MaxPosition));
+        risk.set_limits(1, RiskLimits::new(2, f64::MAX, 6, usize::MAX));
+        let (first, second) = oco(1, 2);
+        risk.add_oco_orders(first, second).unwrap();
+        assert_eq!(open_orders(&risk, 1), vec![1, 2]);
+        assert_eq!(risk.add_order(limit(3, 1, Side::Buy, 90.0, 1)).err(), Some(RejectReason::MaxOpenOrders));
+
+        // a fill of one cancels the other, both stop counting
+        risk.add_order(limit(4, 2, Side::Buy, 104.0, 3)).unwrap();
+        assert!(open_orders(&risk, 1).is_empty());
+        assert_eq!(risk.get_exposure(1).unwrap().get_position(), -3);
+    }
+
+    #[test]
+    fn bracket_legs_count_before_and_after_activation() {
+        let mut risk = RiskCheckedOrderBook::new(OrderBook::new(), RiskLimits::new(3, f64::MAX, i64::MAX, usize::MAX));
+        let take_profit = OrderLeg::Limit(limit(2, 1, Side::Sell, 110.0, 5));
+        let stop_loss = OrderLeg::Stop(limit(3, 1, Side::Sell, 90.0, 5), Trigger::Stop(OrderedFloat(95.0)));
+        risk.add_bracket_order(limit(1, 1, Side::Buy, 100.0, 5), take_profit, stop_loss).unwrap();
+        assert_eq!(open_orders(&risk, 1), vec![1, 2, 3]);
+        assert_eq!(risk.add_order(limit(4, 1, Side::Buy, 90.0, 1)).err(), Some(RejectReason::MaxOpenOrders));
+
+        // the entry fills, its legs are still the account's open orders
+        risk.add_order(limit(5, 2, Side::Sell, 100.0, 5)).unwrap();
+        assert_eq!(open_orders(&risk, 1), vec![2, 3]);
+        assert_eq!(risk.get_book().get_order(2).unwrap().borrow().get_remaining_quantity(), 5);
+        assert!(risk.get_book().get_trigger_price(3).is_some());
+        risk.cancel_order(2).unwrap();
+        assert!(open_orders(&risk, 1).is_empty());
+    }
+
+    #[test]
+    fn group_orders_need_one_account() {
+        let mut risk = RiskCheckedOrderBook::new(OrderBook::new(), RiskLimits::default());
+        let other_account = OrderLeg::Limit(limit(2, 2, Side::Sell, 106.0, 3));
+        assert_eq!(risk.add_oco_orders(OrderLeg::Limit(limit(1, 1, Side::Sell, 104.0, 3)), other_account).err(), Some(RejectReason::AccountMismatch));
+        let no_account = OrderLeg::Limit(Rc::new(RefCell::new(Order::new(3, OrderType::GoodTillCancel, Side::Sell, OrderedFloat(110.0), 5))));
+        let stop_loss = OrderLeg::Limit(limit(4, 1, Side::Sell, 90.0, 5));
+        assert_eq!(risk.add_bracket_order(limit(5, 1, Side::Buy, 100.0, 5), no_account, stop_loss).err(), Some(RejectReason::MissingAccount));
+        assert_eq!(risk.get_book().size(), 0);
+    }
+}
@@ -0,0 +1,83 @@
+use super::{BTreeMap, SessionId, Timestamp};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionState {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Session {
+    session_id: SessionId,
+    state: SessionState,
+    changed_at: Timestamp, // last connect or disconnect
+}
+impl Session {
+    pub fn new(session_id: SessionId, state: SessionState, changed_at: Timestamp) -> Self {
+        Self { session_id, state, changed_at }
+    }
+
+    pub fn get_session_id(&self) -> SessionId {
+        self.session_id
+    }
+    pub fn get_state(&self) -> SessionState {
+        self.state
+    }
+    pub fn get_changed_at(&self) -> Timestamp {
+        self.changed_at
+    }
+}
+
+// ----------------------------
+// SessionRegistry knows every gateway session the engine has seen,
+// only connected sessions can enter orders.
+// ----------------------------
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: BTreeMap<SessionId, Session>,
+}
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self { sessions: BTreeMap::new() }
+    }
+
+    // returns false if the session was already connected.
+    pub fn connect(&mut self, session_id: SessionId, now: Timestamp) -> bool {
+        if self.is_connected(session_id) {
+            return false;
+        }
+        self.sessions.insert(session_id, Session { session_id, state: SessionState::Connected, changed_at: now });
+        true
+    }
+
+    // returns false if the session wasn't connected.
+    pub fn disconnect(&mut self, session_id: SessionId, now: Timestamp) -> bool {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) if session.state == SessionState::Connected => {
+                session.state = SessionState::Disconnected;
+                session.changed_at = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Adds (or replaces) a session as is, used when restoring a snapshot.
+    pub fn insert(&mut self, session: Session) {
+        self.sessions.insert(session.session_id, session);
+    }
+
+    pub fn is_connected(&self, session_id: SessionId) -> bool {
+        self.sessions.get(&session_id).is_some_and(|session| session.state == SessionState::Connected)
+    }
+
+    pub fn get(&self, session_id: SessionId) -> Option<&Session> {
+        self.sessions.get(&session_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+}
@@ -0,0 +1,123 @@
+use super::*;
+use crate::codec::DecodeError;
+use crate::helperfns::crc32;
+use std::fmt;
+
+// header: magic, version u16, kind u8, payload length u32, checksum u32 (over the payload).
+const MAGIC: &[u8; 4] = b"OBSN";
+// bump whenever the payload layout changes, older snapshots are refused rather than misread.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotKind {
+    Book,   // a single OrderBook.
+    Engine, // every book of an Engine plus its sessions and journal position.
+}
+impl SnapshotKind {
+    fn get_tag(&self) -> u8 {
+        match self {
+            SnapshotKind::Book => 0,
+            SnapshotKind::Engine => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    WrongKind(u8),
+    ChecksumMismatch,
+    Decode(DecodeError),
+    TrailingBytes(usize),
+    UnknownOrder(OrderId),          // an id refers to an order that isn't in the snapshot.
+    UnknownSymbol(Symbol),          // the engine restored into has no book for this symbol.
+    SequenceBeforeSnapshot(u64),    // asked for a journal sequence older than the snapshot.
+    JournalGap(u64),                // the journal doesn't continue where the snapshot ends.
+}
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            SnapshotError::WrongKind(tag) => write!(f, "unexpected snapshot kind {}", tag),
+            SnapshotError::ChecksumMismatch => write!(f, "snapshot fails its checksum"),
+            SnapshotError::Decode(error) => write!(f, "snapshot decode error: {}", error),
+            SnapshotError::TrailingBytes(count) => write!(f, "{} unexpected bytes after the snapshot", count),
+            SnapshotError::UnknownOrder(order_id) => write!(f, "snapshot refers to unknown order {}", order_id),
+            SnapshotError::UnknownSymbol(symbol) => write!(f, "no book for snapshot symbol {}", symbol),
+            SnapshotError::SequenceBeforeSnapshot(sequence) => write!(f, "sequence {} is before the snapshot", sequence),
+            SnapshotError::JournalGap(sequence) => write!(f, "journal is missing sequence {}", sequence),
+        }
+    }
+}
+impl std::error::Error for SnapshotError {}
+impl From<DecodeError> for SnapshotError {
+    fn from(error: DecodeError) -> Self {
+        SnapshotError::Decode(error)
+    }
+}
+
+// Wraps an encoded payload with the snapshot header.
+pub fn seal(kind: SnapshotKind, payload: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder.put_bytes(MAGIC);
+    encoder.put_u16(SNAPSHOT_VERSION);
+    encoder.put_u8(kind.get_tag());
+    encoder.put_u32(payload.len() as u32);
+    encoder.put_u32(crc32(payload));
+    encoder.put_bytes(payload);
+    encoder.into_bytes()
+}
+
+// Checks the snapshot header and returns the payload.
+pub fn unseal(kind: SnapshotKind, bytes: &[u8]) -> Result<&[u8], SnapshotError> {
+    let mut decoder = Decoder::new(bytes);
+    if decoder.get_bytes(MAGIC.len())? != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = decoder.get_u16()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let tag = decoder.get_u8()?;
+    if tag != kind.get_tag() {
+        return Err(SnapshotError::WrongKind(tag));
+    }
+    let length = decoder.get_u32()? as usize;
+    let checksum = decoder.get_u32()?;
+    let payload = decoder.get_bytes(length)?;
+    if !decoder.is_finished() {
+        return Err(SnapshotError::TrailingBytes(decoder.get_remaining()));
+    }
+    if crc32(payload) != checksum {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+    Ok(payload)
+}
+
+// ----------------------------
+// Point in time recovery: `engine` (fresh, with the same instruments and running on `clock`) is restored
+// from `snapshot` if there's one, then the journal entries after the snapshot are
+// replayed up to and including `sequence` (u64::MAX for everything there is).
+// Returns the sequence of the last command the engine now includes.
+// ----------------------------
+pub fn restore_at(engine: &mut Engine, clock: &ManualClock, snapshot: Option<&[u8]>, entries: &[JournalEntry], sequence: u64) -> Result<u64, SnapshotError> {
+    let snapshot_sequence = match snapshot {
+        Some(bytes) => engine.restore_snapshot(bytes)?,
+        None => 0,
+    };
+    if sequence < snapshot_sequence {
+        return Err(SnapshotError::SequenceBeforeSnapshot(sequence));
+    }
+
+    let mut last_sequence = snapshot_sequence;
+    for entry in entries.iter().filter(|entry| entry.sequence > snapshot_sequence && entry.sequence <= sequence) {
+        if entry.sequence != last_sequence + 1 {
+            return Err(SnapshotError::JournalGap(last_sequence + 1));
+        }
+        entry.apply(engine, clock);
+        last_sequence = entry.sequence;
+    }
+    Ok(last_sequence)
+}
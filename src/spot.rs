@@ -0,0 +1,335 @@
+use super::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Asset {
+    Base,   // what's traded, one unit per unit of Quantity.
+    Quote,  // what prices are in, fees are charged in it too.
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssetBalance {
+    available: Amount,
+    reserved: Amount, // held by open orders
+}
+impl AssetBalance {
+    pub fn get_available(&self) -> Amount {
+        self.available
+    }
+    pub fn get_reserved(&self) -> Amount {
+        self.reserved
+    }
+    pub fn get_total(&self) -> Amount {
+        self.available + self.reserved
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpotAccount {
+    base: AssetBalance,
+    quote: AssetBalance,
+}
+impl SpotAccount {
+    pub fn get_balance(&self, asset: Asset) -> &AssetBalance {
+        match asset {
+            Asset::Base => &self.base,
+            Asset::Quote => &self.quote,
+        }
+    }
+    fn get_balance_mut(&mut self, asset: Asset) -> &mut AssetBalance {
+        match asset {
+            Asset::Base => &mut self.base,
+            Asset::Quote => &mut self.quote,
+        }
+    }
+}
+
+// ----------------------------
+// Reservation is what an open order holds from its account:
+// buys hold quote (limit price * quantity, or their whole quote budget)
+// plus the most they can pay in fees, sells hold base (quantity).
+// ----------------------------
+struct Reservation {
+    account_id: AccountId,
+    asset: Asset,
+    remaining: Amount,
+    fee_remaining: Amount, // buys, held for fees on top of `remaining`
+    limit_price: Option<Price>, // buys with a limit consume limit price * quantity per fill
+}
+
+fn to_amount(price: Price, quantity: Quantity) -> Amount {
+    Amount::from(price.into_inner() as f64 * quantity as f64)
+}
+
+// ----------------------------
+// SpotOrderBook: an orderbook where every order is backed by its account's balances.
+// Placing an order reserves funds, every trade settles both sides at once
+// (buyer gets base, seller gets quote, fees come out of quote),
+// whatever an order still holds once it leaves the book is released.
+// ----------------------------
+pub struct SpotOrderBook {
+    book: OrderBook,
+    accounts: HashMap<AccountId, SpotAccount>,
+    reservations: HashMap<OrderId, Reservation>,
+}
+impl SpotOrderBook {
+    pub fn new(book: OrderBook) -> Self {
+        Self { book, accounts: HashMap::new(), reservations: HashMap::new() }
+    }
+
+    pub fn get_book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn get_account(&self, account_id: AccountId) -> Option<&SpotAccount> {
+        self.accounts.get(&account_id)
+    }
+
+    pub fn deposit(&mut self, account_id: AccountId, asset: Asset, amount: Amount) {
+        self.accounts.entry(account_id).or_default().get_balance_mut(asset).available += amount;
+    }
+
+    pub fn withdraw(&mut self, account_id: AccountId, asset: Asset, amount: Amount) -> Result<(), RejectReason> {
+        let balance = self.accounts.entry(account_id).or_default().get_balance_mut(asset);
+        if balance.available < amount {
+            return Err(RejectReason::InsufficientBalance);
+        }
+        balance.available -= amount;
+        Ok(())
+    }
+
+    fn get_reservation(&self, account_id: AccountId, order: &Order) -> Result<Reservation, RejectReason> {
+        let reservation = |asset, remaining, fee_remaining, limit_price| Reservation { account_id, asset, remaining, fee_remaining, limit_price };
+        if order.get_side() == Side::Sell {
+            return Ok(reservation(Asset::Base, Amount::from(order.get_remaining_quantity() as f64), Amount::from(0.0), None));
+        }
+        if let Some(quote_budget) = order.get_quote_budget() {
+            return Ok(reservation(Asset::Quote, quote_budget, self.get_worst_case_fee(order), None));
+        }
+        if order.get_price().is_nan() {
+            return Err(RejectReason::MissingQuoteBudget);
+        }
+        let notional = to_amount(order.get_price(), order.get_remaining_quantity());
+        Ok(reservation(Asset::Quote, notional, self.get_worst_case_fee(order), Some(order.get_price())))
+    }
+
+    // Most a buy can pay in fees on what it still has to fill, as maker or taker,
+    // at its limit price (it only trades better) or on its whole quote budget. Rebates hold nothing.
+    fn get_worst_case_fee(&self, order: &Order) -> Amount {
+        let schedule = self.book.get_fee_model().get_schedule(order.get_account_id());
+        [schedule.get_maker(), schedule.get_taker()]
+            .into_iter()
+            .map(|rate| match (rate, order.get_quote_budget()) {
+                (FeeRate::BasisPoints(bps), Some(quote_budget)) => Amount::from(quote_budget.into_inner() * bps / 10_000.0),
+                (rate, _) => rate.get_fee(order.get_price(), order.get_remaining_quantity()),
+            })
+            .fold(Amount::from(0.0), Amount::max)
+    }
+
+    fn reserve(&mut self, account_id: AccountId, order: &Order) -> Result<(), RejectReason> {
+        let reservation = self.get_reservation(account_id, order)?;
+        let balance = self.accounts.entry(account_id).or_default().get_balance(reservation.asset);
+        if balance.available < reservation.remaining + reservation.fee_remaining {
+            return Err(RejectReason::InsufficientBalance);
+        }
+        self.hold(order.get_order_id(), reservation);
+        Ok(())
+    }
+
+    // Moves what `reservation` holds from available to reserved.
+    fn hold(&mut self, order_id: OrderId, reservation: Reservation) {
+        let amount = reservation.remaining + reservation.fee_remaining;
+        let balance = self.accounts.entry(reservation.account_id).or_default().get_balance_mut(reservation.asset);
+        balance.available -= amount;
+        balance.reserved += amount;
+        self.reservations.insert(order_id, reservation);
+    }
+
+    // Returns what the order held to its account's available balance.
+    fn release(&mut self, order_id: OrderId) -> Option<Reservation> {
+        let reservation = self.reservations.remove(&order_id)?;
+        let amount = reservation.remaining + reservation.fee_remaining;
+        let balance = self.accounts.entry(reservation.account_id).or_default().get_balance_mut(reservation.asset);
+        balance.reserved -= amount;
+        balance.available += amount;
+        Some(reservation)
+    }
+
+    // Consumes `amount` of what the order reserved, returns how much of it actually came from the reservation.
+    fn consume(&mut self, order_id: OrderId, amount: Amount) -> Amount {
+        let Some(reservation) = self.reservations.get_mut(&order_id) else {
+            return Amount::from(0.0);
+        };
+        let consumed = amount.min(reservation.remaining);
+        reservation.remaining -= consumed;
+        let balance = self.accounts.entry(reservation.account_id).or_default().get_balance_mut(reservation.asset);
+        balance.reserved -= consumed;
+        consumed
+    }
+
+    // Same as consume for what the order holds for fees, rebates consume nothing.
+    fn consume_fee(&mut self, order_id: OrderId, fee: Amount) -> Amount {
+        let Some(reservation) = self.reservations.get_mut(&order_id) else {
+            return Amount::from(0.0);
+        };
+        let consumed = fee.max(Amount::from(0.0)).min(reservation.fee_remaining);
+        reservation.fee_remaining -= consumed;
+        let balance = self.accounts.entry(reservation.account_id).or_default().get_balance_mut(reservation.asset);
+        balance.reserved -= consumed;
+        consumed
+    }
+
+    fn settle(&mut self, trade: &Trade) {
+        let price = trade.get_price();
+        let quantity = trade.get_quantity();
+        let notional = to_amount(price, quantity);
+        let bid_trade = *trade.get_bid_trade();
+        let ask_trade = *trade.get_ask_trade();
+
+        if let Some(account_id) = bid_trade.account_id {
+            // a limit buy reserved at its limit price, what it saved trading better goes back
+            let reserved_for_fill = match self.reservations.get(&bid_trade.order_id).and_then(|reservation| reservation.limit_price) {
+                Some(limit_price) => to_amount(limit_price, quantity),
+                None => notional,
+            };
+            let consumed = self.consume(bid_trade.order_id, reserved_for_fill);
+            let fee_consumed = self.consume_fee(bid_trade.order_id, bid_trade.fee);
+            let account = self.accounts.entry(account_id).or_default();
+            account.quote.available += consumed - notional + fee_consumed - bid_trade.fee;
+            account.base.available += Amount::from(quantity as f64);
+        }
+
+        if let Some(account_id) = ask_trade.account_id {
+            let consumed = self.consume(ask_trade.order_id, Amount::from(quantity as f64));
+            let account = self.accounts.entry(account_id).or_default();
+            account.base.available += consumed - Amount::from(quantity as f64);
+            account.quote.available += notional - ask_trade.fee;
+        }
+    }
+
+    // Settles `trades` and releases what orders that left the book still hold.
+    fn process_trades(&mut self, trades: &Trades) {
+        for trade in trades {
+            self.settle(trade);
+        }
+
+        let closed: BTreeSet<OrderId> = self
+            .reservations
+            .keys()
+            .filter(|order_id| self.book.get_order(**order_id).is_none())
+            .copied()
+            .collect();
+        for order_id in closed {
+            self.release(order_id);
+        }
+    }
+
+    // Market buys need a quote budget (`Order::with_quote_budget`), quantity alone can't be reserved.
+    pub fn add_order(&mut self, order: OrderPointer) -> Result<Trades, RejectReason> {
+        let (account_id, order_id) = {
+            let order = order.borrow();
+            (order.get_account_id().ok_or(RejectReason::MissingAccount)?, order.get_order_id())
+        };
+        if self.reservations.contains_key(&order_id) {
+            return Err(RejectReason::RejectedByBook);
+        }
+        self.reserve(account_id, &order.borrow())?;
+
+        let Some(trades) = self.book.add_order(order) else {
+            self.release(order_id);
+            return Err(RejectReason::RejectedByBook);
+        };
+        self.process_trades(&trades);
+        Ok(trades)
+    }
+
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<Trades, RejectReason> {
+        if !self.reservations.contains_key(&order_id) {
+            return Err(RejectReason::UnknownOrder);
+        }
+        let trades = self.book.cancel_order(order_id);
+        self.process_trades(&trades);
+        Ok(trades)
+    }
+
+    // The new order's reservation replaces what's left of the old one,
+    // which the order keeps if the book refuses the modify and still has it.
+    pub fn modify_order(&mut self, order: OrderModify) -> Result<Trades, RejectReason> {
+        let order_id = order.get_order_id();
+        let account_id = self.reservations.get(&order_id).ok_or(RejectReason::UnknownOrder)?.account_id;
+        let original = self.book.get_order(order_id).ok_or(RejectReason::UnknownOrder)?;
+        let modified = order.to_order_pointer(&original.borrow());
+
+        let previous = self.release(order_id).unwrap();
+        if let Err(reason) = self.reserve(account_id, &modified.borrow()) {
+            self.hold(order_id, previous);
+            return Err(reason);
+        }
+
+        let Some(trades) = self.book.modify_order(order) else {
+            self.release(order_id);
+            if self.book.get_order(order_id).is_some() {
+                self.hold(order_id, previous);
+            }
+            return Err(RejectReason::RejectedByBook);
+        };
+        self.process_trades(&trades);
+        Ok(trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    fn order(order_id: OrderId, account_id: AccountId, side: Side, price: f32, quantity: Quantity) -> OrderPointer {
+        Rc::new(RefCell::new(Order::new(order_id, OrderType::GoodTillCancel, side, OrderedFloat(price), quantity).with_account(account_id)))
+    }
+
+    fn assert_balance(spot: &SpotOrderBook, account_id: AccountId, asset: Asset, available: f64, reserved: f64) {
+        let balance = spot.get_account(account_id).unwrap().get_balance(asset);
+        assert!((balance.get_available().into_inner() - available).abs() < 1e-9, "available {} instead of {}", balance.get_available(), available);
+        assert!((balance.get_reserved().into_inner() - reserved).abs() < 1e-9, "reserved {} instead of {}", balance.get_reserved(), reserved);
+    }
+
+    #[test]
+    fn buyer_fee_is_reserved() {
+        let mut book = OrderBook::new();
+        book.set_fee_model(FeeModel::new(FeeSchedule::new(FeeRate::BasisPoints(5.0), FeeRate::BasisPoints(10.0))));
+        let mut spot = SpotOrderBook::new(book);
+        spot.deposit(2, Asset::Base, Amount::from(10.0));
+        spot.add_order(order(1, 2, Side::Sell, 100.0, 10)).unwrap();
+
+        // 10 @ 101 plus a 10 bps taker fee on it
+        spot.deposit(1, Asset::Quote, Amount::from(1010.0));
+        assert_eq!(spot.add_order(order(2, 1, Side::Buy, 101.0, 10)), Err(RejectReason::InsufficientBalance));
+        spot.deposit(1, Asset::Quote, Amount::from(1.01));
+        let trades = spot.add_order(order(3, 1, Side::Buy, 101.0, 10)).unwrap();
+        assert_eq!(trades.len(), 1);
+
+        // traded at 100 for a fee of 1, the rest of the reservation comes back
+        assert_balance(&spot, 1, Asset::Quote, 10.01, 0.0);
+        assert_balance(&spot, 1, Asset::Base, 10.0, 0.0);
+        assert_balance(&spot, 2, Asset::Quote, 999.5, 0.0);
+    }
+
+    #[test]
+    fn refused_modify_keeps_the_reservation() {
+        let mut spot = SpotOrderBook::new(OrderBook::new());
+        spot.deposit(1, Asset::Quote, Amount::from(1000.0));
+        spot.add_order(order(1, 1, Side::Buy, 99.0, 10)).unwrap();
+        assert_balance(&spot, 1, Asset::Quote, 10.0, 990.0);
+
+        spot.book.halt();
+        assert_eq!(spot.modify_order(OrderModify::new(1, Side::Buy, OrderedFloat(98.0), 10)).err(), Some(RejectReason::RejectedByBook));
+        assert_balance(&spot, 1, Asset::Quote, 10.0, 990.0);
+
+        spot.book.resume();
+        spot.cancel_order(1).unwrap();
+        assert_balance(&spot, 1, Asset::Quote, 1000.0, 0.0);
+    }
+}
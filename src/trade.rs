@@ -1,31 +1,101 @@
-use super::{OrderId, Price, Quantity};
+use super::{AccountId, Amount, OrderId, Price, Quantity, Side, Timestamp, TradeId};
 
+// ----------------------------
+// TradeInfo is one side of a trade, price is the execution price
+// (the passive order's price) on both sides.
+// fee is what this side was charged for the trade, negative for a rebate.
+// ----------------------------
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TradeInfo {
     pub order_id: OrderId,
+    pub account_id: Option<AccountId>,
     pub price: Price,
     pub quantity: Quantity,
+    pub fee: Amount,
 }
 impl TradeInfo {
     pub fn new(order_id: OrderId, price: Price, quantity: Quantity) -> Self {
-        Self { order_id, price, quantity }
+        Self { order_id, account_id: None, price, quantity, fee: Amount::from(0.0) }
+    }
+
+    pub fn with_account(mut self, account_id: Option<AccountId>) -> Self {
+        self.account_id = account_id;
+        self
+    }
+
+    pub fn with_fee(mut self, fee: Amount) -> Self {
+        self.fee = fee;
+        self
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Trade {
+    trade_id: TradeId, // unique and increasing within a book, or across an engine's books
+    price: Price,
+    quantity: Quantity,
+    aggressor_side: Side, // side of the order that took liquidity
+    timestamp: Timestamp, // nanoseconds since unix epoch
     bid_trade: TradeInfo,
     ask_trade: TradeInfo,
 }
 impl Trade {
-    pub fn new(bid_trade: TradeInfo, ask_trade: TradeInfo) -> Self {
+    pub fn new(
+        trade_id: TradeId,
+        aggressor_side: Side,
+        timestamp: Timestamp,
+        bid_trade: TradeInfo,
+        ask_trade: TradeInfo,
+    ) -> Self {
         Self {
+            trade_id,
+            price: bid_trade.price,
+            quantity: bid_trade.quantity,
+            aggressor_side,
+            timestamp,
             bid_trade,
             ask_trade,
         }
     }
+    pub fn get_trade_id(&self) -> TradeId {
+        self.trade_id
+    }
+    pub fn get_price(&self) -> Price {
+        self.price
+    }
+    pub fn get_quantity(&self) -> Quantity {
+        self.quantity
+    }
+    pub fn get_aggressor_side(&self) -> Side {
+        self.aggressor_side
+    }
+    pub fn get_timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
     pub fn get_bid_trade(&self) -> &TradeInfo {
         &self.bid_trade
     }
     pub fn get_ask_trade(&self) -> &TradeInfo {
         &self.ask_trade
     }
+    pub fn get_aggressor_trade(&self) -> &TradeInfo {
+        match self.aggressor_side {
+            Side::Buy => &self.bid_trade,
+            Side::Sell => &self.ask_trade,
+        }
+    }
+    pub fn get_passive_trade(&self) -> &TradeInfo {
+        match self.aggressor_side {
+            Side::Buy => &self.ask_trade,
+            Side::Sell => &self.bid_trade,
+        }
+    }
+    pub fn get_aggressor_order_id(&self) -> OrderId {
+        self.get_aggressor_trade().order_id
+    }
+    pub fn get_passive_order_id(&self) -> OrderId {
+        self.get_passive_trade().order_id
+    }
 }
@@ -0,0 +1,220 @@
+use super::{OrderId, OrderPointer, Price, Side};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trail {
+    Amount(Price),  // trigger stays this far away from the best print.
+    Percent(f32),   // trigger stays this percent (1.5 = 1.5%) away from the best print.
+}
+impl Trail {
+    fn get_distance(&self, price: Price) -> Price {
+        match *self {
+            Trail::Amount(amount) => amount,
+            Trail::Percent(percent) => price * percent / 100.0,
+        }
+    }
+
+    // a trail at or below zero (or NaN) would put the trigger at or through the market.
+    fn is_valid(&self) -> bool {
+        match *self {
+            Trail::Amount(amount) => amount.is_finite() && amount.0 > 0.0,
+            Trail::Percent(percent) => percent.is_finite() && percent > 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trigger {
+    Stop(Price),          // fixed trigger price.
+    TrailingStop(Trail),  // trigger price follows the market, only in the favourable direction.
+}
+
+// ----------------------------
+// StopOrder is an order waiting in the trigger book,
+// it's sent to `OrderBook::add_order` once the market trades through `trigger_price`.
+// buy stops fire when a trade prints at or above the trigger,
+// sell stops fire when a trade prints at or below the trigger.
+// ----------------------------
+pub struct StopOrder {
+    order: OrderPointer,
+    trigger: Trigger,
+    trigger_price: Price,
+}
+impl StopOrder {
+    pub fn get_order(&self) -> &OrderPointer {
+        &self.order
+    }
+    pub fn get_trigger(&self) -> Trigger {
+        self.trigger
+    }
+    pub fn get_trigger_price(&self) -> Price {
+        self.trigger_price
+    }
+
+    fn is_triggered(&self, price: Price) -> bool {
+        match self.order.borrow().get_side() {
+            Side::Buy => price >= self.trigger_price,
+            Side::Sell => price <= self.trigger_price,
+        }
+    }
+
+    // moves a trailing trigger towards `price`, never away from it.
+    fn trail(&mut self, price: Price) {
+        let Trigger::TrailingStop(trail) = self.trigger else {
+            return;
+        };
+        let distance = trail.get_distance(price);
+        match self.order.borrow().get_side() {
+            Side::Buy => self.trigger_price = self.trigger_price.min(price + distance),
+            Side::Sell => self.trigger_price = self.trigger_price.max(price - distance),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TriggerBook {
+    stops: Vec<StopOrder>, // in the order they entered, which is also the order they fire in
+}
+impl TriggerBook {
+    pub fn new() -> Self {
+        Self { stops: Vec::new() }
+    }
+
+    // A trailing stop needs a last trade price to start trailing from and a trail above zero,
+    // returns false (order not added) otherwise.
+    pub fn insert(&mut self, order: OrderPointer, trigger: Trigger, last_trade_price: Option<Price>) -> bool {
+        let side = order.borrow().get_side();
+        let trigger_price = match trigger {
+            Trigger::Stop(price) => price,
+            Trigger::TrailingStop(trail) => {
+                let Some(last_price) = last_trade_price.filter(|_| trail.is_valid()) else {
+                    return false;
+                };
+                match side {
+                    Side::Buy => last_price + trail.get_distance(last_price),
+                    Side::Sell => last_price - trail.get_distance(last_price),
+                }
+            }
+        };
+        self.stops.push(StopOrder { order, trigger, trigger_price });
+        true
+    }
+
+    // Puts back a stop with the trigger price it had, used when restoring a snapshot.
+    pub fn restore(&mut self, order: OrderPointer, trigger: Trigger, trigger_price: Price) {
+        self.stops.push(StopOrder { order, trigger, trigger_price });
+    }
+
+    pub fn remove(&mut self, order_id: OrderId) -> Option<OrderPointer> {
+        let index = self.stops.iter().position(|stop| stop.order.borrow().get_order_id() == order_id)?;
+        Some(self.stops.remove(index).order)
+    }
+
+    pub fn contains(&self, order_id: OrderId) -> bool {
+        self.get(order_id).is_some()
+    }
+
+    pub fn get(&self, order_id: OrderId) -> Option<&StopOrder> {
+        self.stops.iter().find(|stop| stop.order.borrow().get_order_id() == order_id)
+    }
+
+    pub fn get_trigger_price(&self, order_id: OrderId) -> Option<Price> {
+        self.get(order_id).map(|stop| stop.trigger_price)
+    }
+
+    pub fn size(&self) -> usize {
+        self.stops.len()
+    }
+
+    // In firing order.
+    pub fn iter(&self) -> impl Iterator<Item = &StopOrder> {
+        self.stops.iter()
+    }
+
+    // Feeds one trade print, every stop the print went through is removed
+    // and returned in firing order, trailing triggers of the others follow the print.
+    pub fn on_trade(&mut self, price: Price) -> Vec<OrderPointer> {
+        let mut triggered = Vec::new();
+        let mut index = 0;
+        while index < self.stops.len() {
+            let stop = &mut self.stops[index];
+            if stop.is_triggered(price) {
+                triggered.push(self.stops.remove(index).order);
+                continue;
+            }
+            stop.trail(price);
+            index += 1;
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Order, OrderType};
+    use ordered_float::OrderedFloat;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn stop(order_id: OrderId, side: Side) -> OrderPointer {
+        Rc::new(RefCell::new(Order::new(order_id, OrderType::Market, side, OrderedFloat(f32::NAN), 10)))
+    }
+
+    fn prints(triggers: &mut TriggerBook, prices: &[f32]) -> Vec<OrderId> {
+        let mut fired = Vec::new();
+        for price in prices {
+            fired.extend(triggers.on_trade(OrderedFloat(*price)).iter().map(|order| order.borrow().get_order_id()));
+        }
+        fired
+    }
+
+    #[test]
+    fn trail_must_be_above_zero() {
+        let mut triggers = TriggerBook::new();
+        let last_price = Some(OrderedFloat(100.0));
+        for trail in [Trail::Amount(OrderedFloat(0.0)), Trail::Amount(OrderedFloat(-1.0)), Trail::Amount(OrderedFloat(f32::NAN)), Trail::Percent(0.0), Trail::Percent(-2.0)] {
+            assert!(!triggers.insert(stop(1, Side::Sell), Trigger::TrailingStop(trail), last_price));
+        }
+        assert!(!triggers.insert(stop(1, Side::Sell), Trigger::TrailingStop(Trail::Amount(OrderedFloat(2.0))), None));
+        assert_eq!(triggers.size(), 0);
+    }
+
+    #[test]
+    fn sell_trailing_stop_follows_only_rises() {
+        let mut triggers = TriggerBook::new();
+        assert!(triggers.insert(stop(1, Side::Sell), Trigger::TrailingStop(Trail::Amount(OrderedFloat(2.0))), Some(OrderedFloat(100.0))));
+        assert_eq!(triggers.get_trigger_price(1), Some(OrderedFloat(98.0)));
+
+        assert!(prints(&mut triggers, &[103.0, 102.0, 104.0]).is_empty());
+        assert_eq!(triggers.get_trigger_price(1), Some(OrderedFloat(102.0)));
+        // falls back through the trigger
+        assert_eq!(prints(&mut triggers, &[102.5, 102.0]), vec![1]);
+        assert_eq!(triggers.get_trigger_price(1), None);
+    }
+
+    #[test]
+    fn buy_trailing_stop_follows_only_falls() {
+        let mut triggers = TriggerBook::new();
+        assert!(triggers.insert(stop(1, Side::Buy), Trigger::TrailingStop(Trail::Percent(10.0)), Some(OrderedFloat(100.0))));
+        assert_eq!(triggers.get_trigger_price(1), Some(OrderedFloat(110.0)));
+
+        assert!(prints(&mut triggers, &[90.0, 95.0]).is_empty());
+        assert_eq!(triggers.get_trigger_price(1), Some(OrderedFloat(99.0)));
+        assert_eq!(prints(&mut triggers, &[99.5]), vec![1]);
+        assert_eq!(triggers.size(), 0);
+    }
+
+    #[test]
+    fn fixed_stop_doesnt_trail() {
+        let mut triggers = TriggerBook::new();
+        triggers.insert(stop(1, Side::Sell), Trigger::Stop(OrderedFloat(95.0)), None);
+        triggers.insert(stop(2, Side::Sell), Trigger::Stop(OrderedFloat(97.0)), None);
+        assert!(prints(&mut triggers, &[110.0]).is_empty());
+        assert_eq!(triggers.get_trigger_price(1), Some(OrderedFloat(95.0)));
+        // in the order they entered
+        assert_eq!(prints(&mut triggers, &[94.0]), vec![1, 2]);
+        assert_eq!(triggers.get_trigger_price(3), None);
+    }
+}
@@ -0,0 +1,579 @@
+use super::*;
+use crate::itch::{decode_packet, FeedBook, ItchMessage, Locate};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+// how long a connection waits for a client message before sending what's queued for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// messages waiting for a connection, a client falling that far behind is disconnected.
+const MAX_QUEUED_MESSAGES: usize = 4096;
+// a client not taking a message within it is disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    L1,     // best bid and ask
+    L2,     // every displayed level
+    Trades,
+}
+
+// ----------------------------
+// ClientMessage / ServerMessage: JSON text frames tagged by "type", e.g.
+//   {"type":"subscribe","symbol":"DEMO","channel":"l2"}
+//   {"type":"auth","token":"secret"}
+//   {"type":"new_order","client_order_id":"1","symbol":"DEMO","side":"Buy","order_type":"GoodTillCancel","price":100.0,"quantity":5}
+// A depth subscription starts with a snapshot, then every change comes as an update
+// carrying the next `sequence` of the symbol, a level with quantity 0 is gone.
+// ----------------------------
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Auth { token: String },
+    Subscribe { symbol: Symbol, channel: Channel },
+    Unsubscribe { symbol: Symbol, channel: Channel },
+    // no price is a market order
+    NewOrder {
+        client_order_id: String,
+        symbol: Symbol,
+        side: Side,
+        order_type: OrderType,
+        price: Option<f32>,
+        quantity: Quantity,
+        #[serde(default)]
+        all_or_none: bool,
+    },
+    CancelOrder { client_order_id: String, original_client_order_id: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Authenticated { account_id: AccountId },
+    Subscribed { symbol: Symbol, channel: Channel },
+    Unsubscribed { symbol: Symbol, channel: Channel },
+    L1 { symbol: Symbol, sequence: u64, bid: Option<LevelInfo>, ask: Option<LevelInfo> },
+    L2Snapshot { symbol: Symbol, sequence: u64, bids: Vec<LevelInfo>, asks: Vec<LevelInfo> },
+    L2Update { symbol: Symbol, sequence: u64, bids: Vec<LevelInfo>, asks: Vec<LevelInfo> },
+    Trade { symbol: Symbol, trade_id: TradeId, price: Price, quantity: Quantity, aggressor_side: Side, timestamp: Timestamp },
+    OrderUpdate { update: OrderUpdate },
+    Error { message: String },
+}
+
+enum HubInput {
+    Packet(Vec<u8>),
+    Connected { client_id: u64, outgoing: Outgoing },
+    Subscribe { client_id: u64, symbol: Symbol, channel: Channel },
+    Unsubscribe { client_id: u64, symbol: Symbol, channel: Channel },
+    Disconnected { client_id: u64 },
+}
+
+// What's queued for one connection, bounded so a slow client can't make it grow without limit:
+// once it's full the client is dropped (by the hub, the engine and its connection) instead of
+// missing messages and showing wrong depth.
+#[derive(Clone)]
+struct Outgoing {
+    sender: SyncSender<ServerMessage>,
+    overflowed: Arc<AtomicBool>,
+}
+impl Outgoing {
+    fn channel(capacity: usize) -> (Self, Receiver<ServerMessage>) {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        (Self { sender, overflowed: Arc::new(AtomicBool::new(false)) }, receiver)
+    }
+
+    // false once the queue overflowed or the connection is gone, nothing is queued anymore.
+    fn send(&self, message: ServerMessage) -> bool {
+        if self.has_overflowed() {
+            return false;
+        }
+        match self.sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.store(true, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    fn has_overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+}
+
+struct HubClient {
+    outgoing: Outgoing,
+    subscriptions: BTreeSet<(Symbol, Channel)>,
+}
+
+struct SymbolDepth {
+    sequence: u64,
+    depth: OrderbookLevelInfos,
+}
+
+// Levels of `new` that differ from `old`, quantity 0 for levels that went away.
+fn changed_levels(old: &[LevelInfo], new: &[LevelInfo]) -> Vec<LevelInfo> {
+    let old: BTreeMap<Price, Quantity> = old.iter().map(|level| (level.price, level.quantity)).collect();
+    let new: BTreeMap<Price, Quantity> = new.iter().map(|level| (level.price, level.quantity)).collect();
+    let prices: BTreeSet<Price> = old.keys().chain(new.keys()).copied().collect();
+    prices
+        .into_iter()
+        .filter(|price| old.get(price) != new.get(price))
+        .map(|price| LevelInfo::new(price, new.get(&price).copied().unwrap_or(0)))
+        .collect()
+}
+
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    }
+}
+
+// ----------------------------
+// MarketDataHub rebuilds the books from the market data feed on a thread of its own
+// and fans depth and trades out to the subscribed connections.
+// ----------------------------
+struct MarketDataHub {
+    feed: FeedBook,
+    clients: BTreeMap<u64, HubClient>,
+    depths: BTreeMap<Symbol, SymbolDepth>,
+}
+impl MarketDataHub {
+    fn run(inputs: Receiver<HubInput>) {
+        let mut hub = Self { feed: FeedBook::new(), clients: BTreeMap::new(), depths: BTreeMap::new() };
+        while let Ok(input) = inputs.recv() {
+            match input {
+                HubInput::Packet(packet) => hub.on_packet(&packet),
+                HubInput::Connected { client_id, outgoing } => {
+                    hub.clients.insert(client_id, HubClient { outgoing, subscriptions: BTreeSet::new() });
+                }
+                HubInput::Subscribe { client_id, symbol, channel } => hub.subscribe(client_id, symbol, channel),
+                HubInput::Unsubscribe { client_id, symbol, channel } => {
+                    if let Some(client) = hub.clients.get_mut(&client_id) {
+                        client.subscriptions.remove(&(symbol.clone(), channel));
+                        client.outgoing.send(ServerMessage::Unsubscribed { symbol, channel });
+                    }
+                }
+                HubInput::Disconnected { client_id } => {
+                    hub.clients.remove(&client_id);
+                }
+            }
+        }
+    }
+
+    fn get_depth(&mut self, symbol: &str) -> &mut SymbolDepth {
+        self.depths.entry(symbol.to_string()).or_insert_with(|| SymbolDepth { sequence: 0, depth: OrderbookLevelInfos::new(Vec::new(), Vec::new()) })
+    }
+
+    fn level_one(symbol: &str, depth: &SymbolDepth) -> ServerMessage {
+        ServerMessage::L1 {
+            symbol: symbol.to_string(),
+            sequence: depth.sequence,
+            bid: depth.depth.get_bids().first().cloned(),
+            ask: depth.depth.get_asks().first().cloned(),
+        }
+    }
+
+    fn subscribe(&mut self, client_id: u64, symbol: Symbol, channel: Channel) {
+        let depth = self.get_depth(&symbol);
+        let snapshot = match channel {
+            Channel::L1 => Some(Self::level_one(&symbol, depth)),
+            Channel::L2 => Some(ServerMessage::L2Snapshot {
+                symbol: symbol.clone(),
+                sequence: depth.sequence,
+                bids: depth.depth.get_bids().clone(),
+                asks: depth.depth.get_asks().clone(),
+            }),
+            Channel::Trades => None,
+        };
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        client.subscriptions.insert((symbol.clone(), channel));
+        client.outgoing.send(ServerMessage::Subscribed { symbol, channel });
+        if let Some(snapshot) = snapshot {
+            client.outgoing.send(snapshot);
+        }
+    }
+
+    // Clients whose queue is full are dropped, their connection closes.
+    fn publish(&mut self, symbol: &str, channel: Channel, message: ServerMessage) {
+        let key = (symbol.to_string(), channel);
+        self.clients.retain(|_, client| !client.subscriptions.contains(&key) || client.outgoing.send(message.clone()));
+    }
+
+    fn on_packet(&mut self, packet: &[u8]) {
+        let Ok((_, messages)) = decode_packet(packet) else {
+            return;
+        };
+        let mut touched = BTreeSet::new();
+        for message in &messages {
+            let trade = match message {
+                ItchMessage::OrderExecuted { timestamp, locate, order_reference, executed_shares, match_number } => {
+                    self.feed.get_order(*locate, *order_reference).map(|(side, price, _)| (*locate, *match_number, price, *executed_shares, opposite(side), *timestamp))
+                }
+                ItchMessage::Trade { timestamp, locate, side, shares, price, match_number } => Some((*locate, *match_number, *price, *shares, opposite(*side), *timestamp)),
+                _ => None,
+            };
+            if let Some((locate, trade_id, price, quantity, aggressor_side, timestamp)) = trade
+                && let Some(symbol) = self.feed.get_symbol(locate).cloned()
+            {
+                let trade = ServerMessage::Trade { symbol: symbol.clone(), trade_id, price, quantity, aggressor_side, timestamp };
+                self.publish(&symbol, Channel::Trades, trade);
+            }
+            // the hub gets every packet through a channel, nothing can be missing
+            let _ = self.feed.apply(message);
+            touched.extend(self.get_locate(message));
+        }
+
+        for locate in touched {
+            let Some(symbol) = self.feed.get_symbol(locate).cloned() else {
+                continue;
+            };
+            let current = self.feed.get_orderlevelinfos(&symbol).unwrap_or_else(|| OrderbookLevelInfos::new(Vec::new(), Vec::new()));
+            let depth = self.get_depth(&symbol);
+            if depth.depth == current {
+                continue;
+            }
+            let best_changed = depth.depth.get_bids().first() != current.get_bids().first() || depth.depth.get_asks().first() != current.get_asks().first();
+            let update = ServerMessage::L2Update {
+                symbol: symbol.clone(),
+                sequence: depth.sequence + 1,
+                bids: changed_levels(depth.depth.get_bids(), current.get_bids()),
+                asks: changed_levels(depth.depth.get_asks(), current.get_asks()),
+            };
+            depth.sequence += 1;
+            depth.depth = current;
+            let level_one = best_changed.then(|| Self::level_one(&symbol, depth));
+            self.publish(&symbol, Channel::L2, update);
+            if let Some(level_one) = level_one {
+                self.publish(&symbol, Channel::L1, level_one);
+            }
+        }
+    }
+
+    fn get_locate(&self, message: &ItchMessage) -> Option<Locate> {
+        match message {
+            ItchMessage::SystemEvent { .. } => None,
+            ItchMessage::StockDirectory { locate, .. }
+            | ItchMessage::AddOrder { locate, .. }
+            | ItchMessage::OrderExecuted { locate, .. }
+            | ItchMessage::OrderCancel { locate, .. }
+            | ItchMessage::OrderDelete { locate, .. }
+            | ItchMessage::OrderReplace { locate, .. }
+            | ItchMessage::Trade { locate, .. } => Some(*locate),
+        }
+    }
+}
+
+// ----------------------------
+// WebSocketServer: JSON market data and order entry for web clients.
+// Anyone can subscribe to depth and trades, rebuilt from the market data feed
+// (a `ChannelSink` on the matching service's publisher), so streaming never touches the matching thread.
+// Each connection queues at most MAX_QUEUED_MESSAGES, a client that can't keep up is disconnected.
+// Clients that authenticate with one of `credentials` (token -> account) become a session of
+// the matching service and can enter and cancel orders for that account, their orders are
+// cancelled when the connection drops.
+// ----------------------------
+pub struct WebSocketServer {
+    local_address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+impl WebSocketServer {
+    pub fn bind(address: impl ToSocketAddrs, handle: EngineHandle, packets: Receiver<Vec<u8>>, credentials: BTreeMap<String, AccountId>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let (hub, inputs) = mpsc::channel();
+        thread::spawn(move || MarketDataHub::run(inputs));
+        let feed = hub.clone();
+        thread::spawn(move || {
+            while let Ok(packet) = packets.recv() {
+                if feed.send(HubInput::Packet(packet)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let credentials = Arc::new(credentials);
+        let accept_shutdown = shutdown.clone();
+        thread::spawn(move || {
+            while !accept_shutdown.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let (hub, handle, credentials, shutdown) = (hub.clone(), handle.clone(), credentials.clone(), accept_shutdown.clone());
+                        thread::spawn(move || WebSocketConnection::serve(stream, hub, handle, credentials, shutdown));
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(100)),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Self { local_address, shutdown })
+    }
+
+    pub fn get_local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+}
+impl Drop for WebSocketServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+struct WebSocketConnection {
+    client_id: u64,
+    account_id: Option<AccountId>,
+    outgoing: Outgoing,
+    hub: Sender<HubInput>,
+    handle: EngineHandle,
+    credentials: Arc<BTreeMap<String, AccountId>>,
+}
+impl WebSocketConnection {
+    fn serve(stream: TcpStream, hub: Sender<HubInput>, handle: EngineHandle, credentials: Arc<BTreeMap<String, AccountId>>, shutdown: Arc<AtomicBool>) {
+        if stream.set_nonblocking(false).and_then(|_| stream.set_nodelay(true)).is_err() {
+            return;
+        }
+        let Ok(mut socket) = tungstenite::accept(stream) else {
+            return;
+        };
+        // reads give up after a poll interval so queued messages go out
+        if socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).and_then(|_| socket.get_ref().set_write_timeout(Some(WRITE_TIMEOUT))).is_err() {
+            return;
+        }
+
+        // client ids come from the session id space, an authenticated client is that session
+        let (outgoing, messages) = Outgoing::channel(MAX_QUEUED_MESSAGES);
+        let mut connection = Self { client_id: handle.new_session_id(), account_id: None, outgoing, hub, handle, credentials };
+        let _ = connection.hub.send(HubInput::Connected { client_id: connection.client_id, outgoing: connection.outgoing.clone() });
+        let _ = connection.run(&mut socket, &messages, &shutdown);
+
+        let _ = connection.hub.send(HubInput::Disconnected { client_id: connection.client_id });
+        if connection.account_id.is_some() {
+            connection.handle.send(EngineRequest::Disconnect { session_id: connection.client_id });
+        }
+    }
+
+    fn run(&mut self, socket: &mut WebSocket<TcpStream>, messages: &Receiver<ServerMessage>, shutdown: &AtomicBool) -> tungstenite::Result<()> {
+        loop {
+            if shutdown.load(Ordering::Relaxed) {
+                return socket.close(None);
+            }
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    let reply = match serde_json::from_str::<ClientMessage>(text.as_str()) {
+                        Ok(message) => self.on_message(message),
+                        Err(error) => Some(format!("malformed message: {}", error)),
+                    };
+                    if let Some(message) = reply {
+                        self.outgoing.send(ServerMessage::Error { message });
+                    }
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                // pings are answered by tungstenite on the next write or flush
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(error)) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(error) => return Err(error),
+            }
+            while let Ok(message) = messages.try_recv() {
+                let text = serde_json::to_string(&message).unwrap_or_default();
+                socket.write(Message::text(text))?;
+            }
+            // everything before the overflow went out, what came after is lost
+            if self.outgoing.has_overflowed() {
+                let error = ServerMessage::Error { message: "too many messages queued, disconnected".to_string() };
+                socket.write(Message::text(serde_json::to_string(&error).unwrap_or_default()))?;
+                return socket.close(None);
+            }
+            socket.flush()?;
+        }
+    }
+
+    // Returns an error message for the client.
+    fn on_message(&mut self, message: ClientMessage) -> Option<String> {
+        match message {
+            ClientMessage::Auth { token } => {
+                if self.account_id.is_some() {
+                    return Some("already authenticated".to_string());
+                }
+                let Some(account_id) = self.credentials.get(&token).copied() else {
+                    return Some("invalid token".to_string());
+                };
+                self.account_id = Some(account_id);
+                self.outgoing.send(ServerMessage::Authenticated { account_id });
+                let outgoing = self.outgoing.clone();
+                self.handle.send(EngineRequest::Connect {
+                    session_id: self.client_id,
+                    sink: Box::new(move |update| outgoing.send(ServerMessage::OrderUpdate { update })),
+                });
+                None
+            }
+            ClientMessage::Subscribe { symbol, channel } => {
+                let _ = self.hub.send(HubInput::Subscribe { client_id: self.client_id, symbol, channel });
+                None
+            }
+            ClientMessage::Unsubscribe { symbol, channel } => {
+                let _ = self.hub.send(HubInput::Unsubscribe { client_id: self.client_id, symbol, channel });
+                None
+            }
+            ClientMessage::NewOrder { client_order_id, symbol, side, order_type, price, quantity, all_or_none } => {
+                let Some(account_id) = self.account_id else {
+                    return Some("authenticate before entering orders".to_string());
+                };
+                let order = NewOrder {
+                    client_order_id,
+                    symbol,
+                    side,
+                    order_type,
+                    price: Price::from(price.unwrap_or(f32::NAN)),
+                    quantity,
+                    all_or_none,
+                    account_id: Some(account_id),
+                };
+                self.handle.send(EngineRequest::NewOrder { session_id: self.client_id, order });
+                None
+            }
+            ClientMessage::CancelOrder { client_order_id, original_client_order_id } => {
+                if self.account_id.is_none() {
+                    return Some("authenticate before cancelling orders".to_string());
+                }
+                self.handle.send(EngineRequest::CancelOrder { session_id: self.client_id, client_order_id, original_client_order_id });
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const SYMBOL: &str = "DEMO";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn connect(server: &WebSocketServer) -> WebSocket<TcpStream> {
+        let stream = TcpStream::connect(server.get_local_address()).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let (socket, _) = tungstenite::client(format!("ws://{}", server.get_local_address()), stream).unwrap();
+        socket
+    }
+
+    fn send(socket: &mut WebSocket<TcpStream>, message: &ClientMessage) {
+        socket.send(Message::text(serde_json::to_string(message).unwrap())).unwrap();
+    }
+
+    fn receive(socket: &mut WebSocket<TcpStream>) -> ServerMessage {
+        loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                return serde_json::from_str(text.as_str()).unwrap();
+            }
+        }
+    }
+
+    fn new_order(client_order_id: &str, side: Side, quantity: Quantity, price: f32) -> ClientMessage {
+        ClientMessage::NewOrder {
+            client_order_id: client_order_id.to_string(),
+            symbol: SYMBOL.to_string(),
+            side,
+            order_type: OrderType::GoodTillCancel,
+            price: Some(price),
+            quantity,
+            all_or_none: false,
+        }
+    }
+
+    fn apply_levels(levels: &mut BTreeMap<Price, Quantity>, changes: Vec<LevelInfo>) {
+        for level in changes {
+            if level.quantity == 0 {
+                levels.remove(&level.price);
+            } else {
+                levels.insert(level.price, level.quantity);
+            }
+        }
+    }
+
+    #[test]
+    fn depth_is_a_snapshot_then_updates_in_sequence() {
+        let journal_path = std::env::temp_dir().join(format!("orderbook-rs-websocket-{}.journal", std::process::id()));
+        let _ = std::fs::remove_file(&journal_path);
+        let (sink, packets) = ChannelSink::channel();
+        let (handle, requests) = EngineHandle::channel();
+        let service_journal_path = journal_path.clone();
+        let matching = thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.add_instrument(SYMBOL, OrderBook::new());
+            let engine = JournaledEngine::open(&service_journal_path, engine).unwrap();
+            MatchingService::new(engine, requests).with_market_data(MarketDataPublisher::new().with_sink(sink)).run();
+        });
+        let server = WebSocketServer::bind("127.0.0.1:0", handle.clone(), packets, BTreeMap::from([("token".to_string(), 1)])).unwrap();
+
+        let mut trader = connect(&server);
+        send(&mut trader, &ClientMessage::Auth { token: "token".to_string() });
+        assert_eq!(receive(&mut trader), ServerMessage::Authenticated { account_id: 1 });
+        send(&mut trader, &new_order("1", Side::Sell, 10, 101.0));
+        assert!(matches!(receive(&mut trader), ServerMessage::OrderUpdate { update: OrderUpdate::Accepted(_) }));
+
+        let mut viewer = connect(&server);
+        send(&mut viewer, &ClientMessage::Subscribe { symbol: SYMBOL.to_string(), channel: Channel::L2 });
+        assert_eq!(receive(&mut viewer), ServerMessage::Subscribed { symbol: SYMBOL.to_string(), channel: Channel::L2 });
+        let (mut sequence, mut bids, mut asks) = match receive(&mut viewer) {
+            ServerMessage::L2Snapshot { sequence, bids, asks, .. } => {
+                (sequence, bids.into_iter().map(|level| (level.price, level.quantity)).collect(), asks.into_iter().map(|level| (level.price, level.quantity)).collect())
+            }
+            message => panic!("expected a snapshot, got {:?}", message),
+        };
+
+        send(&mut trader, &new_order("2", Side::Sell, 5, 102.0));
+        send(&mut trader, &new_order("3", Side::Buy, 4, 99.0));
+        send(&mut trader, &new_order("4", Side::Buy, 3, 101.0));
+        send(&mut trader, &ClientMessage::CancelOrder { client_order_id: "5".to_string(), original_client_order_id: "2".to_string() });
+
+        let expected_bids = BTreeMap::from([(OrderedFloat(99.0), 4)]);
+        let expected_asks = BTreeMap::from([(OrderedFloat(101.0), 7)]);
+        let deadline = Instant::now() + TIMEOUT;
+        while bids != expected_bids || asks != expected_asks {
+            assert!(Instant::now() < deadline, "depth never reached bids {:?} asks {:?}", bids, asks);
+            match receive(&mut viewer) {
+                ServerMessage::L2Update { sequence: update_sequence, bids: bid_changes, asks: ask_changes, .. } => {
+                    assert_eq!(update_sequence, sequence + 1);
+                    sequence = update_sequence;
+                    apply_levels(&mut bids, bid_changes);
+                    apply_levels(&mut asks, ask_changes);
+                }
+                message => panic!("expected an update, got {:?}", message),
+            }
+        }
+        assert!(sequence > 1);
+
+        handle.send(EngineRequest::Shutdown);
+        matching.join().unwrap();
+        let _ = std::fs::remove_file(&journal_path);
+    }
+
+    #[test]
+    fn full_queue_drops_the_client() {
+        let (outgoing, messages) = Outgoing::channel(2);
+        let message = ServerMessage::Error { message: "test".to_string() };
+        assert!(outgoing.send(message.clone()));
+        assert!(outgoing.send(message.clone()));
+        assert!(!outgoing.send(message.clone()));
+        assert!(outgoing.has_overflowed());
+
+        // making room doesn't take the client back, it already missed a message
+        assert!(messages.try_recv().is_ok());
+        assert!(!outgoing.send(message));
+    }
+}